@@ -1,5 +1,6 @@
 import tkinter as tk
 from tkinter import ttk
+import json
 import socket
 import threading
 import queue
@@ -118,7 +119,7 @@ class SentimentGUI:
         self.status_label.config(text="🟢 Listening for UDP…", fg='#00ff88')
 
     def listen_for_sentiment(self, ticker, port):
-        """Receive float payload over UDP, push into queue."""
+        """Receive JSON SentimentPacket payloads over UDP, push into queue."""
         try:
             sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
             sock.bind(('127.0.0.1', port))
@@ -128,7 +129,7 @@ class SentimentGUI:
             while self.running:
                 try:
                     data, _ = sock.recvfrom(1024)
-                    val = float(data.decode().strip())
+                    val = float(json.loads(data)['value'])
                     # timestamp it and queue it
                     self.update_queue.put(('sentiment', ticker, (datetime.now(), val)))
                 except socket.timeout:
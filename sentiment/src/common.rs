@@ -0,0 +1,10 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Multicast group the service broadcasts to and the client joins.
+pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 123);
+
+/// Link-local IPv6 counterpart of `MULTICAST_ADDR` (ff02::123).
+pub const MULTICAST_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x123);
+
+/// Default multicast TTL; 1 keeps packets on the local network segment.
+pub const MULTICAST_TTL: u32 = 1;
@@ -0,0 +1,5 @@
+//! Definitions shared between the sentiment service and the GUI client, so the
+//! two binaries can't drift apart.
+
+pub mod common;
+pub mod wire;
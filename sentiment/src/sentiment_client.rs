@@ -1,77 +1,323 @@
 use std::{
-    collections::HashMap,
-    net::UdpSocket,
-    sync::mpsc,
+    collections::{HashMap, VecDeque},
+    error::Error,
+    io::{BufRead, BufReader, ErrorKind},
+    net::{Ipv4Addr, TcpStream, UdpSocket},
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
     thread,
     time::{Duration, Instant},
 };
 
 use eframe::{egui, run_native, App, CreationContext, NativeOptions};
+use sentiment_microservice::{common::MULTICAST_ADDR, wire};
+use serde::Deserialize;
+
+/// Environment variable naming the stock list, used when no path is given
+/// on the command line.
+const STOCKS_ENV_VAR: &str = "SENTIMENT_STOCKS";
+
+/// Environment variable with extra comma-separated ports to listen on.
+/// Tickers seen there are added to the plot as they first appear.
+const DISCOVERY_PORTS_ENV_VAR: &str = "SENTIMENT_DISCOVERY_PORTS";
+
+/// Most tickers added on the fly, on top of the configured ones.
+const MAX_DISCOVERED_TICKERS: usize = 64;
+
+/// Used when no stock list is configured or it cannot be read.
+const DEFAULT_STOCKS: [(&str, u16); 3] = [("AAPL", 3001), ("GOOGL", 4001), ("PLTR", 5001)];
+
+/// Points kept per ticker until the user changes it.
+const DEFAULT_HISTORY_CAPACITY: usize = 1_000;
+
+/// Range offered by the history slider.
+const HISTORY_CAPACITY_RANGE: std::ops::RangeInclusive<usize> = 500..=50_000;
+
+/// Samples the listeners can queue before the UI drains them. Listeners
+/// never block on a full queue; they drop the newest sample instead, so
+/// memory stays flat however long the UI stalls.
+const SAMPLE_QUEUE_CAPACITY: usize = 10_000;
+
+/// Default EMA smoothing factor, roughly a 20-sample average.
+const DEFAULT_EMA_ALPHA: f64 = 0.1;
+
+/// Samples averaged for the auto baseline, the current one included.
+const ROLLING_BASELINE_WINDOW: usize = 100;
+
+/// Most samples ingested in one frame, so a burst can't stall drawing. The
+/// rest wait for the next frame, which is requested straight away.
+const MAX_SAMPLES_PER_FRAME: usize = 2_000;
+
+/// First and longest wait between attempts to reach a `--server`.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// A `--server` stream this quiet is treated as dropped. The service writes
+/// every broadcast interval, so silence means the connection is gone.
+const STREAM_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Line colors, picked per ticker by `ticker_color`.
+const TICKER_PALETTE: [egui::Color32; 10] = [
+    egui::Color32::from_rgb(31, 119, 180),
+    egui::Color32::from_rgb(255, 127, 14),
+    egui::Color32::from_rgb(44, 160, 44),
+    egui::Color32::from_rgb(214, 39, 40),
+    egui::Color32::from_rgb(148, 103, 189),
+    egui::Color32::from_rgb(140, 86, 75),
+    egui::Color32::from_rgb(227, 119, 194),
+    egui::Color32::from_rgb(127, 127, 127),
+    egui::Color32::from_rgb(188, 189, 34),
+    egui::Color32::from_rgb(23, 190, 207),
+];
+
+/// Default and adjustable range of the alerts panel's |sentiment| threshold.
+const DEFAULT_ALERT_THRESHOLD: f64 = 0.9;
+const ALERT_THRESHOLD_RANGE: std::ops::RangeInclusive<f64> = 0.1..=1.0;
+
+/// Keys under which the UI state is kept in eframe's storage between runs.
+const VISIBILITY_KEY: &str = "visible";
+const WINDOW_KEY: &str = "window";
+
+/// Command-line options for the `sentiment_client` binary.
+#[derive(Debug, clap::Parser)]
+#[command(about = "Plots live sentiment from a sentiment service")]
+struct Cli {
+    /// Stock list to plot; defaults to $SENTIMENT_STOCKS, then built-in tickers
+    stocks: Option<String>,
+    /// Read the service's TCP stream at host:port instead of listening for UDP
+    #[arg(long)]
+    server: Option<String>,
+    /// Local address of the interface to join the multicast group on;
+    /// defaults to one the OS picks
+    #[arg(long, default_value_t = Ipv4Addr::UNSPECIFIED)]
+    interface: Ipv4Addr,
+    /// Also listen for the service's batched datagrams on this port; their
+    /// stock ids are named from the stock list
+    #[arg(long)]
+    batch_port: Option<u16>,
+}
+
+/// How much of the time axis the plot shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TimeWindow {
+    Last10s,
+    Last1m,
+    Last5m,
+    /// Everything still in the history.
+    #[default]
+    All,
+}
+
+impl TimeWindow {
+    const ALL: [TimeWindow; 4] = [
+        TimeWindow::Last10s,
+        TimeWindow::Last1m,
+        TimeWindow::Last5m,
+        TimeWindow::All,
+    ];
+
+    fn seconds(self) -> Option<f64> {
+        match self {
+            TimeWindow::Last10s => Some(10.0),
+            TimeWindow::Last1m => Some(60.0),
+            TimeWindow::Last5m => Some(300.0),
+            TimeWindow::All => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            TimeWindow::Last10s => "10s",
+            TimeWindow::Last1m => "1m",
+            TimeWindow::Last5m => "5m",
+            TimeWindow::All => "all",
+        }
+    }
+
+    fn from_label(label: &str) -> Option<TimeWindow> {
+        TimeWindow::ALL.into_iter().find(|w| w.label() == label)
+    }
+}
+
+/// A ticker listed in the alerts panel.
+#[derive(Debug, PartialEq)]
+struct Alert {
+    ticker: String,
+    /// Latest sentiment.
+    value: f64,
+    /// Plot time of the first point in the current run beyond the threshold.
+    since: f64,
+}
+
+/// State of the connection to a `--server`, shown in the side panel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConnectionStatus {
+    Connecting,
+    Connected,
+    /// The last attempt failed or the stream dropped; the next attempt is
+    /// due after this long.
+    Reconnecting(Duration),
+}
 
 struct MyApp {
-    history: HashMap<String, Vec<[f64; 2]>>,
+    history: HashMap<String, VecDeque<[f64; 2]>>,
+    /// Most recent points kept per ticker; older ones are dropped.
+    history_capacity: usize,
     visible: HashMap<String, bool>,
-    rx: mpsc::Receiver<(String, f64)>,
+    /// How many tickers were added because a packet named them, rather than
+    /// from the stock list.
+    discovered: usize,
+    /// Packets lost in transit per ticker, judged by their sequence numbers.
+    drops: HashMap<String, SeqTracker>,
+    /// Packets with the time they arrived, so ingesting late doesn't shift
+    /// them on the time axis.
+    rx: mpsc::Receiver<(wire::SentimentPacket, Instant)>,
     start: Instant,
+    /// While set, the plot neither ingests samples nor repaints on its own.
+    /// Listeners keep receiving into the bounded sample queue, which holds
+    /// the first `SAMPLE_QUEUE_CAPACITY` samples and drops the rest. On
+    /// unfreezing, the queued samples are plotted at their arrival times,
+    /// so the lines continue where they were rather than jumping.
+    frozen: bool,
+    /// Whether to overlay each visible ticker's EMA as a dashed line.
+    show_ema: bool,
+    /// EMA smoothing factor in (0, 1]; smaller is smoother.
+    ema_alpha: f64,
+    /// Subtracted from every plotted value, unless `auto_baseline` is set.
+    baseline: f64,
+    /// Plot each ticker relative to its own rolling mean instead.
+    auto_baseline: bool,
+    /// Connection to the `--server`, if reading from one rather than UDP.
+    connection: Option<Arc<Mutex<ConnectionStatus>>>,
+    /// Visible stretch of the time axis. Anything but `All` follows the
+    /// latest time unless frozen.
+    window: TimeWindow,
+    /// Tickers whose latest |sentiment| is at least this are listed in the
+    /// alerts panel.
+    alert_threshold: f64,
 }
 
 impl MyApp {
     // Note: eframe will call this at startup.
-    pub fn new(_cc: &CreationContext<'_>) -> Self {
-        // Define your tickers and UDP ports here
-        let stocks = vec![
-            ("AAPL".to_string(), 3001),
-            ("GOOGL".to_string(), 4001),
-            ("PLTR".to_string(), 5001),
-        ];
-
-        let (tx, rx) = mpsc::channel();
-
-        // Spawn one blocking‐UDP listener per ticker
-        for (ticker, port) in stocks.clone() {
-            let tx = tx.clone();
-            thread::spawn(move || {
-                let sock = UdpSocket::bind(("127.0.0.1", port)).expect("could not bind UDP socket");
-                sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
-                let mut buf = [0u8; 1024];
-                while let Ok(n) = sock.recv(&mut buf) {
-                    if let Ok(s) = std::str::from_utf8(&buf[..n]) {
-                        if let Ok(val) = s.trim().parse::<f64>() {
-                            let _ = tx.send((ticker.clone(), val));
-                        }
-                    }
+    pub fn new(cc: &CreationContext<'_>, cli: Cli) -> Self {
+        let names = match cli.batch_port {
+            Some(_) => configured_names(cli.stocks.clone()),
+            None => HashMap::new(),
+        };
+        let stocks = configured_stocks(cli.stocks);
+
+        let (tx, rx) = mpsc::sync_channel(SAMPLE_QUEUE_CAPACITY);
+
+        let connection = match cli.server {
+            // One reader for the service's TCP stream, which carries every
+            // ticker
+            Some(server) => {
+                let status = Arc::new(Mutex::new(ConnectionStatus::Connecting));
+                let thread_status = Arc::clone(&status);
+                thread::spawn(move || {
+                    let backoff = Backoff::new(RECONNECT_INITIAL_DELAY, RECONNECT_MAX_DELAY);
+                    stream_from_server(&server, &tx, &thread_status, backoff);
+                });
+                Some(status)
+            }
+            // Spawn one blocking‐UDP listener per port; packets carry their
+            // own ticker, so samples are routed by name rather than by port.
+            // The wire format is detected per packet, so any service
+            // `WireFormat` works, and batches are unpacked wherever they
+            // arrive. Unknown tickers on any of these ports are discovered
+            // on the fly.
+            None => {
+                let mut ports: Vec<u16> = stocks.iter().map(|&(_, port)| port).collect();
+                ports.extend(discovery_ports());
+                ports.extend(cli.batch_port);
+                ports.sort_unstable();
+                ports.dedup();
+                let interface = cli.interface;
+                let names = Arc::new(names);
+                for port in ports {
+                    let tx = tx.clone();
+                    let names = Arc::clone(&names);
+                    thread::spawn(move || {
+                        let sock =
+                            UdpSocket::bind(("0.0.0.0", port)).expect("could not bind UDP socket");
+                        sock.join_multicast_v4(&MULTICAST_ADDR, &interface)
+                            .expect("could not join multicast group");
+                        sock.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+                        listen(&sock, &names, &tx);
+                    });
                 }
-            });
-        }
+                None
+            }
+        };
 
         // Prepare history & visibility maps
         let history = stocks
             .iter()
-            .map(|(t, _)| (t.clone(), Vec::new()))
+            .map(|(t, _)| (t.clone(), VecDeque::new()))
             .collect();
-        let visible = stocks.iter().map(|(t, _)| (t.clone(), true)).collect();
+        let mut visible = stocks.iter().map(|(t, _)| (t.clone(), true)).collect();
+        let mut window = TimeWindow::default();
+
+        // Restore the last run's choices
+        if let Some(storage) = cc.storage {
+            if let Some(saved) = storage.get_string(VISIBILITY_KEY) {
+                restore_visibility(&saved, &mut visible);
+            }
+            if let Some(saved) = storage.get_string(WINDOW_KEY) {
+                window = TimeWindow::from_label(&saved).unwrap_or_default();
+            }
+        }
 
         Self {
             history,
+            history_capacity: DEFAULT_HISTORY_CAPACITY,
             visible,
+            discovered: 0,
+            drops: HashMap::new(),
             rx,
             start: Instant::now(),
+            frozen: false,
+            show_ema: false,
+            ema_alpha: DEFAULT_EMA_ALPHA,
+            baseline: 0.0,
+            auto_baseline: false,
+            connection,
+            window,
+            alert_threshold: DEFAULT_ALERT_THRESHOLD,
         }
     }
 }
 
 impl App for MyApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(VISIBILITY_KEY, save_visibility(&self.visible));
+        storage.set_string(WINDOW_KEY, self.window.label().to_string());
+    }
+
     // We no longer implement `fn name`; window title is set in `run_native`.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // 1️⃣ Ingest any new UDP samples
-        while let Ok((ticker, val)) = self.rx.try_recv() {
-            let t = self.start.elapsed().as_secs_f64();
-            if let Some(hist) = self.history.get_mut(&ticker) {
-                hist.push([t, val]);
-                // Trim to last 1,000 points
-                if hist.len() > 1_000 {
-                    hist.drain(0..hist.len() - 1_000);
+        // 1️⃣ Ingest any new UDP samples (left queued while frozen)
+        let mut backlog = false;
+        if !self.frozen {
+            let samples: Vec<_> = self.rx.try_iter().take(MAX_SAMPLES_PER_FRAME).collect();
+            backlog = samples.len() == MAX_SAMPLES_PER_FRAME;
+            for (packet, arrived) in samples {
+                let t = arrived.saturating_duration_since(self.start).as_secs_f64();
+                if !discover_ticker(
+                    &mut self.history,
+                    &mut self.visible,
+                    &mut self.discovered,
+                    &packet.ticker,
+                ) {
+                    continue;
+                }
+                if let Some(hist) = self.history.get_mut(&packet.ticker) {
+                    push_bounded(hist, [t, packet.value], self.history_capacity);
                 }
+                self.drops
+                    .entry(packet.ticker)
+                    .or_default()
+                    .observe(packet.seq);
             }
         }
 
@@ -79,41 +325,1037 @@ impl App for MyApp {
         egui::SidePanel::left("side_panel")
             .resizable(true)
             .show(ctx, |ui| {
+                if let Some(connection) = &self.connection {
+                    let status = *connection.lock().unwrap();
+                    let (color, text) = match status {
+                        ConnectionStatus::Connecting => {
+                            (egui::Color32::YELLOW, "Connecting…".to_string())
+                        }
+                        ConnectionStatus::Connected => {
+                            (egui::Color32::GREEN, "Connected".to_string())
+                        }
+                        ConnectionStatus::Reconnecting(delay) => (
+                            egui::Color32::RED,
+                            format!("Disconnected, retrying in {:.1}s", delay.as_secs_f64()),
+                        ),
+                    };
+                    ui.colored_label(color, text);
+                    ui.separator();
+                }
                 ui.heading("Tickers");
                 ui.separator();
+                // Summarise what the plot shows: the selected window, unless
+                // frozen, when the plot can be panned anywhere.
+                let bounds = self
+                    .window
+                    .seconds()
+                    .filter(|_| !self.frozen)
+                    .map(|secs| window_bounds(self.start.elapsed().as_secs_f64(), secs));
                 for (ticker, vis) in &mut self.visible {
-                    ui.checkbox(vis, ticker);
+                    ui.horizontal(|ui| {
+                        ui.checkbox(vis, ticker);
+                        let Some(hist) = self.history.get(ticker) else {
+                            return;
+                        };
+                        let (last, min, max) = summary(points_in(hist, bounds));
+                        if last.is_nan() {
+                            return;
+                        }
+                        let color = if last > 0.0 {
+                            egui::Color32::GREEN
+                        } else if last < 0.0 {
+                            egui::Color32::RED
+                        } else {
+                            ui.visuals().text_color()
+                        };
+                        ui.colored_label(color, format!("{:.3}", last));
+                        ui.label(format!("min {:.3} max {:.3}", min, max));
+                        if let Some(drops) = self.drops.get(ticker).filter(|d| d.dropped > 0) {
+                            ui.label(format!("dropped {}", drops.dropped));
+                        }
+                    });
+                }
+
+                ui.separator();
+                let capacity =
+                    egui::Slider::new(&mut self.history_capacity, HISTORY_CAPACITY_RANGE)
+                        .logarithmic(true)
+                        .text("points");
+                if ui.add(capacity).changed() {
+                    for hist in self.history.values_mut() {
+                        trim_history(hist, self.history_capacity);
+                    }
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Last");
+                    for window in TimeWindow::ALL {
+                        ui.selectable_value(&mut self.window, window, window.label());
+                    }
+                });
+                ui.toggle_value(&mut self.frozen, "Freeze");
+                ui.checkbox(&mut self.show_ema, "EMA overlay");
+                ui.add_enabled(
+                    self.show_ema,
+                    egui::Slider::new(&mut self.ema_alpha, 0.01..=1.0)
+                        .logarithmic(true)
+                        .text("alpha"),
+                );
+                ui.checkbox(&mut self.auto_baseline, "Rolling-mean baseline");
+                ui.add_enabled(
+                    !self.auto_baseline,
+                    egui::Slider::new(&mut self.baseline, -1.0..=1.0).text("baseline"),
+                );
+                if ui.button("Export CSV").clicked() {
+                    let path = rfd::FileDialog::new()
+                        .add_filter("CSV", &["csv"])
+                        .set_file_name("sentiment_history.csv")
+                        .save_file();
+                    if let Some(path) = path {
+                        if let Err(e) = std::fs::write(&path, history_to_csv(&self.history)) {
+                            eprintln!("Could not export to {}: {}", path.display(), e);
+                        }
+                    }
                 }
             });
 
-        // 3️⃣ Central panel: live sentiment plot
+        // 3️⃣ Bottom panel: tickers at extreme sentiment
+        egui::TopBottomPanel::bottom("alerts_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Alerts");
+                ui.add(
+                    egui::Slider::new(&mut self.alert_threshold, ALERT_THRESHOLD_RANGE)
+                        .text("|sentiment| ≥"),
+                );
+            });
+            let alerts = extreme_tickers(&self.history, self.alert_threshold);
+            if alerts.is_empty() {
+                ui.label("No ticker beyond the threshold.");
+            }
+            for alert in alerts {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!(
+                        "{} at {:.3} since {:.1}s",
+                        alert.ticker, alert.value, alert.since
+                    ),
+                );
+            }
+        });
+
+        // 4️⃣ Central panel: live sentiment plot
         egui::CentralPanel::default().show(ctx, |ui| {
             let plot = egui::plot::Plot::new("sentiment_plot")
                 .legend(egui::plot::Legend::default())
                 .view_aspect(2.0);
 
+            let window = self.window.seconds().filter(|_| !self.frozen);
+            let baseline = if self.auto_baseline {
+                Baseline::RollingMean(ROLLING_BASELINE_WINDOW)
+            } else {
+                Baseline::Constant(self.baseline)
+            };
+            let now = self.start.elapsed().as_secs_f64();
             plot.show(ui, |plot_ui| {
+                if let Some(window) = window {
+                    let (min_x, max_x) = window_bounds(now, window);
+                    let bounds = plot_ui.plot_bounds();
+                    plot_ui.set_plot_bounds(egui::plot::PlotBounds::from_min_max(
+                        [min_x, bounds.min()[1]],
+                        [max_x, bounds.max()[1]],
+                    ));
+                }
                 for (ticker, hist) in &self.history {
                     if *self.visible.get(ticker).unwrap_or(&false) && !hist.is_empty() {
-                        let line =
-                            egui::plot::Line::new(egui::plot::PlotPoints::from(hist.clone()))
-                                .name(ticker.clone());
+                        let color = ticker_color(ticker);
+                        let points: Vec<[f64; 2]> = hist.iter().copied().collect();
+                        let points = rebase(&points, baseline);
+                        if self.show_ema {
+                            let smoothed = ema(&points, self.ema_alpha);
+                            let line =
+                                egui::plot::Line::new(egui::plot::PlotPoints::from(smoothed))
+                                    .style(egui::plot::LineStyle::dashed_loose())
+                                    .color(color)
+                                    .name(format!("{} EMA", ticker));
+                            plot_ui.line(line);
+                        }
+                        let line = egui::plot::Line::new(egui::plot::PlotPoints::from(points))
+                            .color(color)
+                            .name(ticker.clone());
                         plot_ui.line(line);
                     }
                 }
             });
         });
 
-        // 4️⃣ Keep the UI painting for real‐time updates
-        ctx.request_repaint_after(Duration::from_millis(100));
+        // 5️⃣ Keep the UI painting for real‐time updates
+        if backlog {
+            ctx.request_repaint();
+        } else if !self.frozen {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+    }
+}
+
+/// The columns of the service's stock list that the client needs. Other
+/// columns are ignored.
+#[derive(Debug, Deserialize)]
+struct StockPort {
+    ticker: String,
+    /// Only needed to name the samples of batched datagrams.
+    #[serde(default)]
+    id: Option<u64>,
+    sentiment_port: u16,
+}
+
+/// Reads the service's stock list: a CSV with `ticker` and
+/// `sentiment_port` columns, or a `.json` array of stocks.
+fn load_stock_rows(path: &Path) -> Result<Vec<StockPort>, Box<dyn Error>> {
+    Ok(if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))?
+    } else {
+        csv::Reader::from_path(path)?
+            .deserialize()
+            .collect::<Result<_, _>>()?
+    })
+}
+
+/// Reads ticker to port pairs from the service's stock list.
+fn load_stock_ports(path: &Path) -> Result<HashMap<String, u16>, Box<dyn Error>> {
+    Ok(load_stock_rows(path)?
+        .into_iter()
+        .map(|row| (row.ticker, row.sentiment_port))
+        .collect())
+}
+
+/// Reads stock id to ticker pairs from the service's stock list, skipping
+/// rows without an id.
+fn load_stock_names(path: &Path) -> Result<HashMap<u64, String>, Box<dyn Error>> {
+    Ok(load_stock_rows(path)?
+        .into_iter()
+        .filter_map(|row| Some((row.id?, row.ticker)))
+        .collect())
+}
+
+/// `path`, or else the one named by `STOCKS_ENV_VAR`.
+fn stock_list_path(path: Option<String>) -> Option<String> {
+    path.or_else(|| std::env::var(STOCKS_ENV_VAR).ok())
+}
+
+/// Stock id to ticker pairs from the stock list at `path` or
+/// `STOCKS_ENV_VAR`; empty when there is none or it cannot be read.
+fn configured_names(path: Option<String>) -> HashMap<u64, String> {
+    stock_list_path(path)
+        .and_then(|path| load_stock_names(Path::new(&path)).ok())
+        .unwrap_or_default()
+}
+
+/// The stock list at `path` or `STOCKS_ENV_VAR`, falling back to
+/// `DEFAULT_STOCKS`, sorted by ticker.
+fn configured_stocks(path: Option<String>) -> Vec<(String, u16)> {
+    let path = stock_list_path(path);
+    let loaded = path.and_then(|path| match load_stock_ports(Path::new(&path)) {
+        Ok(stocks) => Some(stocks),
+        Err(e) => {
+            eprintln!("Could not load stocks from {}: {}; using defaults", path, e);
+            None
+        }
+    });
+
+    let mut stocks: Vec<(String, u16)> = match loaded {
+        Some(stocks) => stocks.into_iter().collect(),
+        None => DEFAULT_STOCKS
+            .iter()
+            .map(|&(ticker, port)| (ticker.to_string(), port))
+            .collect(),
+    };
+    stocks.sort();
+    stocks
+}
+
+/// Serialises every ticker's history as `time_s,ticker,value` rows, tickers
+/// in alphabetical order. Tickers without data are skipped.
+fn history_to_csv(history: &HashMap<String, VecDeque<[f64; 2]>>) -> String {
+    let mut tickers: Vec<&String> = history.keys().collect();
+    tickers.sort();
+
+    let mut out = String::from("time_s,ticker,value\n");
+    for ticker in tickers {
+        for [t, value] in &history[ticker] {
+            out.push_str(&format!("{},{},{}\n", t, ticker, value));
+        }
+    }
+    out
+}
+
+/// Exponential moving average of `points`' values, seeded with the first
+/// value: `ema[i] = alpha * value[i] + (1 - alpha) * ema[i - 1]`. Times are
+/// kept as they are.
+fn ema(points: &[[f64; 2]], alpha: f64) -> Vec<[f64; 2]> {
+    let mut average = None;
+    points
+        .iter()
+        .map(|&[t, value]| {
+            let next = match average {
+                Some(previous) => alpha * value + (1.0 - alpha) * previous,
+                None => value,
+            };
+            average = Some(next);
+            [t, next]
+        })
+        .collect()
+}
+
+/// What `rebase` subtracts from each plotted value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Baseline {
+    Constant(f64),
+    /// The mean of the value and up to this many minus one before it.
+    RollingMean(usize),
+}
+
+/// `points` with `baseline` subtracted from each value. Times are kept as
+/// they are.
+fn rebase(points: &[[f64; 2]], baseline: Baseline) -> Vec<[f64; 2]> {
+    match baseline {
+        Baseline::Constant(offset) => points
+            .iter()
+            .map(|&[t, value]| [t, value - offset])
+            .collect(),
+        Baseline::RollingMean(window) => {
+            let window = window.max(1);
+            let mut sum = 0.0;
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, &[t, value])| {
+                    sum += value;
+                    if i >= window {
+                        sum -= points[i - window][1];
+                    }
+                    [t, value - sum / (i + 1).min(window) as f64]
+                })
+                .collect()
+        }
+    }
+}
+
+/// `(last, min, max)` of the values in `points`; all NaN if there are
+/// none.
+fn summary(points: impl IntoIterator<Item = [f64; 2]>) -> (f64, f64, f64) {
+    points.into_iter().fold(
+        (f64::NAN, f64::NAN, f64::NAN),
+        |(_, min, max), [_, value]| (value, min.min(value), max.max(value)),
+    )
+}
+
+/// The points of `hist` whose time lies within `bounds`, or all of them
+/// without bounds.
+fn points_in(
+    hist: &VecDeque<[f64; 2]>,
+    bounds: Option<(f64, f64)>,
+) -> impl Iterator<Item = [f64; 2]> + '_ {
+    hist.iter()
+        .copied()
+        .filter(move |&[t, _]| bounds.is_none_or(|(min, max)| (min..=max).contains(&t)))
+}
+
+/// Counts the packets missing from one ticker's sequence numbers.
+#[derive(Debug, Default)]
+struct SeqTracker {
+    last: Option<u64>,
+    /// How many times the sequence skipped ahead.
+    gaps: u64,
+    /// Packets skipped over, summed across all gaps.
+    dropped: u64,
+}
+
+impl SeqTracker {
+    /// Records `seq`, counting any packets skipped since the last one.
+    /// Unnumbered packets (`seq` 0) are ignored. A `seq` at or below the
+    /// last one, as after a service restart, starts the count afresh.
+    fn observe(&mut self, seq: u64) {
+        if seq == 0 {
+            return;
+        }
+        if let Some(last) = self.last.filter(|&last| seq > last + 1) {
+            self.gaps += 1;
+            self.dropped += seq - last - 1;
+        }
+        self.last = Some(seq);
+    }
+}
+
+/// Forwards decoded packets from `sock` to `tx` until the socket fails or
+/// the UI has gone away. A `SentimentBatch` is unpacked into a packet per
+/// sample, named from `names` or else by its stock id. Read timeouts just
+/// mean a quiet moment and are retried.
+fn listen(
+    sock: &UdpSocket,
+    names: &HashMap<u64, String>,
+    tx: &mpsc::SyncSender<(wire::SentimentPacket, Instant)>,
+) {
+    let mut buf = [0u8; 2 * wire::MAX_BATCH_DATAGRAM_LEN];
+    loop {
+        let n = match sock.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                eprintln!("UDP listener stopped: {}", e);
+                return;
+            }
+        };
+        let packets = match wire::decode_any(&buf[..n]) {
+            Ok(packet) => vec![packet],
+            Err(_) => match wire::decode_batch(&buf[..n]) {
+                Ok(batch) => {
+                    batch.packets(|id| names.get(&id).cloned().unwrap_or_else(|| id.to_string()))
+                }
+                Err(_) => continue,
+            },
+        };
+        for packet in packets {
+            if let Err(mpsc::TrySendError::Disconnected(_)) = tx.try_send((packet, Instant::now()))
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Doubling delay between attempts to reach a server, back to the initial
+/// delay once a connection succeeds.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            next: initial,
+        }
+    }
+
+    /// How long to wait before the next attempt. Each call doubles the
+    /// following delay, up to `max`.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (delay * 2).min(self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+/// Reads the newline-delimited JSON or framed stream at `addr` into `tx`,
+/// reconnecting after `backoff` whenever the connection fails or drops,
+/// until the UI has gone away. `status` tracks the connection for the UI.
+fn stream_from_server(
+    addr: &str,
+    tx: &mpsc::SyncSender<(wire::SentimentPacket, Instant)>,
+    status: &Mutex<ConnectionStatus>,
+    mut backoff: Backoff,
+) {
+    let set_status = |new| *status.lock().unwrap() = new;
+    loop {
+        let connected = TcpStream::connect(addr).and_then(|stream| {
+            stream
+                .set_read_timeout(Some(STREAM_READ_TIMEOUT))
+                .map(|()| stream)
+        });
+        match connected {
+            Ok(stream) => {
+                backoff.reset();
+                set_status(ConnectionStatus::Connected);
+                if !read_stream(BufReader::new(stream), tx) {
+                    return;
+                }
+                eprintln!("Stream from {} dropped", addr);
+            }
+            Err(e) => eprintln!("Could not connect to {}: {}", addr, e),
+        }
+        let delay = backoff.next_delay();
+        set_status(ConnectionStatus::Reconnecting(delay));
+        thread::sleep(delay);
+        set_status(ConnectionStatus::Connecting);
+    }
+}
+
+/// Forwards each line of `reader` that decodes as a `SentimentPacket` to
+/// `tx` until the stream ends or fails; other lines are skipped. A stream
+/// that starts with a frame tag rather than JSON is read as
+/// `wire::encode_frame` frames instead. Returns `false` if the UI has gone
+/// away.
+fn read_stream(
+    mut reader: impl BufRead,
+    tx: &mpsc::SyncSender<(wire::SentimentPacket, Instant)>,
+) -> bool {
+    let framed = reader
+        .fill_buf()
+        .is_ok_and(|buf| buf.first().is_some_and(|&b| b <= 2));
+    if framed {
+        while let Ok(packets) = wire::read_frame(&mut reader) {
+            for packet in packets {
+                if let Err(mpsc::TrySendError::Disconnected(_)) =
+                    tx.try_send((packet, Instant::now()))
+                {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let Ok(packet) = wire::decode_packet(line.as_bytes()) else {
+            continue;
+        };
+        if let Err(mpsc::TrySendError::Disconnected(_)) = tx.try_send((packet, Instant::now())) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Ports listed in `DISCOVERY_PORTS_ENV_VAR`; unparsable entries are
+/// skipped.
+fn discovery_ports() -> Vec<u16> {
+    std::env::var(DISCOVERY_PORTS_ENV_VAR)
+        .map(|ports| {
+            ports
+                .split(',')
+                .filter_map(|port| port.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Makes sure `ticker` has a history and a visible line, adding it if this
+/// is the first packet naming it and fewer than `MAX_DISCOVERED_TICKERS`
+/// have been added so far. Returns whether the ticker is tracked.
+fn discover_ticker(
+    history: &mut HashMap<String, VecDeque<[f64; 2]>>,
+    visible: &mut HashMap<String, bool>,
+    discovered: &mut usize,
+    ticker: &str,
+) -> bool {
+    if history.contains_key(ticker) {
+        return true;
+    }
+    if *discovered >= MAX_DISCOVERED_TICKERS {
+        return false;
+    }
+    history.insert(ticker.to_string(), VecDeque::new());
+    visible.insert(ticker.to_string(), true);
+    *discovered += 1;
+    true
+}
+
+/// Tickers whose latest |sentiment| is at least `threshold`, largest first.
+/// Each comes with the time it crossed, found by walking back through its
+/// history to the start of the run of points beyond the threshold (or the
+/// oldest point kept, if the run is longer than the history).
+fn extreme_tickers(history: &HashMap<String, VecDeque<[f64; 2]>>, threshold: f64) -> Vec<Alert> {
+    let mut alerts: Vec<Alert> = history
+        .iter()
+        .filter_map(|(ticker, hist)| {
+            let &[_, value] = hist.back()?;
+            if value.abs() < threshold {
+                return None;
+            }
+            let since = hist
+                .iter()
+                .rev()
+                .take_while(|[_, v]| v.abs() >= threshold)
+                .last()
+                .map(|&[t, _]| t)?;
+            Some(Alert {
+                ticker: ticker.clone(),
+                value,
+                since,
+            })
+        })
+        .collect();
+    alerts.sort_by(|a, b| {
+        b.value
+            .abs()
+            .total_cmp(&a.value.abs())
+            .then_with(|| a.ticker.cmp(&b.ticker))
+    });
+    alerts
+}
+
+/// Serializes the visibility map for `App::save`.
+fn save_visibility(visible: &HashMap<String, bool>) -> String {
+    serde_json::to_string(visible).expect("a string-keyed map always serializes")
+}
+
+/// Applies saved visibility to the tickers in `visible`. Saved tickers that
+/// aren't configured any more are ignored, and unreadable state is skipped
+/// so the client still starts with everything shown.
+fn restore_visibility(saved: &str, visible: &mut HashMap<String, bool>) {
+    let saved: HashMap<String, bool> = match serde_json::from_str(saved) {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!("Ignoring saved visibility: {}", e);
+            return;
+        }
+    };
+    for (ticker, shown) in visible.iter_mut() {
+        if let Some(&saved) = saved.get(ticker) {
+            *shown = saved;
+        }
+    }
+}
+
+/// A stable line color for `ticker`, so it keeps its color whichever other
+/// tickers are shown. Hashes the name with FNV-1a rather than the std hasher,
+/// whose output is not guaranteed across releases.
+fn ticker_color(ticker: &str) -> egui::Color32 {
+    let hash = ticker.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    TICKER_PALETTE[(hash % TICKER_PALETTE.len() as u64) as usize]
+}
+
+/// The x-axis range showing the last `window_s` seconds up to `now`. Until
+/// that much time has passed, the range starts at 0 and keeps its width.
+fn window_bounds(now: f64, window_s: f64) -> (f64, f64) {
+    let start = (now - window_s).max(0.0);
+    (start, start + window_s)
+}
+
+/// Appends `point`, dropping the oldest points beyond `capacity`.
+fn push_bounded(hist: &mut VecDeque<[f64; 2]>, point: [f64; 2], capacity: usize) {
+    hist.push_back(point);
+    trim_history(hist, capacity);
+}
+
+fn trim_history(hist: &mut VecDeque<[f64; 2]>, capacity: usize) {
+    while hist.len() > capacity {
+        hist.pop_front();
     }
 }
 
 fn main() {
+    let cli = <Cli as clap::Parser>::parse();
     let native_options = NativeOptions::default();
     let _ = run_native(
         "Real-time Stock Sentiment Monitor", // window title
         native_options,
-        Box::new(|cc| Box::new(MyApp::new(cc))),
+        Box::new(|cc| Box::new(MyApp::new(cc, cli))),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_stock_ports_reads_service_csv() {
+        let path =
+            std::env::temp_dir().join(format!("sentiment_client_{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,18001\n\
+             GOOGL,2,Alphabet Inc.,15982000000,2800.0,18002\n",
+        )
+        .unwrap();
+
+        let stocks = load_stock_ports(&path).unwrap();
+        let names = load_stock_names(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(names[&2], "GOOGL");
+
+        let expected = HashMap::from([("AAPL".to_string(), 18001), ("GOOGL".to_string(), 18002)]);
+        assert_eq!(stocks, expected);
+    }
+
+    #[test]
+    fn test_history_to_csv_skips_empty_tickers() {
+        let history = HashMap::from([
+            ("PLTR".to_string(), VecDeque::new()),
+            (
+                "GOOGL".to_string(),
+                VecDeque::from([[0.5, -0.25], [1.5, 0.125]]),
+            ),
+            ("AAPL".to_string(), VecDeque::from([[0.25, 0.5]])),
+        ]);
+
+        assert_eq!(
+            history_to_csv(&history),
+            "time_s,ticker,value\n\
+             0.25,AAPL,0.5\n\
+             0.5,GOOGL,-0.25\n\
+             1.5,GOOGL,0.125\n"
+        );
+    }
+
+    #[test]
+    fn test_ema_of_constant_series_is_constant() {
+        let series: Vec<[f64; 2]> = (0..50).map(|i| [i as f64, 0.3]).collect();
+        let smoothed = ema(&series, 0.2);
+        assert_eq!(smoothed.len(), series.len());
+        assert!(smoothed
+            .iter()
+            .zip(&series)
+            .all(|(s, p)| s[0] == p[0] && (s[1] - 0.3).abs() < 1e-12));
+
+        let step = ema(&[[0.0, 0.0], [1.0, 1.0]], 0.25);
+        assert_eq!(step[1], [1.0, 0.25]);
+    }
+
+    #[test]
+    fn test_rebase_subtracts_constant_baseline() {
+        let rebased = rebase(&[[0.0, 0.5], [1.0, -0.25]], Baseline::Constant(0.5));
+        assert_eq!(rebased, vec![[0.0, 0.0], [1.0, -0.75]]);
+    }
+
+    #[test]
+    fn test_rolling_baseline_centers_offset_series() {
+        // A constant 0.5 plus deterministic noise in [-0.1, 0.1]
+        let series: Vec<[f64; 2]> = (0..1_000)
+            .map(|i| [i as f64, 0.5 + 0.1 * (i as f64 * 1.7).sin()])
+            .collect();
+        let rebased = rebase(&series, Baseline::RollingMean(ROLLING_BASELINE_WINDOW));
+        assert_eq!(rebased.len(), series.len());
+        assert!(rebased.iter().zip(&series).all(|(r, p)| r[0] == p[0]));
+
+        let settled = &rebased[ROLLING_BASELINE_WINDOW..];
+        let mean = settled.iter().map(|p| p[1]).sum::<f64>() / settled.len() as f64;
+        assert!(mean.abs() < 0.01, "mean {}", mean);
+        assert!(settled.iter().all(|p| p[1].abs() < 0.25));
+    }
+
+    #[test]
+    fn test_summary_reports_last_min_and_max() {
+        let series = [[0.0, 0.1], [1.0, -0.4], [2.0, 0.7], [3.0, 0.2]];
+        assert_eq!(summary(series), (0.2, -0.4, 0.7));
+
+        let (last, min, max) = summary([]);
+        assert!(last.is_nan() && min.is_nan() && max.is_nan());
+    }
+
+    #[test]
+    fn test_summary_of_a_window_ignores_older_points() {
+        let hist = VecDeque::from([[0.0, 0.9], [85.0, -0.8], [92.0, 0.3], [99.0, -0.1]]);
+        let bounds = window_bounds(100.0, 10.0);
+        assert_eq!(summary(points_in(&hist, Some(bounds))), (-0.1, -0.1, 0.3));
+        assert_eq!(summary(points_in(&hist, None)), (-0.1, -0.8, 0.9));
+        assert!(summary(points_in(&hist, Some((200.0, 210.0)))).0.is_nan());
+    }
+
+    #[test]
+    fn test_discovery_adds_new_tickers_up_to_the_cap() {
+        let mut history = HashMap::from([("AAPL".to_string(), VecDeque::new())]);
+        let mut visible = HashMap::from([("AAPL".to_string(), true)]);
+        let mut discovered = 0;
+
+        let packets = ["AAPL", "MSFT", "AAPL", "MSFT", "NVDA"];
+        let tracked: Vec<bool> = packets
+            .iter()
+            .map(|ticker| discover_ticker(&mut history, &mut visible, &mut discovered, ticker))
+            .collect();
+        assert_eq!(tracked, [true; 5]);
+        assert_eq!(discovered, 2);
+        assert!(history.contains_key("MSFT") && history.contains_key("NVDA"));
+        assert_eq!(visible.get("NVDA"), Some(&true));
+
+        for i in 2..MAX_DISCOVERED_TICKERS {
+            let ticker = format!("T{}", i);
+            assert!(discover_ticker(
+                &mut history,
+                &mut visible,
+                &mut discovered,
+                &ticker
+            ));
+        }
+        assert!(!discover_ticker(
+            &mut history,
+            &mut visible,
+            &mut discovered,
+            "LATE"
+        ));
+        assert!(discover_ticker(
+            &mut history,
+            &mut visible,
+            &mut discovered,
+            "AAPL"
+        ));
+        assert_eq!(history.len(), 1 + MAX_DISCOVERED_TICKERS);
+    }
+
+    #[test]
+    fn test_listener_survives_read_timeout() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        let addr = sock.local_addr().unwrap();
+        let (tx, rx) = mpsc::sync_channel(8);
+        thread::spawn(move || listen(&sock, &HashMap::new(), &tx));
+
+        // Several read timeouts pass before anything is sent
+        thread::sleep(Duration::from_millis(100));
+        let packet = wire::SentimentPacket::now("AAPL", 1, 0.5);
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(&wire::WireFormat::Json.encode(&packet), addr)
+            .unwrap();
+
+        let (received, _) = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(received.ticker, "AAPL");
+        assert_eq!(received.value, 0.5);
+    }
+
+    #[test]
+    fn test_listener_unpacks_batches() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        let names = HashMap::from([(1, "AAPL".to_string()), (2, "GOOGL".to_string())]);
+        let (tx, rx) = mpsc::sync_channel(8);
+        thread::spawn(move || listen(&sock, &names, &tx));
+
+        let batch = wire::SentimentBatch {
+            timestamp_ms: 1_700_000_000_000,
+            samples: vec![(1, 0.5), (2, -0.25), (7, 0.1)],
+        };
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(&batch.encode(), addr)
+            .unwrap();
+
+        let received: Vec<(String, f64)> = (0..3)
+            .map(|_| {
+                let (packet, _) = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+                (packet.ticker, packet.value)
+            })
+            .collect();
+        assert_eq!(
+            received,
+            [
+                ("AAPL".to_string(), 0.5),
+                ("GOOGL".to_string(), -0.25),
+                ("7".to_string(), 0.1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slow_consumer_keeps_queue_bounded() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        let addr = sock.local_addr().unwrap();
+        let (tx, rx) = mpsc::sync_channel(16);
+        thread::spawn(move || listen(&sock, &HashMap::new(), &tx));
+
+        // Nobody reads while a burst arrives
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let packet = wire::WireFormat::Json.encode(&wire::SentimentPacket::now("AAPL", 1, 0.5));
+        for _ in 0..200 {
+            sender.send_to(&packet, addr).unwrap();
+        }
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(rx.try_iter().count(), 16);
+
+        // The listener dropped the overflow rather than blocking on it
+        sender.send_to(&packet, addr).unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(2)).is_ok());
+    }
+
+    #[test]
+    fn test_seq_tracker_counts_gaps() {
+        let mut tracker = SeqTracker::default();
+        for seq in [1, 2, 4, 5] {
+            tracker.observe(seq);
+        }
+        assert_eq!(tracker.gaps, 1);
+        assert_eq!(tracker.dropped, 1);
+
+        // A restarted service numbers from 1 again without counting a gap
+        tracker.observe(1);
+        tracker.observe(2);
+        assert_eq!(tracker.dropped, 1);
+    }
+
+    #[test]
+    fn test_read_stream_skips_malformed_lines() {
+        let packet = wire::SentimentPacket::now("AAPL", 1, 0.5);
+        let mut stream = wire::encode_packet(&packet);
+        stream.extend_from_slice(b"\nnot json\n\n");
+        stream.extend_from_slice(&wire::encode_packet(&packet.clone().with_seq(2)));
+
+        let (tx, rx) = mpsc::sync_channel(8);
+        assert!(read_stream(stream.as_slice(), &tx));
+        let seqs: Vec<u64> = rx.try_iter().map(|(packet, _)| packet.seq).collect();
+        assert_eq!(seqs, [0, 2]);
+
+        drop(rx);
+        assert!(!read_stream(wire::encode_packet(&packet).as_slice(), &tx));
+    }
+
+    #[test]
+    fn test_read_stream_reads_compressed_frames() {
+        let packets: Vec<wire::SentimentPacket> = (1..=30)
+            .map(|seq| wire::SentimentPacket::now("AAPL", 1, 0.5).with_seq(seq))
+            .collect();
+        let compression = Some(wire::StreamCompression::Gzip);
+        let mut stream = wire::encode_frame(&packets[..29], compression, 0).0;
+        stream.extend(wire::encode_frame(&packets[29..], compression, 0).0);
+
+        let (tx, rx) = mpsc::sync_channel(64);
+        assert!(read_stream(stream.as_slice(), &tx));
+        let seqs: Vec<u64> = rx.try_iter().map(|(packet, _)| packet.seq).collect();
+        assert_eq!(seqs, (1..=30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(350));
+        let delays: Vec<u128> = (0..4).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, [100, 200, 350, 350]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_stream_reconnects_after_drop() {
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::sync_channel(8);
+        let status = Arc::new(Mutex::new(ConnectionStatus::Connecting));
+        let thread_status = Arc::clone(&status);
+        thread::spawn(move || {
+            let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50));
+            stream_from_server(&addr, &tx, &thread_status, backoff);
+        });
+
+        // Each connection gets one packet and is then dropped
+        for seq in 1..=2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let packet = wire::SentimentPacket::now("AAPL", 1, 0.5).with_seq(seq);
+            let mut line = wire::encode_packet(&packet);
+            line.push(b'\n');
+            stream.write_all(&line).unwrap();
+            let (received, _) = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            assert_eq!(received.seq, seq);
+            assert_eq!(*status.lock().unwrap(), ConnectionStatus::Connected);
+        }
+    }
+
+    #[test]
+    fn test_extreme_tickers() {
+        let history = HashMap::from([
+            (
+                "AAPL".to_string(),
+                VecDeque::from([[0.0, 0.5], [1.0, 0.95], [2.0, 0.97]]),
+            ),
+            (
+                "GOOGL".to_string(),
+                VecDeque::from([[0.0, -0.99], [1.0, 0.2], [2.0, -0.92], [3.0, -0.99]]),
+            ),
+            (
+                "PLTR".to_string(),
+                VecDeque::from([[0.0, 0.99], [1.0, 0.5]]),
+            ),
+            ("NVDA".to_string(), VecDeque::new()),
+        ]);
+
+        let alerts = extreme_tickers(&history, 0.9);
+        assert_eq!(
+            alerts,
+            vec![
+                Alert {
+                    ticker: "GOOGL".to_string(),
+                    value: -0.99,
+                    since: 2.0,
+                },
+                Alert {
+                    ticker: "AAPL".to_string(),
+                    value: 0.97,
+                    since: 1.0,
+                },
+            ]
+        );
+
+        assert!(extreme_tickers(&history, 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_visibility_round_trip() {
+        let saved = HashMap::from([("AAPL".to_string(), false), ("PLTR".to_string(), true)]);
+        let mut visible = HashMap::from([("AAPL".to_string(), true), ("PLTR".to_string(), true)]);
+        restore_visibility(&save_visibility(&saved), &mut visible);
+        assert_eq!(visible, saved);
+    }
+
+    #[test]
+    fn test_restore_visibility_skips_missing_and_unreadable_state() {
+        let saved = HashMap::from([("GONE".to_string(), false), ("AAPL".to_string(), false)]);
+        let mut visible = HashMap::from([("AAPL".to_string(), true), ("NEW".to_string(), true)]);
+        restore_visibility(&save_visibility(&saved), &mut visible);
+        assert_eq!(
+            visible,
+            HashMap::from([("AAPL".to_string(), false), ("NEW".to_string(), true)])
+        );
+
+        restore_visibility("not json", &mut visible);
+        assert_eq!(visible.get("NEW"), Some(&true));
+    }
+
+    #[test]
+    fn test_time_window_label_round_trip() {
+        for window in TimeWindow::ALL {
+            assert_eq!(TimeWindow::from_label(window.label()), Some(window));
+        }
+        assert_eq!(TimeWindow::from_label("1h"), None);
+    }
+
+    #[test]
+    fn test_ticker_color_is_stable() {
+        assert_eq!(ticker_color("AAPL"), ticker_color("AAPL"));
+        assert_eq!(ticker_color("AAPL"), ticker_color(&String::from("AAPL")));
+        assert!(TICKER_PALETTE.contains(&ticker_color("GOOGL")));
+        assert!(TICKER_PALETTE.contains(&ticker_color("")));
+    }
+
+    #[test]
+    fn test_window_bounds_follow_now() {
+        assert_eq!(window_bounds(100.0, 10.0), (90.0, 100.0));
+        assert_eq!(window_bounds(360.0, 300.0), (60.0, 360.0));
+        assert_eq!(window_bounds(10.0, 10.0), (0.0, 10.0));
+    }
+
+    #[test]
+    fn test_window_bounds_before_window_has_elapsed() {
+        assert_eq!(window_bounds(3.0, 10.0), (0.0, 10.0));
+        assert_eq!(window_bounds(0.0, 60.0), (0.0, 60.0));
+    }
+
+    #[test]
+    fn test_history_respects_capacity() {
+        let mut hist = VecDeque::new();
+        for i in 0..10 {
+            push_bounded(&mut hist, [i as f64, 0.0], 4);
+        }
+        assert_eq!(hist.len(), 4);
+        assert_eq!(hist.front(), Some(&[6.0, 0.0]));
+        assert_eq!(hist.back(), Some(&[9.0, 0.0]));
+
+        trim_history(&mut hist, 2);
+        assert_eq!(hist.iter().map(|p| p[0]).collect::<Vec<_>>(), [8.0, 9.0]);
+    }
+}
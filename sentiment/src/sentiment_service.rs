@@ -1,31 +1,640 @@
 // src/sentiment_service.rs
-use rand::Rng;
-use rand_distr::{Distribution, Normal};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use log::{error, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Normal, Poisson, StandardNormal};
+use sentiment_microservice::{
+    common::{MULTICAST_ADDR, MULTICAST_ADDR_V6, MULTICAST_TTL},
+    wire::{
+        self, HeadlineKind, NewsEvent, Regime, SentimentBatch, SentimentPacket, StreamCompression,
+        WireFormat,
+    },
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    net::{Ipv4Addr, UdpSocket},
-    sync::{Arc, RwLock},
-    thread,
-    time::Duration,
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex, OnceLock, PoisonError, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Packets buffered per `subscribe` receiver. Packets for a receiver that
+/// is this far behind are dropped until it catches up.
+const SUBSCRIBER_QUEUE_CAPACITY: usize = 1_024;
+
+/// Frames buffered per WebSocket client before it is dropped as a slow
+/// consumer.
+#[cfg(feature = "websocket")]
+const WEBSOCKET_QUEUE_CAPACITY: usize = 64;
+
+/// Sending half of one WebSocket client's frame queue.
+#[cfg(feature = "websocket")]
+type WebSocketQueue = SyncSender<Arc<Vec<SentimentPacket>>>;
+
+/// WebSocket client queues plus the last backfill tick the fan-out has
+/// sent them, updated together so a joining client's backfill ends where
+/// its live batches begin.
+#[cfg(feature = "websocket")]
+#[derive(Default)]
+struct WebSocketClients {
+    queues: Vec<WebSocketQueue>,
+    cursor: u64,
+}
+
+/// Receivers handed out by `SentimentService::subscribe`, shared with the
+/// engine.
+type Subscriptions = Arc<Mutex<Vec<Subscription>>>;
+
+/// Receivers handed out by `SentimentService::subscribe_news`, shared with
+/// the engine.
+type NewsSubscriptions = Arc<Mutex<Vec<SyncSender<NewsEvent>>>>;
+
+/// Callback registered through `SentimentService::on_update`.
+type UpdateCallback = Arc<dyn Fn(u64, f64) + Send + Sync>;
+
+/// The service's current stock list. `reload_stocks` swaps in a new list;
+/// worker threads take a cheap clone of the inner `Arc` once per cycle.
+type StockList = Arc<RwLock<Arc<Vec<Stock>>>>;
+
+/// Port the CLI serves `/metrics` on when built with the `metrics` feature.
+#[cfg(feature = "metrics")]
+const METRICS_PORT: u16 = 9184;
+
+/// Replay speeds outside this range are clamped to it.
+const REPLAY_SPEED_RANGE: std::ops::RangeInclusive<f64> = 0.01..=100.0;
+
+/// Tolerance used when checking that a correlation matrix is positive
+/// semidefinite during its Cholesky decomposition.
+const CHOLESKY_EPSILON: f64 = 1e-9;
+
+/// Upper bound on how long a worker thread sleeps before re-checking the
+/// shutdown flag.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The engine counts as dead once it has missed this many ticks.
+const HEARTBEAT_STALE_TICKS: u32 = 10;
+
+/// Most bytes of request line and headers an SSE client may send before
+/// its stream starts.
+const SSE_MAX_REQUEST_LEN: u64 = 8 * 1024;
+
+/// Repeated send failures for one stock are reported at most this often.
+const SEND_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// With `broadcast_price`, a stock's price goes out on its sentiment port
+/// plus this offset.
+const PRICE_PORT_OFFSET: u16 = 1000;
+
+/// Width of the band either side of an `on_threshold_cross` level, relative
+/// to the range's half-width. A sentiment must clear the band to count as
+/// having crossed.
+const THRESHOLD_HYSTERESIS: f64 = 0.02;
+
+/// Ticker and stock id that market index packets are sent under. The id is
+/// the largest possible so that it can't clash with a configured stock.
+const INDEX_TICKER: &str = "INDEX";
+const INDEX_STOCK_ID: u64 = u64::MAX;
+
+/// How stock lists passed to `from_csv_reader` and `from_json_reader` are
+/// named in errors and logs.
+const READER_SOURCE: &str = "<reader>";
+
+/// Ticker and stock id the news event broadcaster sends under, next to the
+/// index's.
+const EVENTS_TICKER: &str = "EVENTS";
+const EVENTS_STOCK_ID: u64 = u64::MAX - 1;
+
+/// Ticker and stock id the batch broadcaster sends under, next to the
+/// events'.
+const BATCH_TICKER: &str = "BATCH";
+const BATCH_STOCK_ID: u64 = u64::MAX - 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Stock {
     pub ticker: String,
     pub id: u64,
     pub company_name: String,
     pub total_float: u64,
     pub initial_price: f64,
-    pub sentiment_port: u64,
+    pub sentiment_port: u16,
+    /// Sentiment to start from, e.g. the last value of a previous run.
+    /// `None` starts at the config's `mean`. Clamped to the sentiment range.
+    #[serde(default)]
+    pub initial_sentiment: Option<f64>,
+    /// Stocks naming the same sector share a sector mood on top of the
+    /// market mood. `None` follows the market mood alone.
+    #[serde(default)]
+    pub sector: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+/// Row shape read by `from_csv`. The port is read as `u64` so that values
+/// above 65535 can be reported with their row number rather than as an opaque
+/// deserialization error.
+#[derive(Debug, Deserialize)]
+struct StockRecord {
+    ticker: String,
+    id: u64,
+    company_name: String,
+    total_float: u64,
+    initial_price: f64,
+    sentiment_port: u64,
+    /// Optional column; empty or missing means no seed.
+    #[serde(default)]
+    initial_sentiment: Option<f64>,
+    /// Optional column; empty or missing means no sector.
+    #[serde(default)]
+    sector: Option<String>,
+}
+
+impl StockRecord {
+    fn into_stock(self) -> Result<Stock, String> {
+        let sentiment_port = u16::try_from(self.sentiment_port).map_err(|_| {
+            format!(
+                "sentiment_port {} for {} is out of range (max 65535); \
+                 sentiment_port is now a 16-bit UDP port, update this row",
+                self.sentiment_port, self.ticker
+            )
+        })?;
+
+        Ok(Stock {
+            ticker: self.ticker,
+            id: self.id,
+            company_name: self.company_name,
+            total_float: self.total_float,
+            initial_price: self.initial_price,
+            sentiment_port,
+            initial_sentiment: self.initial_sentiment,
+            sector: self.sector,
+        })
+    }
+}
+
+/// One stock list row that could not be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvRowError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CsvRowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row {}: {}", self.line, self.message)
+    }
+}
+
+/// Every bad row in a stock list, returned instead of stopping at the
+/// first one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvLoadError {
+    pub path: String,
+    pub rows: Vec<CsvRowError>,
+}
+
+impl fmt::Display for CsvLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} invalid row(s) in {}", self.rows.len(), self.path)?;
+        for row in &self.rows {
+            write!(f, "\n  {}", row)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CsvLoadError {}
+
+/// Why a stock list file could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The CSV file itself could not be read, e.g. a missing header.
+    Csv(csv::Error),
+    /// The CSV header has no column with this required name.
+    MissingColumn(&'static str),
+    /// A `csv_delimiter` that is not a single ASCII character.
+    InvalidDelimiter(char),
+    /// One or more CSV rows were bad.
+    Rows(CsvLoadError),
+    Json(serde_json::Error),
+    /// A JSONL line that is not a valid `Stock`.
+    JsonLine {
+        line: usize,
+        source: serde_json::Error,
+    },
+    /// A stock reuses an earlier stock's id. `index` is its position in the
+    /// list.
+    DuplicateId {
+        id: u64,
+        index: usize,
+    },
+    /// A stock reuses an earlier stock's sentiment port.
+    DuplicatePort {
+        port: u16,
+        index: usize,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "failed to read stock list: {}", e),
+            LoadError::Csv(e) => write!(f, "failed to read stock list: {}", e),
+            LoadError::MissingColumn(column) => {
+                write!(f, "stock list has no `{}` column", column)
+            }
+            LoadError::InvalidDelimiter(delimiter) => {
+                write!(f, "CSV delimiter {:?} is not an ASCII character", delimiter)
+            }
+            LoadError::Rows(e) => e.fmt(f),
+            LoadError::Json(e) => write!(f, "invalid stock list JSON: {}", e),
+            LoadError::JsonLine { line, source } => {
+                write!(f, "line {}: invalid stock JSON: {}", line, source)
+            }
+            LoadError::DuplicateId { id, index } => {
+                write!(f, "stock #{}: duplicate id {}", index + 1, id)
+            }
+            LoadError::DuplicatePort { port, index } => {
+                write!(f, "stock #{}: duplicate sentiment_port {}", index + 1, port)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Csv(e) => Some(e),
+            LoadError::Rows(e) => Some(e),
+            LoadError::Json(e) | LoadError::JsonLine { source: e, .. } => Some(e),
+            LoadError::MissingColumn(_)
+            | LoadError::InvalidDelimiter(_)
+            | LoadError::DuplicateId { .. }
+            | LoadError::DuplicatePort { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<csv::Error> for LoadError {
+    fn from(e: csv::Error) -> Self {
+        LoadError::Csv(e)
+    }
+}
+
+/// Two-state Markov chain switching the market mood's long-run mean
+/// between a bull and a bear level. Each tick the active regime is kept
+/// with its `*_stay` probability and swapped for the other otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegimeModel {
+    /// Mood mean while bullish, in the sentiment range.
+    pub bull_mean: f64,
+    /// Mood mean while bearish, in the sentiment range.
+    pub bear_mean: f64,
+    /// Probability per tick of staying bullish.
+    pub bull_stay: f64,
+    /// Probability per tick of staying bearish.
+    pub bear_stay: f64,
+}
+
+impl Default for RegimeModel {
+    fn default() -> Self {
+        Self {
+            bull_mean: 0.5,
+            bear_mean: -0.5,
+            bull_stay: 0.999,
+            bear_stay: 0.999,
+        }
+    }
+}
+
+impl RegimeModel {
+    /// Mood mean while in `regime`. The chain is never `Neutral`.
+    fn mean(&self, regime: Regime) -> f64 {
+        match regime {
+            Regime::Bearish => self.bear_mean,
+            _ => self.bull_mean,
+        }
+    }
+
+    fn stay(&self, regime: Regime) -> f64 {
+        match regime {
+            Regime::Bearish => self.bear_stay,
+            _ => self.bull_stay,
+        }
+    }
+}
+
+/// Which way a sentiment crossed an `on_threshold_cross` level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossDirection {
+    Upward,
+    Downward,
+}
+
+/// Which side of `level` a stock's sentiment was last seen on. Values
+/// within `band` of the level leave the side unchanged, so a sentiment
+/// hovering at the level reports one crossing rather than one per tick.
+struct ThresholdWatch {
+    level: f64,
+    band: f64,
+    above: Option<bool>,
+}
+
+impl ThresholdWatch {
+    /// Records `value`, returning the crossing it completes, if any. The
+    /// first value outside the band only sets the side.
+    fn observe(&mut self, value: f64) -> Option<CrossDirection> {
+        let above = if value >= self.level + self.band {
+            true
+        } else if value <= self.level - self.band {
+            false
+        } else {
+            return None;
+        };
+        match (self.above.replace(above), above) {
+            (Some(false), true) => Some(CrossDirection::Upward),
+            (Some(true), false) => Some(CrossDirection::Downward),
+            _ => None,
+        }
+    }
+}
+
+/// How broadcaster threads are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BroadcastMode {
+    /// One thread and socket per stock.
+    #[default]
+    PerStock,
+    /// A single thread and socket that sends every stock's packet each
+    /// cycle. Scales to large stock lists without a thread per stock.
+    Shared,
+}
+
+/// IP version the broadcast sockets use. Every transport address must
+/// belong to this family.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpFamily {
+    #[default]
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    /// The wildcard address sockets of this family bind to.
+    pub fn unspecified(self) -> IpAddr {
+        match self {
+            IpFamily::V4 => Ipv4Addr::UNSPECIFIED.into(),
+            IpFamily::V6 => Ipv6Addr::UNSPECIFIED.into(),
+        }
+    }
+
+    fn of(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => IpFamily::V4,
+            IpAddr::V6(_) => IpFamily::V6,
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpFamily::V4 => write!(f, "IPv4"),
+            IpFamily::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// How sentiment values are presented outside the engine. The engine
+/// always works on the configured sentiment range; the scale is applied
+/// only to broadcast packets, the TCP and WebSocket streams, and
+/// `get_sentiment`/`get_sentiment_by_ticker`. Setters take range values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SentimentScale {
+    /// Values as they are in the sentiment range, [-1, 1] by default.
+    #[default]
+    Signed,
+    /// The sentiment range mapped linearly onto [0, 1], for consumers that
+    /// treat sentiment as a probability.
+    Unit,
+}
+
+/// Where broadcast packets are sent.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transport {
+    /// Send each stock's packets to `addr:<sentiment_port>`. `addr` must be
+    /// a multicast group (224.0.0.0/4 or ff00::/8); a `ttl` of 1 keeps
+    /// packets on the local segment. For IPv6 the TTL is the hop limit.
+    Multicast { addr: IpAddr, ttl: u32 },
+    /// Send every stock's packets directly to each subscriber's IP, on that
+    /// stock's `sentiment_port`. For networks that block multicast.
+    Unicast { subscribers: Vec<SocketAddr> },
+    /// Hand every stock's packets to the service's `InProcessBus`, see
+    /// `SentimentService::bus`, for consumers in the same process. Only
+    /// sentiment broadcasts from `start` reach the bus.
+    InProcess,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::multicast(IpFamily::V4)
+    }
+}
+
+impl Transport {
+    /// Multicast to the default group of `family`.
+    pub fn multicast(family: IpFamily) -> Self {
+        let addr = match family {
+            IpFamily::V4 => MULTICAST_ADDR.into(),
+            IpFamily::V6 => MULTICAST_ADDR_V6.into(),
+        };
+        Transport::Multicast {
+            addr,
+            ttl: MULTICAST_TTL,
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Multicast { addr, ttl } => {
+                write!(f, "multicast group {} (ttl {})", addr, ttl)
+            }
+            Transport::Unicast { subscribers } => {
+                write!(f, "{} unicast subscribers", subscribers.len())
+            }
+            Transport::InProcess => write!(f, "the in-process bus"),
+        }
+    }
+}
+
+/// Engine, broadcast and transport settings. Serializes with durations as
+/// integer milliseconds (`tick_interval_ms`, `broadcast_interval_ms`);
+/// fields missing from a file take their default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SentimentConfig {
+    #[serde(rename = "tick_interval_ms", with = "duration_ms")]
     pub tick_interval: Duration,
+    /// Long-run level the market mood reverts to, in the sentiment range.
+    /// Sentiments and the mood start here.
     pub mean: f64,
+    /// Switch the mood's long-run level between a bull and a bear mean.
+    /// `None` reverts to `mean` throughout.
+    pub regimes: Option<RegimeModel>,
     pub reversion_speed: f64,
+    /// Noise per unit time, relative to the range's half-width; the same
+    /// value produces proportionally the same walk on any range.
     pub volatility: f64,
+    /// Lower bound every sentiment and the mood are clamped to.
+    pub sentiment_min: f64,
+    /// Upper bound every sentiment and the mood are clamped to.
+    pub sentiment_max: f64,
+    /// Seed for the engine RNG. `None` seeds from OS entropy, so every run
+    /// produces a different walk; `Some(seed)` makes the stream reproducible.
+    pub seed: Option<u64>,
+    /// Constant added to every stock's sentiment on top of the market mood,
+    /// relative to the range's half-width. Leave at 0.0 to keep the output
+    /// centered on the mood; a non-zero value shifts the whole distribution
+    /// before clamping to the sentiment range.
+    pub baseline_offset: f64,
+    /// Scale that outgoing values are reported on.
+    pub scale: SentimentScale,
+    /// Snap every reported value to the nearest multiple of this, on the
+    /// reported `scale`; the engine itself stays continuous. Pick a quantum
+    /// that divides the range, or values near its ends may round up to
+    /// half a quantum past them. `None` reports values as they are.
+    pub quantum: Option<f64>,
+    /// Encoding used for broadcast packets.
+    pub wire_format: WireFormat,
+    /// One broadcaster thread per stock (`PerStock`) or one for all of them
+    /// (`Shared`).
+    pub broadcast_mode: BroadcastMode,
+    /// Most stocks to load with `BroadcastMode::PerStock`, which runs a
+    /// thread per stock. Loading or reloading more is an error; the shared
+    /// mode and `batch` aren't limited. `None` allows any number.
+    pub max_stocks: Option<usize>,
+    pub transport: Transport,
+    /// Further transports every sentiment broadcast is also sent over, e.g.
+    /// unicast to remote subscribers next to multicast on the LAN. Prices,
+    /// the index and `start_async` only use `transport`.
+    #[serde(default)]
+    pub extra_transports: Vec<Transport>,
+    /// IP version of the broadcast sockets. Must match the transport's
+    /// addresses; use `Transport::multicast(IpFamily::V6)` for the default
+    /// IPv6 group.
+    pub ip_family: IpFamily,
+    /// Deliver multicast packets to receivers on this host as well. Turn
+    /// off when nothing local consumes the stream.
+    pub multicast_loop: bool,
+    /// Local address of the network interface IPv4 multicast is sent out
+    /// of, for hosts with more than one. `None` leaves the choice to the
+    /// routing table.
+    pub multicast_interface: Option<Ipv4Addr>,
+    /// Consecutive failed sends after which a stock's broadcaster is
+    /// reported unhealthy and its socket is rebound, again every this many
+    /// failures. 0 never rebinds.
+    pub max_send_failures: u32,
+    /// Open sockets and broadcast. When false, `start` and `start_async`
+    /// run only the engine, for tests and sandboxes without networking.
+    pub enable_broadcast: bool,
+    /// Have sentiment broadcasters ease each stock from its previous value
+    /// to its current one over the tick interval, instead of repeating one
+    /// value until the next tick. Stream servers are not interpolated.
+    pub interpolate: bool,
+    /// Send every stock's value in `SentimentBatch` datagrams on
+    /// `batch_port` from one broadcaster, instead of a packet per stock on
+    /// its own port. Replaces `broadcast_mode`'s broadcasters; batches are
+    /// always JSON and are neither interpolated nor staggered. They only go
+    /// over `transport` and to `add_subscriber` addresses registered under
+    /// the batch's stock id, not over `extra_transports` or `add_sink`
+    /// sinks, so `Transport::InProcess` is rejected.
+    pub batch: bool,
+    /// Port that `batch` datagrams are sent to.
+    pub batch_port: u16,
+    /// Spread broadcasts over the tick interval: each stock's new value is
+    /// held back until its `phase` of the interval has passed since the
+    /// tick, instead of every stock going out together.
+    pub stagger: bool,
+    /// With `stagger`, how far into the tick interval a stock's value is
+    /// sent, as a fraction in [0, 1). Set through `per_stock` for one
+    /// stock, or here for every stock without an override. `None` derives
+    /// a phase from the stock id.
+    pub phase: Option<f64>,
+    /// Minimum time between two packets for the same stock. A stock is only
+    /// re-sent once its value has changed, so this is a maximum rate.
+    #[serde(rename = "broadcast_interval_ms", with = "duration_ms")]
+    pub broadcast_interval: Duration,
+    /// Engine steps to take before broadcasters send anything, so that
+    /// consumers never see the all-zero starting state. Ignored on replay.
+    pub warmup_ticks: usize,
+    /// Engine ticks kept for TCP and WebSocket clients that connect late.
+    /// Above 0, a new client first gets every stock's value for each kept
+    /// tick, then each new tick's values exactly once, numbered by tick.
+    /// While paused nothing new is streamed. 0 disables backfill; clients
+    /// then get the latest values every `broadcast_interval`.
+    #[serde(default)]
+    pub backfill_ticks: usize,
+    /// Compress the TCP and WebSocket streams. Set, TCP clients get
+    /// `wire::encode_frame` frames, one per cycle, instead of JSON lines,
+    /// and WebSocket clients get each cycle's frame as a binary message
+    /// instead of a text message per packet. `None` streams uncompressed.
+    pub stream_compression: Option<StreamCompression>,
+    /// Smallest frame payload, in bytes, that `stream_compression`
+    /// compresses; smaller ones are sent as is.
+    pub compression_threshold: usize,
+    /// Expected number of jumps in the market mood per tick (Poisson rate).
+    /// 0.0 disables jumps.
+    pub jump_intensity: f64,
+    /// Standard deviation of each jump's normally distributed size.
+    pub jump_scale: f64,
+    /// Fractional price change per second at the top of the sentiment
+    /// range; a stock at the bottom falls at the same rate. Prices start at
+    /// each stock's `initial_price`.
+    pub price_drift: f64,
+    /// Also broadcast every stock's simulated price, on its sentiment port
+    /// plus `PRICE_PORT_OFFSET`.
+    pub broadcast_price: bool,
+    /// Port to broadcast the float-weighted market index on, as ticker
+    /// `INDEX`. `None` doesn't broadcast it.
+    pub index_port: Option<u16>,
+    /// Expected number of news events per second across all stocks, each
+    /// hitting one stock picked at random (Poisson arrivals). 0.0 disables
+    /// news.
+    pub news_rate: f64,
+    /// Typical size of a news event's shock, relative to the range's
+    /// half-width. Each event's shock is drawn from half to one and a half
+    /// times this, signed by its headline.
+    pub news_impact: f64,
+    /// Port to broadcast news events on, as JSON `NewsEvent`s. `None`
+    /// doesn't broadcast them.
+    pub events_port: Option<u16>,
+    /// Per-stock overrides keyed by stock id. Only the `mean`, `volatility`,
+    /// `reversion_speed`, `baseline_offset` and `phase` of an override apply
+    /// to its stock; an override's `mean` shifts the stock's target by its
+    /// difference from this config's. Stocks without an entry use this
+    /// config.
+    #[serde(with = "per_stock_keys")]
+    pub per_stock: HashMap<u64, SentimentConfig>,
+    /// Field separator of stock CSV files, e.g. `;` for files exported
+    /// with a decimal comma. Must be an ASCII character.
+    pub csv_delimiter: char,
 }
 
 impl Default for SentimentConfig {
@@ -33,236 +642,7939 @@ impl Default for SentimentConfig {
         Self {
             tick_interval: Duration::from_millis(100),
             mean: 0.0,
+            regimes: None,
             reversion_speed: 0.5,
             volatility: 0.2,
+            sentiment_min: -1.0,
+            sentiment_max: 1.0,
+            seed: None,
+            baseline_offset: 0.0,
+            scale: SentimentScale::default(),
+            quantum: None,
+            wire_format: WireFormat::default(),
+            broadcast_mode: BroadcastMode::default(),
+            max_stocks: None,
+            broadcast_interval: Duration::from_millis(5),
+            transport: Transport::default(),
+            extra_transports: Vec::new(),
+            ip_family: IpFamily::default(),
+            multicast_loop: true,
+            multicast_interface: None,
+            max_send_failures: 100,
+            enable_broadcast: true,
+            interpolate: false,
+            batch: false,
+            batch_port: 18000,
+            stagger: false,
+            phase: None,
+            warmup_ticks: 0,
+            backfill_ticks: 0,
+            stream_compression: None,
+            compression_threshold: 512,
+            csv_delimiter: ',',
+            jump_intensity: 0.0,
+            jump_scale: 0.0,
+            price_drift: 0.01,
+            broadcast_price: false,
+            index_port: None,
+            news_rate: 0.0,
+            news_impact: 0.2,
+            events_port: None,
+            per_stock: HashMap::new(),
         }
     }
 }
 
-pub struct SentimentService {
-    stocks: Vec<Stock>,
-    sentiments: Arc<RwLock<HashMap<u64, f64>>>,
-    market_mood: Arc<RwLock<f64>>,
-    config: SentimentConfig,
+/// The settings that shape the stream, on one line for logs, e.g.
+/// `tick=100ms mean=0 reversion=0.5 volatility=0.2 range=[-1, 1]
+/// seed=entropy scale=Signed format=Json mode=PerStock transport=...`.
+impl fmt::Display for SentimentConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tick={}ms mean={} reversion={} volatility={} range=[{}, {}] seed=",
+            self.tick_interval.as_millis(),
+            self.mean,
+            self.reversion_speed,
+            self.volatility,
+            self.sentiment_min,
+            self.sentiment_max
+        )?;
+        match self.seed {
+            Some(seed) => write!(f, "{}", seed)?,
+            None => write!(f, "entropy")?,
+        }
+        write!(
+            f,
+            " scale={:?} format={:?} mode={:?} transport={}",
+            self.scale, self.wire_format, self.broadcast_mode, self.transport
+        )
+    }
 }
 
-impl SentimentService {
-    pub fn new(stocks: Vec<Stock>, config: Option<SentimentConfig>) -> Self {
-        let mut sentiments = HashMap::new();
-        for stock in &stocks {
-            sentiments.insert(stock.id, 0.0);
-        }
+/// Serde adapter storing a `Duration` as whole milliseconds.
+mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
 
-        Self {
-            stocks,
-            sentiments: Arc::new(RwLock::new(sentiments)),
-            market_mood: Arc::new(RwLock::new(0.0)),
-            config: config.unwrap_or_default(),
-        }
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
     }
 
-    pub fn from_csv(
-        csv_path: &str,
-        config: Option<SentimentConfig>,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut reader = csv::Reader::from_path(csv_path)?;
-        let mut stocks = Vec::new();
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
 
-        for result in reader.deserialize() {
-            let stock: Stock = result?;
-            stocks.push(stock);
-        }
+/// Serde adapter writing `per_stock` ids as string keys, since TOML table
+/// keys are always strings.
+mod per_stock_keys {
+    use super::SentimentConfig;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::{BTreeMap, HashMap};
 
-        println!("Loaded {} stocks from {}", stocks.len(), csv_path);
-        Ok(Self::new(stocks, config))
+    pub fn serialize<S: Serializer>(
+        per_stock: &HashMap<u64, SentimentConfig>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        per_stock
+            .iter()
+            .map(|(id, config)| (id.to_string(), config))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
     }
 
-    pub fn start(&self) {
-        println!(
-            "Starting sentiment service for {} stocks",
-            self.stocks.len()
-        );
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<u64, SentimentConfig>, D::Error> {
+        BTreeMap::<String, SentimentConfig>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(id, config)| {
+                let id = id
+                    .parse()
+                    .map_err(|_| D::Error::custom(format!("stock id {:?} is not a number", id)))?;
+                Ok((id, config))
+            })
+            .collect()
+    }
+}
 
-        // Start the sentiment update engine
-        self.start_sentiment_engine();
+impl SentimentConfig {
+    /// Reads a config from a TOML file and checks it with `validate`.
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let config: SentimentConfig = toml::from_str(&text)?;
+        config.validate()?;
+        Ok(config)
+    }
 
-        // Start UDP broadcasters for each stock
-        for stock in &self.stocks {
-            self.start_udp_broadcaster(stock.clone());
-        }
+    /// The config that governs `stock_id`: its override if one is set,
+    /// otherwise `self`.
+    pub fn for_stock(&self, stock_id: u64) -> &SentimentConfig {
+        self.per_stock.get(&stock_id).unwrap_or(self)
     }
 
-    fn start_sentiment_engine(&self) {
-        let sentiments = Arc::clone(&self.sentiments);
-        let market_mood = Arc::clone(&self.market_mood);
-        let stocks = self.stocks.clone();
-        let config = self.config.clone();
+    /// How long after a tick `stock_id`'s new value may be broadcast, or
+    /// `None` without `stagger`.
+    fn phase(&self, stock_id: u64) -> Option<Duration> {
+        self.stagger.then(|| {
+            let phase = self
+                .for_stock(stock_id)
+                .phase
+                .unwrap_or_else(|| hashed_phase(stock_id));
+            self.tick_interval.mul_f64(phase)
+        })
+    }
 
-        thread::spawn(move || {
-            let mut rng = rand::thread_rng();
-            let dt = config.tick_interval.as_secs_f64();
-            // Create a normal distribution for the noise term
-            let normal_dist = Normal::new(0.0, config.volatility).unwrap();
-            let offset = 0.5;
-            loop {
-                thread::sleep(config.tick_interval);
-
-                let mut mood = market_mood.write().unwrap();
-                let reversion = config.reversion_speed * (config.mean - *mood) * dt;
-                // Use the normal distribution to generate symmetrical noise
-                let noise = normal_dist.sample(&mut rng) * dt.sqrt();
-                *mood += reversion + noise;
-                *mood = mood.clamp(-1.0, 1.0);
-
-                if let Ok(mut sentiment_map) = sentiments.write() {
-                    for stock in &stocks {
-                        if let Some(current_sentiment) = sentiment_map.get_mut(&stock.id) {
-                            let stock_noise = config.volatility * 0.1 * rng.gen_range(-1.0..1.0);
-                            *current_sentiment = (*mood + stock_noise+offset).clamp(-1.0, 1.0);
-                        }
+    /// Checks that the engine parameters are in range and that a multicast
+    /// transport actually names a multicast group. Per-stock overrides are
+    /// checked for the fields they contribute.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.tick_interval.is_zero() {
+            return Err(ConfigError::InvalidTickInterval(self.tick_interval));
+        }
+        if !self.sentiment_min.is_finite()
+            || !self.sentiment_max.is_finite()
+            || self.sentiment_min >= self.sentiment_max
+        {
+            return Err(ConfigError::InvalidSentimentRange {
+                min: self.sentiment_min,
+                max: self.sentiment_max,
+            });
+        }
+        if !(self.sentiment_min..=self.sentiment_max).contains(&self.mean) {
+            return Err(ConfigError::MeanOutOfRange(self.mean));
+        }
+        if let Some(quantum) = self.quantum {
+            let width = match self.scale {
+                SentimentScale::Signed => self.sentiment_max - self.sentiment_min,
+                SentimentScale::Unit => 1.0,
+            };
+            // Also rejects NaN
+            if !(quantum > 0.0 && quantum < width) {
+                return Err(ConfigError::InvalidQuantum(quantum));
+            }
+        }
+        if let Some(model) = &self.regimes {
+            for mean in [model.bull_mean, model.bear_mean] {
+                if !(self.sentiment_min..=self.sentiment_max).contains(&mean) {
+                    return Err(ConfigError::MeanOutOfRange(mean));
+                }
+            }
+            for stay in [model.bull_stay, model.bear_stay] {
+                if !(0.0..=1.0).contains(&stay) {
+                    return Err(ConfigError::TransitionProbabilityOutOfRange(stay));
+                }
+            }
+        }
+        if let Some(interface) = self.multicast_interface {
+            self.check_family(interface.into())?;
+        }
+        for transport in self.transports() {
+            match transport {
+                Transport::Multicast { addr, .. } => {
+                    if !addr.is_multicast() {
+                        return Err(ConfigError::InvalidMulticastAddr(*addr));
+                    }
+                    self.check_family(*addr)?;
+                }
+                Transport::Unicast { subscribers } => {
+                    for subscriber in subscribers {
+                        self.check_family(subscriber.ip())?;
                     }
                 }
+                Transport::InProcess => {}
             }
-        });
+        }
+        if self.batch && self.transport == Transport::InProcess {
+            return Err(ConfigError::InProcessBatch);
+        }
+        if !self.news_rate.is_finite() || self.news_rate < 0.0 {
+            return Err(ConfigError::InvalidNewsRate(self.news_rate));
+        }
+        if !self.news_impact.is_finite() || self.news_impact < 0.0 {
+            return Err(ConfigError::InvalidNewsImpact(self.news_impact));
+        }
+        // The engine builds its jump distributions from these
+        let valid = |value: f64| value.is_finite() && value >= 0.0;
+        if !valid(self.jump_intensity) || !valid(self.jump_scale) {
+            return Err(ConfigError::InvalidJumps {
+                intensity: self.jump_intensity,
+                scale: self.jump_scale,
+            });
+        }
+        for config in std::iter::once(self).chain(self.per_stock.values()) {
+            if !valid(config.volatility) {
+                return Err(ConfigError::NegativeVolatility(config.volatility));
+            }
+            if !(0.0..=1.0).contains(&config.reversion_speed) {
+                return Err(ConfigError::ReversionSpeedOutOfRange(
+                    config.reversion_speed,
+                ));
+            }
+            if let Some(phase) = config.phase.filter(|phase| !(0.0..1.0).contains(phase)) {
+                return Err(ConfigError::PhaseOutOfRange(phase));
+            }
+        }
+        Ok(())
     }
 
-    fn start_udp_broadcaster(&self, stock: Stock) {
-        let sentiments = Arc::clone(&self.sentiments);
-        const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 123);
+    /// `transport` followed by the `extra_transports`.
+    pub fn transports(&self) -> impl Iterator<Item = &Transport> {
+        std::iter::once(&self.transport).chain(&self.extra_transports)
+    }
 
-        thread::spawn(move || {
-            let addr = format!("{}:{}", MULTICAST_ADDR, stock.sentiment_port);
-            let socket = match UdpSocket::bind("0.0.0.0:0") {
-                Ok(socket) => {
-                    // Set a TTL to prevent packets from leaving the local network
-                    socket.set_multicast_ttl_v4(1).expect("set_multicast_ttl_v4 failed");
-                    println!(
-                        "✓ {} ({}) broadcasting to multicast group {}",
-                        stock.ticker, stock.company_name, addr
-                    );
-                    socket
-                }
-                Err(e) => {
-                    eprintln!("✗ Failed to create UDP socket for {}: {}", stock.ticker, e);
-                    return;
-                }
-            };
+    /// `value` clamped to the sentiment range.
+    pub fn clamp(&self, value: f64) -> f64 {
+        value.clamp(self.sentiment_min, self.sentiment_max)
+    }
 
-            loop {
-                let sentiment = {
-                    sentiments
-                        .read()
-                        .map(|map| map.get(&stock.id).copied().unwrap_or(0.0))
-                        .unwrap_or(0.0)
-                };
+    /// Where `stock`'s sentiment starts: its seed if it has one, otherwise
+    /// `mean`.
+    fn initial_sentiment(&self, stock: &Stock) -> f64 {
+        stock
+            .initial_sentiment
+            .map_or(self.mean, |seed| self.clamp(seed))
+    }
+
+    /// `value` from the sentiment range as reported under `scale`, snapped
+    /// to `quantum` if there is one.
+    pub fn output_value(&self, value: f64) -> f64 {
+        let value = match self.scale {
+            SentimentScale::Signed => value,
+            SentimentScale::Unit => (self.normalize(value) + 1.0) / 2.0,
+        };
+        match self.quantum {
+            Some(quantum) => (value / quantum).round() * quantum,
+            None => value,
+        }
+    }
 
-                let message = format!("{:.6}", sentiment);
+    /// Half the width of the sentiment range; 1.0 for the default [-1, 1].
+    /// Volatility, jump sizes and baseline offsets are multiples of this.
+    fn half_width(&self) -> f64 {
+        (self.sentiment_max - self.sentiment_min) / 2.0
+    }
 
-                // Broadcast to multicast group - fire and forget
-                if let Err(e) = socket.send_to(message.as_bytes(), &addr) {
-                    eprintln!("Failed to broadcast {} sentiment: {}", stock.ticker, e);
-                }
+    /// Maps `value` from the sentiment range onto [-1, 1].
+    fn normalize(&self, value: f64) -> f64 {
+        let center = (self.sentiment_min + self.sentiment_max) / 2.0;
+        (value - center) / self.half_width()
+    }
 
-                thread::sleep(Duration::from_millis(5)); // 200 updates per second
+    /// Checks `count` stocks against `max_stocks`.
+    fn check_stock_count(&self, count: usize) -> Result<(), ConfigError> {
+        match self.max_stocks {
+            Some(max)
+                if count > max && self.broadcast_mode == BroadcastMode::PerStock && !self.batch =>
+            {
+                Err(ConfigError::TooManyStocks { count, max })
             }
-        });
+            _ => Ok(()),
+        }
     }
 
-    pub fn get_sentiment(&self, stock_id: u64) -> f64 {
-        self.sentiments
-            .read()
-            .map(|map| map.get(&stock_id).copied().unwrap_or(0.0))
-            .unwrap_or(0.0)
+    fn check_family(&self, addr: IpAddr) -> Result<(), ConfigError> {
+        if IpFamily::of(addr) != self.ip_family {
+            return Err(ConfigError::IpFamilyMismatch {
+                family: self.ip_family,
+                addr,
+            });
+        }
+        Ok(())
     }
 }
 
-// CLI runner
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
+pub struct SentimentService {
+    stocks: StockList,
+    /// Upper-cased ticker to stock id, kept in step with `stocks`.
+    ticker_index: RwLock<HashMap<String, u64>>,
+    sentiments: Arc<Sentiments>,
+    market_mood: Arc<RwLock<f64>>,
+    /// Each sector's mood, relative to the market mood.
+    sector_moods: Arc<RwLock<HashMap<String, f64>>>,
+    /// State of the `regimes` chain; starts bullish. Unused without one.
+    markov_regime: Arc<RwLock<Regime>>,
+    /// Simulated price per stock id, moved by the engine each tick.
+    prices: Arc<RwLock<HashMap<u64, f64>>>,
+    config: SentimentConfig,
+    shutdown: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    /// `None` means independent noise per stock.
+    correlation: Option<Correlation>,
+    callbacks: Arc<Vec<UpdateCallback>>,
+    /// Receivers registered at runtime through `add_subscriber`.
+    subscribers: Arc<RwLock<HashMap<u64, Vec<SocketAddr>>>>,
+    /// Sinks added through `add_sink`, shared by every broadcaster.
+    sinks: Vec<Arc<dyn Sink>>,
+    /// Where `Transport::InProcess` delivers.
+    bus: InProcessBus,
+    /// Recent ticks for stream clients, see `backfill_ticks`.
+    history: Arc<TickHistory>,
+    /// Set when `interpolate` is on.
+    interpolation: Option<Arc<Interpolation>>,
+    /// Senders for the receivers handed out by `subscribe`.
+    subscriptions: Subscriptions,
+    /// Senders for the receivers handed out by `subscribe_news`.
+    news_subscriptions: NewsSubscriptions,
+    metrics: Arc<Metrics>,
+    /// Where `start_recording` appends each engine tick, if anywhere.
+    recorder: Arc<Mutex<Option<csv::Writer<File>>>>,
+    /// Recording to re-emit instead of running the engine; set by
+    /// `replay` and `from_recording`.
+    replay: Option<Replay>,
+    started: AtomicBool,
+    /// When `start` or `start_async` was first called, for `stats`.
+    started_at: OnceLock<Instant>,
+    /// Each stock's packet count and when `stats` (or the reload that
+    /// added it) last sampled it, to measure `send_rate` from.
+    rate_samples: Mutex<HashMap<u64, (u64, Instant)>>,
+    /// Set once the engine (or replay) thread has been spawned. `start`
+    /// leaves it unset when a reload has emptied the stock list, so the
+    /// next reload that adds stocks spawns it instead.
+    engine_started: AtomicBool,
+    /// Stop flags for the running per-stock broadcasters, by stock id.
+    broadcasters: Arc<Mutex<HashMap<u64, Arc<AtomicBool>>>>,
+    /// Threads spawned after `start`, e.g. by `reload_stocks`. Shared with
+    /// the `ServiceHandle` so `join` waits for them too.
+    late_threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
 
-    let csv_path = args.get(1).map(|s| s.as_str()).unwrap_or("stock.csv");
+/// Lower-triangular Cholesky factor of the stock correlation matrix,
+/// indexed in `ids` order. Only applied while the service's stock list
+/// still matches `ids`; `reload_stocks` can invalidate it.
+#[derive(Debug, Clone)]
+struct Correlation {
+    ids: Vec<u64>,
+    factor: Vec<Vec<f64>>,
+}
 
-    let config = SentimentConfig {
-        tick_interval: Duration::from_millis(100),
-        mean: 0.0,
-        reversion_speed: 0.05,
-        volatility: 0.5,
-    };
+/// How `SentimentService::reload_stocks` changed the stock list. A stock
+/// whose row changed appears in both `removed` (old row) and `added` (new
+/// row); it keeps its current sentiment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StockDiff {
+    pub added: Vec<Stock>,
+    pub removed: Vec<Stock>,
+    pub unchanged: Vec<Stock>,
+}
 
-    let service = SentimentService::from_csv(csv_path, Some(config))?;
+/// Which recording `SentimentService::replay` plays back, and how fast.
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// A file written by `SentimentService::start_recording`.
+    pub path: PathBuf,
+    /// Playback rate relative to the original session: 2.0 is twice as
+    /// fast, 0.5 half. Must be positive; clamped to `REPLAY_SPEED_RANGE`.
+    pub speed: f64,
+}
 
-    println!("🚀 Sentiment microservice starting...");
-    service.start();
+/// A loaded recording and the rate to play it at.
+#[derive(Clone)]
+struct Replay {
+    samples: Arc<Vec<RecordedSample>>,
+    speed: f64,
+}
 
-    // Keep main thread alive
-    loop {
-        thread::sleep(Duration::from_secs(1));
-    }
+/// One row of a recording made by `SentimentService::start_recording`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct RecordedSample {
+    timestamp_ms: u64,
+    stock_id: u64,
+    value: f64,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::{BufRead, BufReader};
-    use std::net::TcpStream;
-    use std::time::Duration;
+/// Why a set of pairwise stock correlations was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorrelationError {
+    /// A pair referenced a stock id that is not configured.
+    UnknownStock(u64),
+    /// A coefficient was outside [-1, 1], or a stock was paired with itself.
+    InvalidCoefficient { a: u64, b: u64, coefficient: f64 },
+    /// The coefficients are jointly inconsistent, e.g. A~B and B~C strongly
+    /// positive but A~C strongly negative.
+    NotPositiveSemidefinite,
+}
 
-    fn create_test_stocks() -> Vec<Stock> {
-        vec![
-            Stock {
-                ticker: "AAPL".to_string(),
-                id: 1,
-                company_name: "Apple Inc.".to_string(),
-                total_float: 15_982_000_000,
-                initial_price: 195.37,
-                sentiment_port: 18001,
-            },
-            Stock {
-                ticker: "GOOGL".to_string(),
-                id: 2,
-                company_name: "Alphabet Inc.".to_string(),
-                total_float: 15_982_000_000,
-                initial_price: 2800.0,
-                sentiment_port: 18002,
-            },
-        ]
+impl fmt::Display for CorrelationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorrelationError::UnknownStock(id) => {
+                write!(f, "correlation references unknown stock id {}", id)
+            }
+            CorrelationError::InvalidCoefficient { a, b, coefficient } => write!(
+                f,
+                "invalid correlation {} between stocks {} and {}; \
+                 expected a value in [-1, 1] between two distinct stocks",
+                coefficient, a, b
+            ),
+            CorrelationError::NotPositiveSemidefinite => write!(
+                f,
+                "correlation matrix is not positive semidefinite; \
+                 the pairwise coefficients contradict each other"
+            ),
+        }
     }
+}
 
-    #[test]
-    fn test_service_creation() {
-        let stocks = create_test_stocks();
-        let service = SentimentService::new(stocks, None);
+impl std::error::Error for CorrelationError {}
 
-        assert_eq!(service.get_sentiment(1), 0.0);
-        assert_eq!(service.get_sentiment(2), 0.0);
-        assert_eq!(service.get_sentiment(999), 0.0); // Non-existent stock
-    }
+/// A `SentimentConfig` that the service refuses to run with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The multicast transport's address is not a multicast group.
+    InvalidMulticastAddr(IpAddr),
+    /// A transport address that does not belong to `ip_family`.
+    IpFamilyMismatch { family: IpFamily, addr: IpAddr },
+    /// A replay speed that is zero, negative or NaN.
+    InvalidReplaySpeed(f64),
+    /// A tick interval of zero.
+    InvalidTickInterval(Duration),
+    /// A volatility that is negative, infinite or NaN.
+    NegativeVolatility(f64),
+    /// A jump intensity or jump scale that is negative, infinite or NaN.
+    InvalidJumps { intensity: f64, scale: f64 },
+    /// A reversion speed outside [0, 1].
+    ReversionSpeedOutOfRange(f64),
+    /// A broadcast phase outside [0, 1).
+    PhaseOutOfRange(f64),
+    /// A long-run mean outside the sentiment range.
+    MeanOutOfRange(f64),
+    /// A regime stay probability outside [0, 1].
+    TransitionProbabilityOutOfRange(f64),
+    /// A sentiment range that is empty or not finite.
+    InvalidSentimentRange { min: f64, max: f64 },
+    /// An empty stock list, e.g. from a CSV with only a header.
+    NoStocks,
+    /// A news rate that is negative, infinite or NaN.
+    InvalidNewsRate(f64),
+    /// A news impact that is negative, infinite or NaN.
+    InvalidNewsImpact(f64),
+    /// A quantum that is not positive or not smaller than the reported
+    /// range.
+    InvalidQuantum(f64),
+    /// More stocks than `max_stocks` allows.
+    TooManyStocks { count: usize, max: usize },
+    /// `batch` with `Transport::InProcess`, which batches are never sent
+    /// over.
+    InProcessBatch,
+}
 
-    #[test]
-    fn test_udp_broadcast() {
-        let stocks = create_test_stocks();
-        let service = SentimentService::new(stocks, None);
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidMulticastAddr(addr) => write!(
+                f,
+                "{} is not a multicast address (expected 224.0.0.0/4 or ff00::/8)",
+                addr
+            ),
+            ConfigError::IpFamilyMismatch { family, addr } => {
+                write!(f, "{} is not an {} address", addr, family)
+            }
+            ConfigError::InvalidReplaySpeed(speed) => {
+                write!(f, "replay speed must be positive, got {}", speed)
+            }
+            ConfigError::InvalidTickInterval(interval) => {
+                write!(f, "tick interval must be positive, got {:?}", interval)
+            }
+            ConfigError::NegativeVolatility(volatility) => write!(
+                f,
+                "volatility must be finite and non-negative, got {}",
+                volatility
+            ),
+            ConfigError::InvalidJumps { intensity, scale } => write!(
+                f,
+                "jump intensity and scale must be finite and non-negative, got {} and {}",
+                intensity, scale
+            ),
+            ConfigError::TransitionProbabilityOutOfRange(p) => {
+                write!(
+                    f,
+                    "regime stay probability must be within [0, 1], got {}",
+                    p
+                )
+            }
+            ConfigError::ReversionSpeedOutOfRange(speed) => {
+                write!(f, "reversion speed must be within [0, 1], got {}", speed)
+            }
+            ConfigError::PhaseOutOfRange(phase) => {
+                write!(f, "phase must be within [0, 1), got {}", phase)
+            }
+            ConfigError::MeanOutOfRange(mean) => {
+                write!(f, "mean {} is outside the sentiment range", mean)
+            }
+            ConfigError::InvalidSentimentRange { min, max } => {
+                write!(f, "sentiment range [{}, {}] is empty", min, max)
+            }
+            ConfigError::NoStocks => write!(f, "no stocks to simulate"),
+            ConfigError::InvalidNewsRate(rate) => {
+                write!(f, "news rate must be non-negative, got {}", rate)
+            }
+            ConfigError::InvalidNewsImpact(impact) => {
+                write!(
+                    f,
+                    "news impact must be finite and non-negative, got {}",
+                    impact
+                )
+            }
+            ConfigError::TooManyStocks { count, max } => write!(
+                f,
+                "{} stocks exceed max_stocks ({}) for one broadcaster thread each; \
+                 use BroadcastMode::Shared to broadcast them from one thread",
+                count, max
+            ),
+            ConfigError::InvalidQuantum(quantum) => write!(
+                f,
+                "quantum must be positive and smaller than the range, got {}",
+                quantum
+            ),
+            ConfigError::InProcessBatch => {
+                write!(
+                    f,
+                    "batch mode sends UDP datagrams and cannot use the in-process transport"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Why `SentimentServiceBuilder::build` failed.
+#[derive(Debug)]
+pub enum BuildError {
+    /// A stock list passed to `stocks_from_csv` could not be loaded, or the
+    /// combined stocks reuse an id or port.
+    Load(LoadError),
+    Config(ConfigError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Load(e) => e.fmt(f),
+            BuildError::Config(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Load(e) => Some(e),
+            BuildError::Config(e) => Some(e),
+        }
+    }
+}
+
+impl From<LoadError> for BuildError {
+    fn from(e: LoadError) -> Self {
+        BuildError::Load(e)
+    }
+}
+
+impl From<ConfigError> for BuildError {
+    fn from(e: ConfigError) -> Self {
+        BuildError::Config(e)
+    }
+}
+
+/// Why a stock's broadcaster could not be started.
+#[derive(Debug)]
+pub enum BindError {
+    /// The UDP socket could not be bound.
+    Socket {
+        ticker: String,
+        sentiment_port: u16,
+        source: io::Error,
+    },
+    /// The socket was bound but its multicast TTL could not be set.
+    MulticastTtl {
+        ticker: String,
+        sentiment_port: u16,
+        source: io::Error,
+    },
+    /// The socket was bound but could not be pinned to
+    /// `multicast_interface`, e.g. because no interface has that address.
+    MulticastInterface {
+        ticker: String,
+        sentiment_port: u16,
+        interface: Ipv4Addr,
+        source: io::Error,
+    },
+}
+
+impl BindError {
+    /// The same failure attributed to `stock`. Used when one shared socket
+    /// fails on behalf of every stock.
+    fn for_stock(&self, stock: &Stock) -> BindError {
+        let (ticker, sentiment_port) = (stock.ticker.clone(), stock.sentiment_port);
+        match self {
+            BindError::Socket { source, .. } => BindError::Socket {
+                ticker,
+                sentiment_port,
+                source: io::Error::new(source.kind(), source.to_string()),
+            },
+            BindError::MulticastTtl { source, .. } => BindError::MulticastTtl {
+                ticker,
+                sentiment_port,
+                source: io::Error::new(source.kind(), source.to_string()),
+            },
+            BindError::MulticastInterface {
+                interface, source, ..
+            } => BindError::MulticastInterface {
+                ticker,
+                sentiment_port,
+                interface: *interface,
+                source: io::Error::new(source.kind(), source.to_string()),
+            },
+        }
+    }
+
+    pub fn ticker(&self) -> &str {
+        match self {
+            BindError::Socket { ticker, .. }
+            | BindError::MulticastTtl { ticker, .. }
+            | BindError::MulticastInterface { ticker, .. } => ticker,
+        }
+    }
+
+    pub fn sentiment_port(&self) -> u16 {
+        match self {
+            BindError::Socket { sentiment_port, .. }
+            | BindError::MulticastTtl { sentiment_port, .. }
+            | BindError::MulticastInterface { sentiment_port, .. } => *sentiment_port,
+        }
+    }
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindError::Socket {
+                ticker,
+                sentiment_port,
+                source,
+            } => write!(
+                f,
+                "failed to create UDP socket for {} (port {}): {}",
+                ticker, sentiment_port, source
+            ),
+            BindError::MulticastTtl {
+                ticker,
+                sentiment_port,
+                source,
+            } => write!(
+                f,
+                "failed to set multicast TTL for {} (port {}): {}",
+                ticker, sentiment_port, source
+            ),
+            BindError::MulticastInterface {
+                ticker,
+                sentiment_port,
+                interface,
+                source,
+            } => write!(
+                f,
+                "failed to send multicast for {} (port {}) from interface {}: {}",
+                ticker, sentiment_port, interface, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BindError::Socket { source, .. }
+            | BindError::MulticastTtl { source, .. }
+            | BindError::MulticastInterface { source, .. } => Some(source),
+        }
+    }
+}
+
+/// One stock's broadcaster in a `HealthReport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcasterHealth {
+    pub stock_id: u64,
+    pub ticker: String,
+    /// Milliseconds since the Unix epoch of the last packet sent, if any.
+    pub last_success_ms: Option<u64>,
+    pub consecutive_failures: u64,
+}
+
+/// Returned by `SentimentService::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceStats {
+    /// Time since `start` or `start_async`; zero before either.
+    pub uptime: Duration,
+    /// Engine steps taken.
+    pub ticks: u64,
+    /// Sentiment packets sent, over every stock ever broadcast.
+    pub packets_sent: u64,
+    /// Uncompressed over sent bytes of the TCP and WebSocket frames'
+    /// payloads; 1.0 until a frame has been sent.
+    pub compression_ratio: f64,
+    /// One entry per current stock, in stock list order.
+    pub stocks: Vec<StockStats>,
+}
+
+/// One stock's share of `ServiceStats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StockStats {
+    pub stock_id: u64,
+    pub ticker: String,
+    pub packets_sent: u64,
+    /// Packets per second since the previous `stats` call, or since the
+    /// service started (or a reload added the stock) on the first one.
+    pub send_rate: f64,
+}
+
+/// Returned by `SentimentService::health`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    /// The service is running and its engine has ticked within the last
+    /// `HEARTBEAT_STALE_TICKS` tick intervals.
+    pub engine_alive: bool,
+    /// Times the engine thread panicked and was restarted.
+    pub engine_restarts: u64,
+    pub broadcasters: Vec<BroadcasterHealth>,
+    max_send_failures: u32,
+}
+
+impl HealthReport {
+    /// False when the engine is not alive or any broadcaster's last
+    /// `max_send_failures` sends all failed.
+    pub fn is_healthy(&self) -> bool {
+        let threshold = u64::from(self.max_send_failures.max(1));
+        self.engine_alive
+            && self
+                .broadcasters
+                .iter()
+                .all(|broadcaster| broadcaster.consecutive_failures < threshold)
+    }
+}
+
+/// Returned by `SentimentService::start`. Owns the service's worker threads
+/// and records which stocks' broadcasters came up.
+pub struct ServiceHandle {
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+    late_threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    bindings: Vec<Result<(), BindError>>,
+    metrics: Arc<Metrics>,
+    max_send_failures: u32,
+}
+
+impl ServiceHandle {
+    /// One entry per stock, in the order the stocks were configured.
+    pub fn bindings(&self) -> &[Result<(), BindError>] {
+        &self.bindings
+    }
+
+    /// Number of worker threads the service is running.
+    pub fn thread_count(&self) -> usize {
+        let late = self.late_threads.lock().map_or(0, |threads| threads.len());
+        self.threads.len() + late
+    }
+
+    /// The stocks whose broadcaster failed to start.
+    pub fn failed_bindings(&self) -> Vec<&BindError> {
+        self.bindings
+            .iter()
+            .filter_map(|binding| binding.as_ref().err())
+            .collect()
+    }
+
+    /// Sends for `stock_id` that have failed in a row; 0 once one succeeds.
+    pub fn consecutive_send_failures(&self, stock_id: u64) -> u64 {
+        self.metrics.send_failures(stock_id)
+    }
+
+    /// Ids of the stocks whose last `max_send_failures` sends all failed.
+    pub fn unhealthy_broadcasters(&self) -> Vec<u64> {
+        let threshold = u64::from(self.max_send_failures.max(1));
+        let Ok(failures) = self.metrics.send_failures.read() else {
+            return Vec::new();
+        };
+        let mut unhealthy: Vec<u64> = failures
+            .iter()
+            .filter(|(_, count)| count.load(Ordering::Relaxed) >= threshold)
+            .map(|(id, _)| *id)
+            .collect();
+        unhealthy.sort_unstable();
+        unhealthy
+    }
+
+    /// Signals every engine and broadcaster thread to exit. Threads notice
+    /// the flag within `SHUTDOWN_POLL_INTERVAL`; use `join` to wait for them.
+    pub fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops the service and blocks until all of its threads have exited.
+    pub fn join(self) {
+        self.stop();
+        let late = self
+            .late_threads
+            .lock()
+            .map(|mut threads| std::mem::take(&mut *threads))
+            .unwrap_or_default();
+        for handle in self.threads.into_iter().chain(late) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Returned by `SentimentService::start_async`. Owns the engine and
+/// broadcaster tasks.
+#[cfg(feature = "tokio")]
+pub struct AsyncServiceHandle {
+    shutdown: Arc<AtomicBool>,
+    stop_tx: tokio::sync::watch::Sender<bool>,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncServiceHandle {
+    /// Number of tasks the service is running.
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Signals the tasks to exit without waiting for them.
+    pub fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.stop_tx.send(true);
+    }
+
+    /// Stops the service and waits until all of its tasks have exited.
+    pub async fn shutdown(self) {
+        self.stop();
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Assembles a `SentimentService` from stocks added one at a time or
+/// loaded from CSV, in any mix. Load errors and duplicate ids or ports are
+/// reported by `build`.
+#[derive(Default)]
+pub struct SentimentServiceBuilder {
+    stocks: Vec<Stock>,
+    config: SentimentConfig,
+    per_stock: HashMap<u64, SentimentConfig>,
+    load_error: Option<LoadError>,
+}
+
+impl SentimentServiceBuilder {
+    pub fn add_stock(mut self, stock: Stock) -> Self {
+        self.stocks.push(stock);
+        self
+    }
+
+    /// Appends the stocks in `csv_path`, parsed as by `from_csv` with the
+    /// `csv_delimiter` of the config set so far.
+    pub fn stocks_from_csv(mut self, csv_path: &str) -> Self {
+        match read_stocks(csv_path, false, self.config.csv_delimiter) {
+            Ok(stocks) => self.stocks.extend(stocks),
+            Err(e) => {
+                self.load_error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    /// Replaces the service-wide config. Overrides set through
+    /// `per_stock_config` are kept regardless of call order.
+    pub fn config(mut self, config: SentimentConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn per_stock_config(mut self, stock_id: u64, config: SentimentConfig) -> Self {
+        self.per_stock.insert(stock_id, config);
+        self
+    }
+
+    pub fn build(self) -> Result<SentimentService, BuildError> {
+        if let Some(e) = self.load_error {
+            return Err(e.into());
+        }
+        validate_stocks(&self.stocks)?;
+
+        let mut config = self.config;
+        config.per_stock.extend(self.per_stock);
+        Ok(SentimentService::new(self.stocks, Some(config))?)
+    }
+}
+
+impl SentimentService {
+    pub fn builder() -> SentimentServiceBuilder {
+        SentimentServiceBuilder::default()
+    }
+
+    pub fn new(stocks: Vec<Stock>, config: Option<SentimentConfig>) -> Result<Self, ConfigError> {
+        let config = config.unwrap_or_default();
+        config.validate()?;
+        if stocks.is_empty() {
+            return Err(ConfigError::NoStocks);
+        }
+        config.check_stock_count(stocks.len())?;
+
+        let mut sentiments = HashMap::new();
+        for stock in &stocks {
+            sentiments.insert(stock.id, config.initial_sentiment(stock));
+        }
+
+        let prices = stocks
+            .iter()
+            .map(|stock| (stock.id, stock.initial_price))
+            .collect();
+        let sector_moods = stocks
+            .iter()
+            .filter_map(|stock| Some((stock.sector.clone()?, 0.0)))
+            .collect();
+
+        Ok(Self {
+            sentiments: Arc::new(Sentiments::new(sentiments)),
+            market_mood: Arc::new(RwLock::new(config.mean)),
+            sector_moods: Arc::new(RwLock::new(sector_moods)),
+            markov_regime: Arc::new(RwLock::new(Regime::Bullish)),
+            prices: Arc::new(RwLock::new(prices)),
+            history: Arc::new(TickHistory::new(config.backfill_ticks)),
+            interpolation: config
+                .interpolate
+                .then(|| Arc::new(Interpolation::new(config.tick_interval))),
+            config,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            correlation: None,
+            callbacks: Arc::new(Vec::new()),
+            subscribers: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(Metrics::default()),
+            recorder: Arc::new(Mutex::new(None)),
+            replay: None,
+            started: AtomicBool::new(false),
+            started_at: OnceLock::new(),
+            rate_samples: Mutex::new(HashMap::new()),
+            engine_started: AtomicBool::new(false),
+            broadcasters: Arc::new(Mutex::new(HashMap::new())),
+            sinks: Vec::new(),
+            bus: InProcessBus::new(),
+            subscriptions: Arc::default(),
+            news_subscriptions: Arc::default(),
+            late_threads: Arc::new(Mutex::new(Vec::new())),
+            ticker_index: RwLock::new(ticker_index(&stocks)),
+            stocks: Arc::new(RwLock::new(Arc::new(stocks))),
+        })
+    }
+
+    /// Registers `cb` to be called with `(stock_id, sentiment)` for every
+    /// stock on every engine tick. Callbacks run on the engine thread after
+    /// the tick is published and the mood lock released, so they must be
+    /// fast: a slow callback delays the next tick. Only callbacks registered
+    /// before `start` are seen by the running engine.
+    pub fn on_update(&mut self, cb: impl Fn(u64, f64) + Send + Sync + 'static) {
+        Arc::make_mut(&mut self.callbacks).push(Arc::new(cb));
+    }
+
+    /// The bus `Transport::InProcess` broadcasts to. Subscribe to it before
+    /// `start` to see every packet.
+    pub fn bus(&self) -> &InProcessBus {
+        &self.bus
+    }
+
+    /// Sends every sentiment broadcast to `sink` as well as over the
+    /// configured transports. The sink is shared by all stocks'
+    /// broadcasters. Only sinks added before `start` are used.
+    pub fn add_sink(&mut self, sink: impl Sink) {
+        self.sinks.push(Arc::new(sink));
+    }
+
+    /// Registers `cb` to be called with `(stock_id, sentiment, direction)`
+    /// when `stock_id`'s sentiment crosses `level`, in the sentiment range.
+    /// A crossing only counts once the sentiment is `THRESHOLD_HYSTERESIS`
+    /// past the level, so it doesn't fire again until the sentiment has
+    /// gone back through the whole band. Runs as an `on_update` callback.
+    pub fn on_threshold_cross(
+        &mut self,
+        stock_id: u64,
+        level: f64,
+        cb: impl Fn(u64, f64, CrossDirection) + Send + Sync + 'static,
+    ) {
+        let watch = Mutex::new(ThresholdWatch {
+            level,
+            band: THRESHOLD_HYSTERESIS * self.config.half_width(),
+            above: None,
+        });
+        self.on_update(move |id, value| {
+            if id != stock_id {
+                return;
+            }
+            let crossed = watch.lock().ok().and_then(|mut watch| watch.observe(value));
+            if let Some(direction) = crossed {
+                cb(id, value, direction);
+            }
+        });
+    }
+
+    /// Correlates the per-stock noise using pairwise coefficients
+    /// `(stock_a, stock_b, rho)`. Unlisted pairs are uncorrelated. The
+    /// resulting matrix is Cholesky-decomposed once here and must be
+    /// positive semidefinite.
+    pub fn with_correlations(
+        mut self,
+        correlations: Vec<(u64, u64, f64)>,
+    ) -> Result<Self, CorrelationError> {
+        let stocks = self.stocks();
+        let index: HashMap<u64, usize> = stocks
+            .iter()
+            .enumerate()
+            .map(|(i, stock)| (stock.id, i))
+            .collect();
+
+        let n = stocks.len();
+        let mut matrix = vec![vec![0.0; n]; n];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        for (a, b, coefficient) in correlations {
+            let i = *index.get(&a).ok_or(CorrelationError::UnknownStock(a))?;
+            let j = *index.get(&b).ok_or(CorrelationError::UnknownStock(b))?;
+            if i == j || !(-1.0..=1.0).contains(&coefficient) {
+                return Err(CorrelationError::InvalidCoefficient { a, b, coefficient });
+            }
+            matrix[i][j] = coefficient;
+            matrix[j][i] = coefficient;
+        }
+
+        self.correlation = Some(Correlation {
+            ids: stocks.iter().map(|stock| stock.id).collect(),
+            factor: cholesky(&matrix)?,
+        });
+        Ok(self)
+    }
+
+    /// Loads the stock list from `csv_path`. Any malformed row, or a row
+    /// reusing an earlier row's `id` or `sentiment_port`, fails the load
+    /// with a `CsvLoadError` listing every bad row.
+    pub fn from_csv(
+        csv_path: &str,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let delimiter = config.as_ref().map_or(',', |config| config.csv_delimiter);
+        let stocks = read_stocks(csv_path, false, delimiter)?;
+        Ok(Self::new(stocks, config)?)
+    }
+
+    /// Like `from_csv`, but reads the CSV from `reader`, e.g. stdin.
+    pub fn from_csv_reader(
+        reader: impl Read,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let delimiter = config.as_ref().map_or(',', |config| config.csv_delimiter);
+        let stocks = parse_stocks(reader, READER_SOURCE, false, delimiter)?;
+        Ok(Self::new(stocks, config)?)
+    }
+
+    /// Like `from_csv`, but bad rows are logged and skipped instead of
+    /// failing the load.
+    pub fn from_csv_lenient(
+        csv_path: &str,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let delimiter = config.as_ref().map_or(',', |config| config.csv_delimiter);
+        let stocks = read_stocks(csv_path, true, delimiter)?;
+        Ok(Self::new(stocks, config)?)
+    }
+
+    /// Loads the stock list from a JSON array of `Stock` objects, with the
+    /// same duplicate id and port checks as `from_csv`.
+    pub fn from_json(
+        json_path: &str,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let stocks = read_stocks_json(json_path)?;
+        Ok(Self::new(stocks, config)?)
+    }
+
+    /// Like `from_json`, but reads the JSON from `reader`, e.g. stdin.
+    pub fn from_json_reader(
+        reader: impl Read,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let stocks = parse_stocks_json(reader, READER_SOURCE)?;
+        Ok(Self::new(stocks, config)?)
+    }
+
+    /// Loads the stock list from a file with one JSON `Stock` per line.
+    /// Blank lines are ignored.
+    pub fn from_jsonl(
+        jsonl_path: &str,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let stocks = read_stocks_jsonl(jsonl_path)?;
+        Ok(Self::new(stocks, config)?)
+    }
+
+    /// The stocks currently being served.
+    pub fn stocks(&self) -> Arc<Vec<Stock>> {
+        current_stocks(&self.stocks)
+    }
+
+    /// The served stock with id `id`. Returned by value, since
+    /// `reload_stocks` may replace the list at any time.
+    pub fn stock_by_id(&self, id: u64) -> Option<Stock> {
+        self.stocks().iter().find(|stock| stock.id == id).cloned()
+    }
+
+    /// The served stock with ticker `ticker`, matched case-insensitively.
+    pub fn stock_by_ticker(&self, ticker: &str) -> Option<Stock> {
+        let id = *self.ticker_index.read().ok()?.get(&ticker.to_uppercase())?;
+        self.stock_by_id(id)
+    }
+
+    /// Re-reads `csv_path` and switches the running service over to it.
+    /// Stocks are matched by id. New stocks get a sentiment entry and, if
+    /// the service is running, a broadcaster; removed stocks lose both. The
+    /// shared broadcaster and stream servers pick up the new list on their
+    /// next cycle. Pairwise correlations stop applying once the set of
+    /// stocks changes.
+    pub fn reload_stocks(&self, csv_path: &str) -> Result<StockDiff, Box<dyn std::error::Error>> {
+        let new_stocks = read_stocks(csv_path, false, self.config.csv_delimiter)?;
+        self.config.check_stock_count(new_stocks.len())?;
+
+        // Holding the list's write lock serialises concurrent reloads
+        let mut list = self.stocks.write().unwrap();
+        let mut diff = StockDiff::default();
+        for stock in list.iter() {
+            match new_stocks.iter().find(|new| new.id == stock.id) {
+                Some(new) if new == stock => diff.unchanged.push(stock.clone()),
+                _ => diff.removed.push(stock.clone()),
+            }
+        }
+        for stock in &new_stocks {
+            if !diff.unchanged.iter().any(|same| same.id == stock.id) {
+                diff.added.push(stock.clone());
+            }
+        }
+
+        self.sentiments.update(|sentiments| {
+            sentiments.retain(|id, _| new_stocks.iter().any(|stock| stock.id == *id));
+            for stock in &diff.added {
+                sentiments
+                    .entry(stock.id)
+                    .or_insert(self.config.initial_sentiment(stock));
+            }
+        });
+        if let Ok(mut prices) = self.prices.write() {
+            prices.retain(|id, _| new_stocks.iter().any(|stock| stock.id == *id));
+            for stock in &diff.added {
+                prices.entry(stock.id).or_insert(stock.initial_price);
+            }
+        }
+        if let Ok(mut index) = self.ticker_index.write() {
+            *index = ticker_index(&new_stocks);
+        }
+        let has_stocks = !new_stocks.is_empty();
+        *list = Arc::new(new_stocks);
+        drop(list);
+
+        if has_stocks && self.started.load(Ordering::SeqCst) {
+            if let Some(handle) = self.start_engine() {
+                if let Ok(mut threads) = self.late_threads.lock() {
+                    threads.push(handle);
+                }
+            }
+        }
+
+        if let Ok(mut broadcasters) = self.broadcasters.lock() {
+            for stock in &diff.removed {
+                if let Some(stopped) = broadcasters.remove(&stock.id) {
+                    stopped.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+        if let Ok(mut samples) = self.rate_samples.lock() {
+            for stock in &diff.removed {
+                samples.remove(&stock.id);
+            }
+            if self.started_at.get().is_some() {
+                let now = Instant::now();
+                for stock in &diff.added {
+                    samples.insert(stock.id, (self.metrics.packets_sent(stock.id), now));
+                }
+            }
+        }
+        if self.started.load(Ordering::SeqCst)
+            && self.config.enable_broadcast
+            && self.config.broadcast_mode == BroadcastMode::PerStock
+            && !self.config.batch
+        {
+            for stock in &diff.added {
+                match self.broadcast_sink(stock) {
+                    Ok(sink) => {
+                        let handle = self.start_udp_broadcaster(stock.clone(), sink);
+                        if let Ok(mut threads) = self.late_threads.lock() {
+                            threads.push(handle);
+                        }
+                    }
+                    Err(e) => {
+                        error!("[FAIL] {}", e);
+                        self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        info!(
+            "Reloaded stocks from {}: {} added, {} removed, {} unchanged",
+            csv_path,
+            diff.added.len(),
+            diff.removed.len(),
+            diff.unchanged.len()
+        );
+        Ok(diff)
+    }
+
+    /// Builds a service that replays a file written by `start_recording`
+    /// instead of generating sentiment. Samples are re-emitted in file order
+    /// with their original relative timing, through the same sentiment map
+    /// and `on_update` callbacks the engine would use; once the recording
+    /// runs out the last values stay put. A recording only holds stock ids,
+    /// so `stocks` supplies the tickers and ports to broadcast on; samples
+    /// for other ids are ignored.
+    pub fn from_recording(
+        stocks: Vec<Stock>,
+        path: &Path,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let replay = ReplayConfig {
+            path: path.to_path_buf(),
+            speed: 1.0,
+        };
+        Self::replay(stocks, replay, config)
+    }
+
+    /// Like `from_recording`, but plays the recording back at
+    /// `replay.speed` times its original rate.
+    pub fn replay(
+        stocks: Vec<Stock>,
+        replay: ReplayConfig,
+        config: Option<SentimentConfig>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if replay.speed.is_nan() || replay.speed <= 0.0 {
+            return Err(ConfigError::InvalidReplaySpeed(replay.speed).into());
+        }
+        let speed = replay
+            .speed
+            .clamp(*REPLAY_SPEED_RANGE.start(), *REPLAY_SPEED_RANGE.end());
+        let path = replay.path;
+
+        let mut reader = csv::Reader::from_path(&path)?;
+        let mut samples = Vec::new();
+        for result in reader.deserialize() {
+            let sample: RecordedSample = result?;
+            if stocks.iter().any(|stock| stock.id == sample.stock_id) {
+                samples.push(sample);
+            }
+        }
+
+        info!(
+            "Loaded {} recorded samples from {}",
+            samples.len(),
+            path.display()
+        );
+        let mut service = Self::new(stocks, config)?;
+        service.replay = Some(Replay {
+            samples: Arc::new(samples),
+            speed,
+        });
+        Ok(service)
+    }
+
+    /// Appends every stock's value to `path` as CSV rows of
+    /// `timestamp_ms,stock_id,value` after each engine tick, from now on.
+    /// The header is only written when the file is new or empty. Replaces
+    /// any recording already in progress.
+    pub fn start_recording(&self, path: &Path) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        let writer = csv::WriterBuilder::new()
+            .has_headers(is_empty)
+            .from_writer(file);
+
+        if let Ok(mut recorder) = self.recorder.lock() {
+            *recorder = Some(writer);
+        }
+        info!("[OK] Recording sentiment to {}", path.display());
+        Ok(())
+    }
+
+    pub fn start(&self) -> ServiceHandle {
+        let stocks = self.stocks();
+        info!(
+            "Sentiment microservice starting for {} stocks",
+            stocks.len()
+        );
+        self.started.store(true, Ordering::SeqCst);
+        self.started_at.get_or_init(Instant::now);
+
+        let mut threads = Vec::new();
+        let mut bindings = Vec::new();
+
+        if stocks.is_empty() {
+            warn!("No stocks to simulate; the engine starts once a reload adds some");
+        } else {
+            threads.extend(self.start_engine());
+        }
+
+        match self.config.broadcast_mode {
+            _ if !self.config.enable_broadcast => {
+                info!("Broadcasting disabled, running the engine only");
+            }
+            _ if self.config.batch => match self.start_batch_broadcaster() {
+                Ok(Some(handle)) => {
+                    threads.push(handle);
+                    bindings.extend(stocks.iter().map(|_| Ok(())));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("[FAIL] {}", e);
+                    self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                    bindings.extend(stocks.iter().map(|stock| Err(e.for_stock(stock))));
+                }
+            },
+            // Start UDP broadcasters for each stock
+            BroadcastMode::PerStock => {
+                for stock in stocks.iter() {
+                    match self.broadcast_sink(stock) {
+                        Ok(sink) => {
+                            threads.push(self.start_udp_broadcaster(stock.clone(), sink));
+                            bindings.push(Ok(()));
+                        }
+                        Err(e) => {
+                            error!("[FAIL] {}", e);
+                            self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                            bindings.push(Err(e));
+                        }
+                    }
+                }
+            }
+            BroadcastMode::Shared => match self.start_shared_broadcaster() {
+                Ok(Some(handle)) => {
+                    threads.push(handle);
+                    bindings.extend(stocks.iter().map(|_| Ok(())));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("[FAIL] {}", e);
+                    self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                    bindings.extend(stocks.iter().map(|stock| Err(e.for_stock(stock))));
+                }
+            },
+        }
+
+        if let Some(port) = self
+            .config
+            .index_port
+            .filter(|_| self.config.enable_broadcast)
+        {
+            match self.start_index_broadcaster(port) {
+                Ok(handle) => threads.push(handle),
+                Err(e) => {
+                    error!("[FAIL] {}", e);
+                    self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        if let Some(port) = self
+            .config
+            .events_port
+            .filter(|_| self.config.enable_broadcast)
+        {
+            match self.start_events_broadcaster(port) {
+                Ok(handle) => threads.push(handle),
+                Err(e) => {
+                    error!("[FAIL] {}", e);
+                    self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        if self.config.enable_broadcast && self.config.broadcast_price {
+            match self.start_price_broadcaster() {
+                Ok(Some(handle)) => threads.push(handle),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("[FAIL] {}", e);
+                    self.metrics.bind_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        ServiceHandle {
+            shutdown: Arc::clone(&self.shutdown),
+            threads,
+            late_threads: Arc::clone(&self.late_threads),
+            bindings,
+            metrics: Arc::clone(&self.metrics),
+            max_send_failures: self.config.max_send_failures,
+        }
+    }
+
+    /// How many threads `start` would run for the current stocks: the
+    /// engine, the sentiment broadcasters and any index, events and price
+    /// broadcasters. Stream and metrics servers are not counted.
+    pub fn estimated_thread_count(&self) -> usize {
+        let stocks = self.stocks().len();
+        let any_stocks = usize::from(stocks > 0);
+        if !self.config.enable_broadcast {
+            return any_stocks;
+        }
+        let broadcasters = match self.config.broadcast_mode {
+            _ if self.config.batch => any_stocks,
+            BroadcastMode::PerStock => stocks,
+            BroadcastMode::Shared => any_stocks,
+        };
+        let extras = [
+            self.config.index_port.is_some(),
+            self.config.events_port.is_some(),
+            self.config.broadcast_price && stocks > 0,
+        ]
+        .into_iter()
+        .filter(|&running| running)
+        .count();
+        any_stocks + broadcasters + extras
+    }
+
+    /// Signals every engine and broadcaster thread to exit. Use
+    /// `ServiceHandle::join` to wait for them.
+    pub fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    /// Freezes the mood and every sentiment from the next tick on.
+    /// Broadcasters and stream servers keep sending the frozen values.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Lets the engine take steps again after `pause`.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Starts the sentiment update engine, or replays a recording instead,
+    /// unless one is already running.
+    fn start_engine(&self) -> Option<JoinHandle<()>> {
+        if self.engine_started.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(match &self.replay {
+            Some(replay) => self.start_replay(replay.clone()),
+            None => self.start_sentiment_engine(),
+        })
+    }
+
+    /// Runs the engine on its own thread under a watchdog thread, which is
+    /// the one returned. The engine loop only ends on shutdown, so if its
+    /// thread finishes earlier it has panicked, and the watchdog starts a
+    /// replacement from `SentimentEngine::respawn`. An engine that hangs
+    /// can't be replaced; `health` reports it dead once its heartbeat is
+    /// stale.
+    fn start_sentiment_engine(&self) -> JoinHandle<()> {
+        let mut template = self.engine();
+        let tick_interval = self.config.tick_interval;
+        let shutdown = Arc::clone(&self.shutdown);
+        let paused = Arc::clone(&self.paused);
+        let metrics = Arc::clone(&self.metrics);
+        let recorder = Arc::clone(&self.recorder);
+        let sentiments = Arc::clone(&self.sentiments);
+        let stocks = Arc::clone(&self.stocks);
+
+        let spawn_engine = {
+            let shutdown = Arc::clone(&shutdown);
+            let metrics = Arc::clone(&metrics);
+            move |mut engine: SentimentEngine| {
+                let shutdown = Arc::clone(&shutdown);
+                let paused = Arc::clone(&paused);
+                let metrics = Arc::clone(&metrics);
+                let recorder = Arc::clone(&recorder);
+                let sentiments = Arc::clone(&sentiments);
+                let stocks = Arc::clone(&stocks);
+                thread::spawn(move || {
+                    while sleep_unless_shutdown(&shutdown, tick_interval) {
+                        metrics.heartbeat();
+                        if paused.load(Ordering::SeqCst) {
+                            continue;
+                        }
+                        metrics.tick_started();
+                        engine.step();
+                        metrics.engine_ticks.fetch_add(1, Ordering::Relaxed);
+                        record_tick(&recorder, &current_stocks(&stocks), &sentiments);
+                    }
+                })
+            }
+        };
+
+        thread::spawn(move || {
+            let mut running = spawn_engine(template.clone());
+            while sleep_unless_shutdown(&shutdown, tick_interval) {
+                if !running.is_finished() {
+                    continue;
+                }
+                let _ = running.join();
+                metrics.engine_restarts.fetch_add(1, Ordering::Relaxed);
+                error!("[FAIL] Engine thread panicked; restarting it");
+                running = spawn_engine(template.respawn());
+            }
+            let _ = running.join();
+        })
+    }
+
+    /// Feeds recorded samples into the sentiment map at their offsets from
+    /// the first sample, divided by the replay speed, then exits.
+    fn start_replay(&self, replay: Replay) -> JoinHandle<()> {
+        let sentiments = Arc::clone(&self.sentiments);
+        let callbacks = Arc::clone(&self.callbacks);
+        let shutdown = Arc::clone(&self.shutdown);
+
+        thread::spawn(move || {
+            let Some(first) = replay.samples.first() else {
+                return;
+            };
+            let started = Instant::now();
+            for sample in replay.samples.iter() {
+                let offset_ms = sample.timestamp_ms.saturating_sub(first.timestamp_ms);
+                let due = Duration::from_secs_f64(offset_ms as f64 / 1000.0 / replay.speed);
+                let wait = due.saturating_sub(started.elapsed());
+                if !wait.is_zero() && !sleep_unless_shutdown(&shutdown, wait) {
+                    return;
+                }
+                if shutdown.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                sentiments.update(|map| {
+                    map.insert(sample.stock_id, sample.value);
+                });
+                for callback in callbacks.iter() {
+                    callback(sample.stock_id, sample.value);
+                }
+            }
+        })
+    }
+
+    /// Engine steps broadcasters wait for. A replay has no engine, so it
+    /// never warms up.
+    fn warmup_ticks(&self) -> u64 {
+        if self.replay.is_some() {
+            0
+        } else {
+            self.config.warmup_ticks as u64
+        }
+    }
+
+    fn engine(&self) -> SentimentEngine {
+        SentimentEngine::new(self)
+    }
+
+    /// Returns a receiver that gets a `SentimentPacket` per stock on every
+    /// engine tick from now on, for embedding the service without
+    /// callbacks or sockets. Each call gets its own receiver. A receiver
+    /// holds up to `SUBSCRIBER_QUEUE_CAPACITY` packets; further packets for
+    /// it are dropped until it is drained. Dropping the receiver
+    /// unsubscribes it.
+    pub fn subscribe(&self) -> mpsc::Receiver<SentimentPacket> {
+        self.add_subscription(None)
+    }
+
+    /// Like `subscribe`, but only forwards each stock's latest value once
+    /// per `interval`: the tick that falls due is sent and the ticks in
+    /// between are skipped. The first tick after subscribing is sent.
+    pub fn subscribe_every(&self, interval: Duration) -> mpsc::Receiver<SentimentPacket> {
+        self.add_subscription(Some(interval))
+    }
+
+    /// Returns a receiver that gets every news event the engine generates
+    /// from now on, after its shock has been applied. Queues and drops like
+    /// `subscribe`.
+    pub fn subscribe_news(&self) -> mpsc::Receiver<NewsEvent> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE_CAPACITY);
+        if let Ok(mut subscriptions) = self.news_subscriptions.lock() {
+            subscriptions.push(tx);
+        }
+        rx
+    }
+
+    fn add_subscription(&self, every: Option<Duration>) -> mpsc::Receiver<SentimentPacket> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE_CAPACITY);
+        if let Ok(mut subscriptions) = self.subscriptions.lock() {
+            subscriptions.push(Subscription {
+                tx,
+                every,
+                due: Instant::now(),
+            });
+        }
+        rx
+    }
+
+    /// Registers `addr` to receive unicast copies of `stock_id`'s packets
+    /// from now on, in addition to the configured transport. Takes effect
+    /// on running broadcasters.
+    pub fn add_subscriber(&self, stock_id: u64, addr: SocketAddr) {
+        if let Ok(mut subscribers) = self.subscribers.write() {
+            subscribers.entry(stock_id).or_default().push(addr);
+        }
+    }
+
+    fn destinations(&self) -> Destinations {
+        Destinations {
+            transport: self.config.transport.clone(),
+            subscribers: Arc::clone(&self.subscribers),
+        }
+    }
+
+    /// Everything a sentiment broadcast goes to: a `UdpSink` per configured
+    /// transport, bound on behalf of `stock`, and the `add_sink` sinks.
+    /// Subscribers from `add_subscriber` are reached through the primary
+    /// transport's sink only, so they get one copy.
+    fn broadcast_sink(&self, stock: &Stock) -> Result<FanOut, BindError> {
+        let mut sinks: Vec<Arc<dyn Sink>> = Vec::new();
+        for (i, transport) in self.config.transports().enumerate() {
+            if *transport == Transport::InProcess {
+                sinks.push(Arc::new(self.bus.clone()));
+                continue;
+            }
+            let socket = open_broadcast_socket(stock, transport, &self.config)?;
+            let destinations = if i == 0 {
+                self.destinations()
+            } else {
+                Destinations {
+                    transport: transport.clone(),
+                    subscribers: Arc::default(),
+                }
+            };
+            sinks.push(Arc::new(UdpSink::new(
+                socket,
+                destinations,
+                Arc::clone(&self.metrics),
+                self.config.clone(),
+            )));
+        }
+        sinks.extend(self.sinks.iter().cloned());
+        Ok(FanOut(sinks))
+    }
+
+    /// Sends `stock`'s value through `sink` whenever it changes, checking
+    /// once per `broadcast_interval`, until the service stops or the stock
+    /// is removed.
+    fn start_udp_broadcaster(&self, stock: Stock, sink: impl Sink) -> JoinHandle<()> {
+        info!(
+            "[OK] {} broadcasting to {} ticker={} port={}",
+            stock.company_name, self.config.transport, stock.ticker, stock.sentiment_port
+        );
+        let sentiments = Arc::clone(&self.sentiments);
+        let shutdown = Arc::clone(&self.shutdown);
+        let wire_format = self.config.wire_format;
+        let broadcast_interval = self.config.broadcast_interval;
+        let metrics = Arc::clone(&self.metrics);
+        let config = self.config.clone();
+        let warmup_ticks = self.warmup_ticks();
+        let regime = self.regime_source();
+        let interpolation = self.interpolation.clone();
+        let phase = self.config.phase(stock.id);
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        if let Ok(mut broadcasters) = self.broadcasters.lock() {
+            broadcasters.insert(stock.id, Arc::clone(&stopped));
+        }
+
+        thread::spawn(move || {
+            let mut last_sent = None;
+            let mut outgoing = Outgoing::new(&stock);
+            while !shutdown.load(Ordering::SeqCst) && !stopped.load(Ordering::SeqCst) {
+                if !metrics.warmed_up(warmup_ticks) {
+                    sleep_unless_shutdown(&shutdown, broadcast_interval);
+                    continue;
+                }
+                let sentiment = interpolated(
+                    interpolation.as_deref(),
+                    stock.id,
+                    sentiments.get(stock.id).unwrap_or(0.0),
+                );
+
+                if last_sent != Some(sentiment) && metrics.past_phase(phase) {
+                    let value = config.output_value(sentiment);
+                    outgoing.next(value, Some(regime()), wire_format);
+                    let (packet, message) = outgoing.last();
+                    sink.send_packet(&stock, packet, message);
+                    last_sent = Some(sentiment);
+                }
+
+                sleep_unless_shutdown(&shutdown, broadcast_interval);
+            }
+        })
+    }
+
+    /// Starts the single broadcaster used by `BroadcastMode::Shared`.
+    /// Returns `Ok(None)` when there are no stocks to broadcast.
+    fn start_shared_broadcaster(&self) -> Result<Option<JoinHandle<()>>, BindError> {
+        let initial = self.stocks();
+        let Some(first) = initial.first() else {
+            return Ok(None);
+        };
+        let sink = self.broadcast_sink(first)?;
+        info!(
+            "[OK] {} stocks broadcasting to {} from one socket",
+            initial.len(),
+            self.config.transport
+        );
+        let interpolation = self.interpolation.clone();
+
+        let sentiments = Arc::clone(&self.sentiments);
+        let shutdown = Arc::clone(&self.shutdown);
+        let wire_format = self.config.wire_format;
+        let broadcast_interval = self.config.broadcast_interval;
+        let metrics = Arc::clone(&self.metrics);
+        let stocks = Arc::clone(&self.stocks);
+        let config = self.config.clone();
+        let warmup_ticks = self.warmup_ticks();
+        let regime = self.regime_source();
+
+        Ok(Some(thread::spawn(move || {
+            let mut last_sent = HashMap::new();
+            let mut outgoing: HashMap<u64, Outgoing> = HashMap::new();
+            while !shutdown.load(Ordering::SeqCst) {
+                if !metrics.warmed_up(warmup_ticks) {
+                    sleep_unless_shutdown(&shutdown, broadcast_interval);
+                    continue;
+                }
+                // One snapshot per cycle for every stock
+                let stocks = current_stocks(&stocks);
+                let snapshot = sentiments.load();
+                let regime = Some(regime());
+
+                for stock in stocks.iter() {
+                    let sentiment = interpolated(
+                        interpolation.as_deref(),
+                        stock.id,
+                        snapshot.get(&stock.id).copied().unwrap_or(0.0),
+                    );
+                    if !metrics.past_phase(config.phase(stock.id))
+                        || last_sent.insert(stock.id, sentiment) == Some(sentiment)
+                    {
+                        continue;
+                    }
+                    let outgoing = outgoing
+                        .entry(stock.id)
+                        .or_insert_with(|| Outgoing::new(stock));
+                    outgoing.next(config.output_value(sentiment), regime, wire_format);
+                    let (packet, message) = outgoing.last();
+                    sink.send_packet(stock, packet, message);
+                }
+
+                sleep_unless_shutdown(&shutdown, broadcast_interval);
+            }
+        })))
+    }
+
+    /// Starts one broadcaster packing every stock's value into
+    /// `SentimentBatch` datagrams on `batch_port` whenever any of them
+    /// changes, split so none is over `wire::MAX_BATCH_DATAGRAM_LEN` bytes.
+    /// Each sample sent counts as a packet for its stock. Returns
+    /// `Ok(None)` when there are no stocks to broadcast.
+    fn start_batch_broadcaster(&self) -> Result<Option<JoinHandle<()>>, BindError> {
+        let initial = self.stocks();
+        if initial.is_empty() {
+            return Ok(None);
+        }
+        let batch_stock = Stock {
+            ticker: BATCH_TICKER.to_string(),
+            id: BATCH_STOCK_ID,
+            company_name: "Sentiment batches".to_string(),
+            total_float: 0,
+            initial_price: 0.0,
+            sentiment_port: self.config.batch_port,
+            initial_sentiment: None,
+            sector: None,
+        };
+        let mut socket = open_broadcast_socket(&batch_stock, &self.config.transport, &self.config)?;
+        info!(
+            "[OK] {} stocks broadcasting in batches to {} port={}",
+            initial.len(),
+            self.config.transport,
+            self.config.batch_port
+        );
+
+        let sentiments = Arc::clone(&self.sentiments);
+        let shutdown = Arc::clone(&self.shutdown);
+        let broadcast_interval = self.config.broadcast_interval;
+        let destinations = self.destinations();
+        let metrics = Arc::clone(&self.metrics);
+        let stocks = Arc::clone(&self.stocks);
+        let config = self.config.clone();
+        let warmup_ticks = self.warmup_ticks();
+
+        Ok(Some(thread::spawn(move || {
+            // track_send counts against the batch's own id here; every
+            // stock in a datagram gets its outcome on the service metrics
+            let batch_metrics = Metrics::default();
+            let mut last_sent = Vec::new();
+            let mut addrs = Vec::new();
+            let mut errors = SendErrors::default();
+            while !shutdown.load(Ordering::SeqCst) {
+                if !metrics.warmed_up(warmup_ticks) {
+                    sleep_unless_shutdown(&shutdown, broadcast_interval);
+                    continue;
+                }
+                let snapshot = sentiments.load();
+                let samples: Vec<(u64, f64)> = current_stocks(&stocks)
+                    .iter()
+                    .map(|stock| {
+                        let value = snapshot.get(&stock.id).copied().unwrap_or(0.0);
+                        (stock.id, config.output_value(value))
+                    })
+                    .collect();
+
+                if samples != last_sent {
+                    let batch = SentimentBatch {
+                        timestamp_ms: wire::now_ms(),
+                        samples,
+                    };
+                    destinations.fill(&batch_stock, &mut addrs);
+                    for part in batch.split(wire::MAX_BATCH_DATAGRAM_LEN) {
+                        let message = part.encode();
+                        for &addr in &addrs {
+                            let result = socket.send_to(&message, addr);
+                            let sent = result.is_ok();
+                            let rebind = track_send(
+                                &batch_metrics,
+                                &mut errors,
+                                &batch_stock,
+                                addr,
+                                result,
+                                &config,
+                            );
+                            for &(stock_id, _) in &part.samples {
+                                if sent {
+                                    metrics.packet_sent(stock_id);
+                                }
+                                metrics.set_send_failures(stock_id, errors.consecutive);
+                            }
+                            if rebind {
+                                socket = rebind_broadcast_socket(
+                                    &batch_stock,
+                                    &config.transport,
+                                    &config,
+                                )
+                                .unwrap_or(socket);
+                            }
+                        }
+                    }
+                    last_sent = batch.samples;
+                }
+
+                sleep_unless_shutdown(&shutdown, broadcast_interval);
+            }
+        })))
+    }
+
+    /// Starts one broadcaster sending every stock's price, as a
+    /// `SentimentPacket` whose value is the price, on its sentiment port
+    /// plus `PRICE_PORT_OFFSET`. Stocks whose price port would overflow are
+    /// skipped. Price sends don't count toward the sentiment metrics.
+    /// Returns `Ok(None)` when there are no stocks to broadcast.
+    fn start_price_broadcaster(&self) -> Result<Option<JoinHandle<()>>, BindError> {
+        let initial = self.stocks();
+        let Some(first) = initial.first() else {
+            return Ok(None);
+        };
+        let mut socket = open_broadcast_socket(first, &self.config.transport, &self.config)?;
+        info!(
+            "[OK] {} stock prices broadcasting to {} on port offset {}",
+            initial.len(),
+            self.config.transport,
+            PRICE_PORT_OFFSET
+        );
+
+        let prices = Arc::clone(&self.prices);
+        let shutdown = Arc::clone(&self.shutdown);
+        let wire_format = self.config.wire_format;
+        let broadcast_interval = self.config.broadcast_interval;
+        let destinations = self.destinations();
+        let metrics = Arc::clone(&self.metrics);
+        let stocks = Arc::clone(&self.stocks);
+        let config = self.config.clone();
+        let warmup_ticks = self.warmup_ticks();
+        let regime = self.regime_source();
+
+        Ok(Some(thread::spawn(move || {
+            let price_metrics = Metrics::default();
+            let mut last_sent = HashMap::new();
+            let mut outgoing: HashMap<u64, Outgoing> = HashMap::new();
+            let mut addrs = Vec::new();
+            let mut errors: HashMap<u64, SendErrors> = HashMap::new();
+            while !shutdown.load(Ordering::SeqCst) {
+                if !metrics.warmed_up(warmup_ticks) {
+                    sleep_unless_shutdown(&shutdown, broadcast_interval);
+                    continue;
+                }
+                let stocks = current_stocks(&stocks);
+                let snapshot = prices.read().map(|map| map.clone()).unwrap_or_default();
+                let regime = Some(regime());
+
+                for stock in stocks.iter() {
+                    let Some(&price) = snapshot.get(&stock.id) else {
+                        continue;
+                    };
+                    let Some(port) = stock.sentiment_port.checked_add(PRICE_PORT_OFFSET) else {
+                        continue;
+                    };
+                    if last_sent.insert(stock.id, price) == Some(price) {
+                        continue;
+                    }
+                    let price_stock = Stock {
+                        sentiment_port: port,
+                        ..stock.clone()
+                    };
+                    let message = outgoing
+                        .entry(stock.id)
+                        .or_insert_with(|| Outgoing::new(stock))
+                        .next(price, regime, wire_format);
+                    let errors = errors.entry(stock.id).or_default();
+                    destinations.fill(&price_stock, &mut addrs);
+                    for &addr in &addrs {
+                        let result = socket.send_to(message, addr);
+                        if track_send(&price_metrics, errors, stock, addr, result, &config) {
+                            socket = rebind_broadcast_socket(stock, &config.transport, &config)
+                                .unwrap_or(socket);
+                        }
+                    }
+                }
+
+                sleep_unless_shutdown(&shutdown, broadcast_interval);
+            }
+        })))
+    }
+
+    /// Starts one broadcaster sending the market index on `port` whenever it
+    /// changes. Like prices, index sends don't count toward the sentiment
+    /// metrics.
+    fn start_index_broadcaster(&self, port: u16) -> Result<JoinHandle<()>, BindError> {
+        let index = Stock {
+            ticker: INDEX_TICKER.to_string(),
+            id: INDEX_STOCK_ID,
+            company_name: "Market index".to_string(),
+            total_float: 0,
+            initial_price: 0.0,
+            sentiment_port: port,
+            initial_sentiment: None,
+            sector: None,
+        };
+        let mut socket = open_broadcast_socket(&index, &self.config.transport, &self.config)?;
+        info!(
+            "[OK] Market index broadcasting to {} port={}",
+            self.config.transport, port
+        );
+
+        let sentiments = Arc::clone(&self.sentiments);
+        let shutdown = Arc::clone(&self.shutdown);
+        let wire_format = self.config.wire_format;
+        let broadcast_interval = self.config.broadcast_interval;
+        let destinations = self.destinations();
+        let metrics = Arc::clone(&self.metrics);
+        let stocks = Arc::clone(&self.stocks);
+        let config = self.config.clone();
+        let warmup_ticks = self.warmup_ticks();
+        let regime = self.regime_source();
+
+        Ok(thread::spawn(move || {
+            let index_metrics = Metrics::default();
+            let mut last_sent = None;
+            let mut outgoing = Outgoing::new(&index);
+            let mut addrs = Vec::new();
+            let mut errors = SendErrors::default();
+            while !shutdown.load(Ordering::SeqCst) {
+                if !metrics.warmed_up(warmup_ticks) {
+                    sleep_unless_shutdown(&shutdown, broadcast_interval);
+                    continue;
+                }
+                let value = float_weighted_mean(&current_stocks(&stocks), &sentiments.load())
+                    .unwrap_or(config.mean);
+
+                if last_sent != Some(value) {
+                    let value = config.output_value(value);
+                    let message = outgoing.next(value, Some(regime()), wire_format);
+                    destinations.fill(&index, &mut addrs);
+                    for &addr in &addrs {
+                        let result = socket.send_to(message, addr);
+                        if track_send(&index_metrics, &mut errors, &index, addr, result, &config) {
+                            socket = rebind_broadcast_socket(&index, &config.transport, &config)
+                                .unwrap_or(socket);
+                        }
+                    }
+                    last_sent = Some(value);
+                }
+
+                sleep_unless_shutdown(&shutdown, broadcast_interval);
+            }
+        }))
+    }
+
+    /// Starts one broadcaster sending each news event as JSON on `port` as
+    /// soon as the engine generates it. Event sends don't count toward the
+    /// sentiment metrics.
+    fn start_events_broadcaster(&self, port: u16) -> Result<JoinHandle<()>, BindError> {
+        let events = Stock {
+            ticker: EVENTS_TICKER.to_string(),
+            id: EVENTS_STOCK_ID,
+            company_name: "News events".to_string(),
+            total_float: 0,
+            initial_price: 0.0,
+            sentiment_port: port,
+            initial_sentiment: None,
+            sector: None,
+        };
+        let mut socket = open_broadcast_socket(&events, &self.config.transport, &self.config)?;
+        info!(
+            "[OK] News events broadcasting to {} port={}",
+            self.config.transport, port
+        );
+
+        let receiver = self.subscribe_news();
+        let shutdown = Arc::clone(&self.shutdown);
+        let destinations = self.destinations();
+        let config = self.config.clone();
+
+        Ok(thread::spawn(move || {
+            let events_metrics = Metrics::default();
+            let mut addrs = Vec::new();
+            let mut errors = SendErrors::default();
+            while !shutdown.load(Ordering::SeqCst) {
+                let event = match receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                let message = serde_json::to_vec(&event).expect("NewsEvent always serializes");
+                destinations.fill(&events, &mut addrs);
+                for &addr in &addrs {
+                    let result = socket.send_to(&message, addr);
+                    if track_send(&events_metrics, &mut errors, &events, addr, result, &config) {
+                        socket = rebind_broadcast_socket(&events, &config.transport, &config)
+                            .unwrap_or(socket);
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Async counterpart of `start` for callers already inside a Tokio
+    /// runtime. Runs the engine on a `tokio::time::interval` and every
+    /// stock's broadcasts from one task and one `tokio::net::UdpSocket`,
+    /// like `BroadcastMode::Shared`. Recordings are only replayed by
+    /// `start`. Must be called from within a runtime.
+    #[cfg(feature = "tokio")]
+    pub fn start_async(&self) -> Result<AsyncServiceHandle, BindError> {
+        use tokio::time::{interval, MissedTickBehavior};
+
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        let mut tasks = Vec::new();
+        self.started_at.get_or_init(Instant::now);
+
+        let mut engine = self.engine();
+        let mut ticks = interval(self.config.tick_interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let shutdown = Arc::clone(&self.shutdown);
+        let paused = Arc::clone(&self.paused);
+        let metrics = Arc::clone(&self.metrics);
+        let recorder = Arc::clone(&self.recorder);
+        let sentiments = Arc::clone(&self.sentiments);
+        let stocks = Arc::clone(&self.stocks);
+        let mut stop = stop_rx.clone();
+        tasks.push(tokio::spawn(async move {
+            // The first tick of an interval completes immediately
+            ticks.tick().await;
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stop.changed() => break,
+                }
+                if shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                metrics.heartbeat();
+                if paused.load(Ordering::SeqCst) {
+                    continue;
+                }
+                metrics.tick_started();
+                engine.step();
+                metrics.engine_ticks.fetch_add(1, Ordering::Relaxed);
+                record_tick(&recorder, &current_stocks(&stocks), &sentiments);
+            }
+        }));
+
+        let initial = self.stocks();
+        if let Some(first) = initial.first().filter(|_| self.config.enable_broadcast) {
+            let socket = open_broadcast_socket(first, &self.config.transport, &self.config)?;
+            let mut socket = socket
+                .set_nonblocking(true)
+                .and_then(|()| tokio::net::UdpSocket::from_std(socket))
+                .map_err(|source| BindError::Socket {
+                    ticker: first.ticker.clone(),
+                    sentiment_port: first.sentiment_port,
+                    source,
+                })?;
+            info!(
+                "[OK] {} stocks broadcasting to {} from one async task",
+                initial.len(),
+                self.config.transport
+            );
+
+            let mut sends = interval(self.config.broadcast_interval);
+            sends.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let shutdown = Arc::clone(&self.shutdown);
+            let metrics = Arc::clone(&self.metrics);
+            let sentiments = Arc::clone(&self.sentiments);
+            let stocks = Arc::clone(&self.stocks);
+            let wire_format = self.config.wire_format;
+            let destinations = self.destinations();
+            let config = self.config.clone();
+            let warmup_ticks = self.warmup_ticks();
+            let regime = self.regime_source();
+            let mut stop = stop_rx;
+            tasks.push(tokio::spawn(async move {
+                let mut last_sent = HashMap::new();
+                let mut outgoing: HashMap<u64, Outgoing> = HashMap::new();
+                let mut addrs = Vec::new();
+                let mut errors: HashMap<u64, SendErrors> = HashMap::new();
+                loop {
+                    tokio::select! {
+                        _ = sends.tick() => {}
+                        _ = stop.changed() => break,
+                    }
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if !metrics.warmed_up(warmup_ticks) {
+                        continue;
+                    }
+
+                    let stocks = current_stocks(&stocks);
+                    let snapshot = sentiments.load();
+                    let regime = Some(regime());
+                    for stock in stocks.iter() {
+                        let sentiment = snapshot.get(&stock.id).copied().unwrap_or(0.0);
+                        if !metrics.past_phase(config.phase(stock.id))
+                            || last_sent.insert(stock.id, sentiment) == Some(sentiment)
+                        {
+                            continue;
+                        }
+                        let message = outgoing
+                            .entry(stock.id)
+                            .or_insert_with(|| Outgoing::new(stock))
+                            .next(config.output_value(sentiment), regime, wire_format);
+                        let errors = errors.entry(stock.id).or_default();
+                        destinations.fill(stock, &mut addrs);
+                        for &addr in &addrs {
+                            let result = socket.send_to(message, addr).await;
+                            if !track_send(&metrics, errors, stock, addr, result, &config) {
+                                continue;
+                            }
+                            let fresh = rebind_broadcast_socket(stock, &config.transport, &config)
+                                .and_then(|fresh| {
+                                    fresh
+                                        .set_nonblocking(true)
+                                        .and_then(|()| tokio::net::UdpSocket::from_std(fresh))
+                                        .ok()
+                                });
+                            if let Some(fresh) = fresh {
+                                socket = fresh;
+                            }
+                        }
+                    }
+                }
+            }));
+        }
+
+        Ok(AsyncServiceHandle {
+            shutdown: Arc::clone(&self.shutdown),
+            stop_tx,
+            tasks,
+        })
+    }
+
+    /// Listens on `bind` and streams every stock's latest value to each
+    /// connected client as newline-delimited JSON `SentimentPacket`s, once
+    /// per `broadcast_interval`. With `backfill_ticks` set, a client instead
+    /// gets the kept ticks and then each new one. With `stream_compression`
+    /// set, each cycle's packets go out as one frame instead. Each
+    /// connection gets its own thread; a client that disconnects just ends
+    /// its thread. Returns the bound address (useful with port 0). The
+    /// server exits on `stop()`.
+    pub fn start_tcp_server(&self, bind: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(bind)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        info!("[OK] TCP stream listening on {}", local_addr);
+
+        let sentiments = Arc::clone(&self.sentiments);
+        let shutdown = Arc::clone(&self.shutdown);
+        let stocks = Arc::clone(&self.stocks);
+        let history = Arc::clone(&self.history);
+        let metrics = Arc::clone(&self.metrics);
+        let config = Arc::new(self.config.clone());
+
+        thread::spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let sentiments = Arc::clone(&sentiments);
+                        let shutdown = Arc::clone(&shutdown);
+                        let stocks = Arc::clone(&stocks);
+                        let config = Arc::clone(&config);
+                        let history = Arc::clone(&history);
+                        let metrics = Arc::clone(&metrics);
+                        thread::spawn(move || {
+                            stream_to_tcp_client(
+                                stream,
+                                &stocks,
+                                &sentiments,
+                                &history,
+                                &shutdown,
+                                &metrics,
+                                &config,
+                            )
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        sleep_unless_shutdown(&shutdown, SHUTDOWN_POLL_INTERVAL);
+                    }
+                    Err(e) => error!("TCP accept failed on {}: {}", local_addr, e),
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    /// Serves every stock's latest value over HTTP as Server-Sent Events,
+    /// once per `broadcast_interval`. Each event is named after its ticker so
+    /// browser clients can `addEventListener` for just the tickers they want,
+    /// and carries a JSON `SentimentPacket` as its data. One thread writes to
+    /// all clients and flushes after each event; a client whose write fails
+    /// is dropped. Each request is read on its own thread, so a slow client
+    /// can't hold up the others. Returns the bound address. The server exits
+    /// on `stop()`.
+    pub fn start_sse_server(&self, bind: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(bind)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        info!("[OK] SSE stream listening on http://{}", local_addr);
+
+        let clients: Arc<Mutex<Vec<BufWriter<TcpStream>>>> = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let clients = Arc::clone(&clients);
+            let sentiments = Arc::clone(&self.sentiments);
+            let shutdown = Arc::clone(&self.shutdown);
+            let stocks = Arc::clone(&self.stocks);
+            let interval = self.config.broadcast_interval;
+            let config = self.config.clone();
+            thread::spawn(move || {
+                while sleep_unless_shutdown(&shutdown, interval) {
+                    let snapshot = sentiments.load();
+                    let events: Vec<String> = current_stocks(&stocks)
+                        .iter()
+                        .map(|stock| {
+                            let value = snapshot.get(&stock.id).copied().unwrap_or(0.0);
+                            sse_event(&SentimentPacket::now(
+                                &stock.ticker,
+                                stock.id,
+                                config.output_value(value),
+                            ))
+                        })
+                        .collect();
+                    if let Ok(mut clients) = clients.lock() {
+                        clients.retain_mut(|writer| {
+                            events.iter().all(|event| {
+                                writer.write_all(event.as_bytes()).is_ok() && writer.flush().is_ok()
+                            })
+                        });
+                    }
+                }
+            });
+        }
+
+        let shutdown = Arc::clone(&self.shutdown);
+        thread::spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let clients = Arc::clone(&clients);
+                        thread::spawn(move || match accept_sse_client(stream) {
+                            Ok(Some(writer)) => {
+                                if let Ok(mut clients) = clients.lock() {
+                                    clients.push(writer);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => warn!("SSE request failed: {}", e),
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        sleep_unless_shutdown(&shutdown, SHUTDOWN_POLL_INTERVAL);
+                    }
+                    Err(e) => error!("SSE accept failed on {}: {}", local_addr, e),
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    /// Per-stock send status plus whether the engine is still ticking, for
+    /// liveness and readiness probes. Heartbeats come from `start` and
+    /// `start_async`; a replaying service reports its engine as dead.
+    pub fn health(&self) -> HealthReport {
+        let stale_after = self.config.tick_interval * HEARTBEAT_STALE_TICKS;
+        let heartbeat_ms = self.metrics.engine_heartbeat_ms.load(Ordering::Relaxed);
+        let engine_alive = !self.shutdown.load(Ordering::SeqCst)
+            && heartbeat_ms > 0
+            && wire::now_ms().saturating_sub(heartbeat_ms) <= stale_after.as_millis() as u64;
+
+        let broadcasters = self
+            .stocks()
+            .iter()
+            .map(|stock| BroadcasterHealth {
+                stock_id: stock.id,
+                ticker: stock.ticker.clone(),
+                last_success_ms: self.metrics.last_sent_ms(stock.id),
+                consecutive_failures: self.metrics.send_failures(stock.id),
+            })
+            .collect();
+        HealthReport {
+            engine_alive,
+            engine_restarts: self.metrics.engine_restarts.load(Ordering::Relaxed),
+            broadcasters,
+            max_send_failures: self.config.max_send_failures,
+        }
+    }
+
+    /// Packets sent for `stock_id` since the service was created.
+    pub fn packets_sent(&self, stock_id: u64) -> u64 {
+        self.metrics.packets_sent(stock_id)
+    }
+
+    /// Uptime, engine ticks, packets sent, overall and per stock, and the
+    /// stream compression ratio. Each call starts a new window for the
+    /// stocks' `send_rate`.
+    pub fn stats(&self) -> ServiceStats {
+        let now = Instant::now();
+        let started_at = self.started_at.get().copied();
+        let uptime = started_at.map_or(Duration::ZERO, |at| now - at);
+        let mut samples = self
+            .rate_samples
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let stocks = self
+            .stocks()
+            .iter()
+            .map(|stock| {
+                let packets_sent = self.metrics.packets_sent(stock.id);
+                let since = match started_at {
+                    Some(at) => samples
+                        .insert(stock.id, (packets_sent, now))
+                        .or(Some((0, at))),
+                    None => None,
+                };
+                let send_rate = match since {
+                    Some((count, at)) if now > at => {
+                        packets_sent.saturating_sub(count) as f64 / (now - at).as_secs_f64()
+                    }
+                    _ => 0.0,
+                };
+                StockStats {
+                    stock_id: stock.id,
+                    ticker: stock.ticker.clone(),
+                    packets_sent,
+                    send_rate,
+                }
+            })
+            .collect();
+        ServiceStats {
+            uptime,
+            ticks: self.metrics.engine_ticks.load(Ordering::Relaxed),
+            packets_sent: self.metrics.total_packets_sent(),
+            compression_ratio: self.metrics.compression_ratio(),
+            stocks,
+        }
+    }
+
+    /// Renders the service's counters and current sentiments in the
+    /// Prometheus text exposition format.
+    pub fn metrics_text(&self) -> String {
+        self.metrics
+            .render(&self.stocks(), &self.sentiments, &self.config)
+    }
+
+    /// Serves `metrics_text` over HTTP at `GET /metrics` on `bind`; every
+    /// other path gets a 404. Requests are handled one at a time on the
+    /// listener thread. Returns the bound address. The server exits on
+    /// `stop()`.
+    #[cfg(feature = "metrics")]
+    pub fn start_metrics_server(&self, bind: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(bind)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        info!("[OK] Metrics listening on http://{}/metrics", local_addr);
+
+        let sentiments = Arc::clone(&self.sentiments);
+        let shutdown = Arc::clone(&self.shutdown);
+        let metrics = Arc::clone(&self.metrics);
+        let stocks = Arc::clone(&self.stocks);
+        let config = self.config.clone();
+
+        thread::spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = serve_metrics_request(stream, || {
+                            metrics.render(&current_stocks(&stocks), &sentiments, &config)
+                        }) {
+                            warn!("Metrics request failed: {}", e);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        sleep_unless_shutdown(&shutdown, SHUTDOWN_POLL_INTERVAL);
+                    }
+                    Err(e) => error!("Metrics accept failed on {}: {}", local_addr, e),
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    /// Listens on `bind` for WebSocket clients and pushes each stock's latest
+    /// value as a JSON `SentimentPacket` text frame once per
+    /// `broadcast_interval`, or with `backfill_ticks` set the kept ticks
+    /// and then each new one. With `stream_compression` set, a cycle's
+    /// packets instead go out as one binary `wire::encode_frame` message;
+    /// tungstenite has no permessage-deflate to negotiate. A client may
+    /// send `{"subscribe": ["AAPL"]}` to only receive those tickers. Each
+    /// client has a bounded queue; one that falls `WEBSOCKET_QUEUE_CAPACITY`
+    /// cycles behind is disconnected. Returns the bound address. The server
+    /// exits on `stop()`.
+    #[cfg(feature = "websocket")]
+    pub fn start_websocket_server(&self, bind: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(bind)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        info!("[OK] WebSocket stream listening on {}", local_addr);
+
+        let clients: Arc<Mutex<WebSocketClients>> = Arc::default();
+        let history = Arc::clone(&self.history);
+
+        // Fan-out: one snapshot (or the new backfill ticks) per cycle,
+        // shared by every client queue
+        {
+            let clients = Arc::clone(&clients);
+            let history = Arc::clone(&history);
+            let sentiments = Arc::clone(&self.sentiments);
+            let shutdown = Arc::clone(&self.shutdown);
+            let stocks = Arc::clone(&self.stocks);
+            let interval = self.config.broadcast_interval;
+            let config = self.config.clone();
+            thread::spawn(move || {
+                while sleep_unless_shutdown(&shutdown, interval) {
+                    let Ok(mut clients) = clients.lock() else {
+                        continue;
+                    };
+                    let batch: Arc<Vec<SentimentPacket>> = if history.enabled() {
+                        let (ticks, cursor) = history.since(clients.cursor);
+                        clients.cursor = cursor;
+                        if ticks.is_empty() {
+                            continue;
+                        }
+                        Arc::new(ticks.iter().flat_map(|tick| tick.iter().cloned()).collect())
+                    } else {
+                        let snapshot = sentiments.load();
+                        Arc::new(
+                            current_stocks(&stocks)
+                                .iter()
+                                .map(|stock| {
+                                    let value = snapshot.get(&stock.id).copied().unwrap_or(0.0);
+                                    SentimentPacket::now(
+                                        &stock.ticker,
+                                        stock.id,
+                                        config.output_value(value),
+                                    )
+                                })
+                                .collect(),
+                        )
+                    };
+                    clients
+                        .queues
+                        .retain(|tx| match tx.try_send(Arc::clone(&batch)) {
+                            Ok(()) => true,
+                            Err(TrySendError::Full(_)) => {
+                                warn!("Dropping slow WebSocket client");
+                                false
+                            }
+                            Err(TrySendError::Disconnected(_)) => false,
+                        });
+                }
+            });
+        }
+
+        let shutdown = Arc::clone(&self.shutdown);
+        let metrics = Arc::clone(&self.metrics);
+        let compression = self.config.stream_compression;
+        let threshold = self.config.compression_threshold;
+        thread::spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let (tx, rx) = mpsc::sync_channel(WEBSOCKET_QUEUE_CAPACITY);
+                        if let Ok(mut clients) = clients.lock() {
+                            // Backfill up to where the fan-out has got to, so
+                            // its next batch carries on from there.
+                            if history.enabled() {
+                                let (ticks, _) = history.since(0);
+                                let backfill: Vec<SentimentPacket> = ticks
+                                    .iter()
+                                    .flat_map(|tick| tick.iter())
+                                    .filter(|packet| packet.seq <= clients.cursor)
+                                    .cloned()
+                                    .collect();
+                                if !backfill.is_empty() {
+                                    let _ = tx.try_send(Arc::new(backfill));
+                                }
+                            }
+                            clients.queues.push(tx);
+                        }
+                        let shutdown = Arc::clone(&shutdown);
+                        let metrics = Arc::clone(&metrics);
+                        thread::spawn(move || {
+                            serve_websocket_client(
+                                stream,
+                                rx,
+                                &shutdown,
+                                &metrics,
+                                compression,
+                                threshold,
+                            )
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        sleep_unless_shutdown(&shutdown, SHUTDOWN_POLL_INTERVAL);
+                    }
+                    Err(e) => error!("WebSocket accept failed on {}: {}", local_addr, e),
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+
+    /// Overwrites a stock's sentiment, clamped to the sentiment range. The
+    /// engine evolves it from there on its next tick, so `pause` first to
+    /// hold the value. Unknown ids are ignored.
+    pub fn set_sentiment(&self, stock_id: u64, value: f64) {
+        self.sentiments.update(|map| {
+            if let Some(state) = map.get_mut(&stock_id) {
+                *state = self.config.clamp(value);
+            }
+        });
+    }
+
+    /// Overwrites the market mood, clamped to the sentiment range. Like
+    /// `set_sentiment`, the next engine tick moves it unless the engine is
+    /// paused.
+    pub fn set_market_mood(&self, value: f64) {
+        if let Ok(mut mood) = self.market_mood.write() {
+            *mood = self.config.clamp(value);
+        }
+    }
+
+    /// Adds `magnitude` to a stock's current sentiment, e.g. to simulate a
+    /// news event. The shock then decays through the stock's normal mean
+    /// reversion. Unknown ids are ignored.
+    pub fn inject_shock(&self, stock_id: u64, magnitude: f64) {
+        self.sentiments.update(|map| {
+            if let Some(state) = map.get_mut(&stock_id) {
+                *state = shocked(*state, magnitude, &self.config);
+            }
+        });
+    }
+
+    /// Adds `magnitude` to the market mood, moving every stock as they
+    /// revert toward it.
+    pub fn inject_market_shock(&self, magnitude: f64) {
+        if let Ok(mut mood) = self.market_mood.write() {
+            *mood = self.config.clamp(*mood + magnitude);
+        }
+    }
+
+    /// All current sentiments, copied from one published map so the view
+    /// never mixes values from different ticks.
+    pub fn snapshot(&self) -> HashMap<u64, f64> {
+        HashMap::clone(&self.sentiments.load())
+    }
+
+    /// How the current sentiments spread over the sentiment range: `bins`
+    /// equal-width bins from `sentiment_min` to `sentiment_max`, each as its
+    /// center and the number of stocks in it, lowest first. Bins are
+    /// half-open except the last, which also holds `sentiment_max`. Values
+    /// are on the sentiment range, not `scale`. No bins gives an empty list.
+    pub fn distribution(&self, bins: usize) -> Vec<(f64, usize)> {
+        if bins == 0 {
+            return Vec::new();
+        }
+        let (min, max) = (self.config.sentiment_min, self.config.sentiment_max);
+        let width = (max - min) / bins as f64;
+        let mut counts = vec![0; bins];
+        for &value in self.sentiments.load().values() {
+            let bin = ((value - min) / width) as usize;
+            counts[bin.min(bins - 1)] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (min + width * (i as f64 + 0.5), count))
+            .collect()
+    }
+
+    /// Like `snapshot`, plus the market mood from the same tick. The engine
+    /// holds the mood lock while it writes sentiments, so taking the mood
+    /// lock first guarantees both values are consistent.
+    pub fn snapshot_with_mood(&self) -> (f64, HashMap<u64, f64>) {
+        let mood = self
+            .market_mood
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        (*mood, self.snapshot())
+    }
+
+    /// The mood shared by the stocks in `sector`, added to the market mood
+    /// they revert toward. `None` if no stock is in that sector.
+    pub fn sector_mood(&self, sector: &str) -> Option<f64> {
+        self.sector_moods.read().ok()?.get(sector).copied()
+    }
+
+    /// The market-wide mood every stock reverts toward, in the sentiment
+    /// range.
+    pub fn market_mood(&self) -> f64 {
+        self.market_mood.read().map_or(0.0, |mood| *mood)
+    }
+
+    /// The regime `regimes` is in, if configured. Otherwise the mood's
+    /// regime, judged on the mood mapped onto [-1, 1] so that
+    /// `Regime::THRESHOLD` means the same on any sentiment range.
+    pub fn market_regime(&self) -> Regime {
+        current_regime(&self.config, &self.market_mood, &self.markov_regime)
+    }
+
+    /// `market_regime` for a worker thread to own.
+    fn regime_source(&self) -> impl Fn() -> Regime + Send + 'static {
+        let config = self.config.clone();
+        let market_mood = Arc::clone(&self.market_mood);
+        let markov_regime = Arc::clone(&self.markov_regime);
+        move || current_regime(&config, &market_mood, &markov_regime)
+    }
+
+    /// Current sentiment for `stock_id` on the configured `scale`, or
+    /// `None` if there is no such stock.
+    pub fn try_get_sentiment(&self, stock_id: u64) -> Option<f64> {
+        let value = self.sentiments.get(stock_id)?;
+        Some(self.config.output_value(value))
+    }
+
+    /// Like `try_get_sentiment`, but an unknown stock reads as a neutral
+    /// 0.0 on the internal scale.
+    pub fn get_sentiment(&self, stock_id: u64) -> f64 {
+        self.try_get_sentiment(stock_id)
+            .unwrap_or_else(|| self.config.output_value(0.0))
+    }
+
+    /// Average sentiment across all stocks weighted by `total_float`, on
+    /// the configured `scale`. Stocks with no float don't count; if none
+    /// have any, this is the long-run `mean`.
+    pub fn market_index(&self) -> f64 {
+        let index = float_weighted_mean(&self.stocks(), &self.sentiments.load());
+        self.config.output_value(index.unwrap_or(self.config.mean))
+    }
+
+    /// Simulated price of `stock_id`, or 0.0 for an unknown id. It starts at
+    /// the stock's `initial_price` and compounds at `price_drift` times the
+    /// stock's sentiment, mapped onto [-1, 1], per second.
+    pub fn get_price(&self, stock_id: u64) -> f64 {
+        self.prices
+            .read()
+            .map(|map| map.get(&stock_id).copied().unwrap_or(0.0))
+            .unwrap_or(0.0)
+    }
+
+    /// Current sentiment for `ticker`, matched case-insensitively, or
+    /// `None` if no stock has that ticker.
+    pub fn get_sentiment_by_ticker(&self, ticker: &str) -> Option<f64> {
+        let id = *self.ticker_index.read().ok()?.get(&ticker.to_uppercase())?;
+        let value = self.sentiments.get(id)?;
+        Some(self.config.output_value(value))
+    }
+}
+
+/// Mean of `stocks`' sentiments weighted by `total_float`, or `None` if
+/// no stock has both a sentiment and a non-zero float.
+fn float_weighted_mean(stocks: &[Stock], sentiments: &HashMap<u64, f64>) -> Option<f64> {
+    let (weighted, total) = stocks
+        .iter()
+        .filter(|stock| stock.total_float > 0)
+        .filter_map(|stock| Some((sentiments.get(&stock.id)?, stock.total_float as f64)))
+        .fold((0.0, 0.0), |(weighted, total), (sentiment, float)| {
+            (weighted + sentiment * float, total + float)
+        });
+    (total > 0.0).then(|| weighted / total)
+}
+
+fn current_regime(config: &SentimentConfig, mood: &RwLock<f64>, markov: &RwLock<Regime>) -> Regime {
+    if config.regimes.is_some() {
+        return markov.read().map_or(Regime::Neutral, |regime| *regime);
+    }
+    let mood = mood.read().map_or(config.mean, |mood| *mood);
+    Regime::from_mood(config.normalize(mood))
+}
+
+fn ticker_index(stocks: &[Stock]) -> HashMap<String, u64> {
+    stocks
+        .iter()
+        .map(|stock| (stock.ticker.to_uppercase(), stock.id))
+        .collect()
+}
+
+/// Columns a stock CSV must have. Others may appear in any order, and
+/// unknown ones are ignored.
+const REQUIRED_CSV_COLUMNS: [&str; 6] = [
+    "ticker",
+    "id",
+    "company_name",
+    "total_float",
+    "initial_price",
+    "sentiment_port",
+];
+
+/// Reads the stock CSV at `csv_path`, see `parse_stocks`.
+fn read_stocks(csv_path: &str, lenient: bool, delimiter: char) -> Result<Vec<Stock>, LoadError> {
+    parse_stocks(File::open(csv_path)?, csv_path, lenient, delimiter)
+}
+
+/// Parses a stock CSV separated by `delimiter`, attributing errors to their
+/// line. Fields are matched to columns by header name, surrounding
+/// whitespace is trimmed, and lines starting with `#` are skipped. Bad rows
+/// are collected into a `CsvLoadError` for `source`, or logged and skipped
+/// when `lenient`.
+fn parse_stocks(
+    input: impl Read,
+    source: &str,
+    lenient: bool,
+    delimiter: char,
+) -> Result<Vec<Stock>, LoadError> {
+    let delimiter = u8::try_from(delimiter)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or(LoadError::InvalidDelimiter(delimiter))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let headers = reader.headers()?.clone();
+    if let Some(column) = REQUIRED_CSV_COLUMNS
+        .into_iter()
+        .find(|&column| !headers.iter().any(|header| header == column))
+    {
+        return Err(LoadError::MissingColumn(column));
+    }
+    let mut stocks: Vec<Stock> = Vec::new();
+    let mut errors = Vec::new();
+    let mut validator = StockValidator::default();
+
+    for result in reader.records() {
+        let row = result
+            .map_err(|e| {
+                let line = e.position().map_or(0, |pos| pos.line());
+                (line, e.to_string())
+            })
+            .and_then(|record| {
+                let line = record.position().map_or(0, |pos| pos.line());
+                record
+                    .deserialize::<StockRecord>(Some(&headers))
+                    .map_err(|e| match e.kind() {
+                        csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                        _ => e.to_string(),
+                    })
+                    .and_then(StockRecord::into_stock)
+                    .and_then(|stock| match validator.check(&stock, stocks.len()) {
+                        Ok(()) => Ok(stock),
+                        Err(LoadError::DuplicateId { id, .. }) => {
+                            Err(format!("duplicate id {}", id))
+                        }
+                        Err(LoadError::DuplicatePort { port, .. }) => {
+                            Err(format!("duplicate sentiment_port {}", port))
+                        }
+                        Err(e) => Err(e.to_string()),
+                    })
+                    .map(|stock| (line, stock))
+                    .map_err(|message| (line, message))
+            });
+
+        match row {
+            Ok((_, stock)) => stocks.push(stock),
+            Err((line, message)) => errors.push(CsvRowError {
+                line: line as usize,
+                message,
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        if !lenient {
+            return Err(LoadError::Rows(CsvLoadError {
+                path: source.to_string(),
+                rows: errors,
+            }));
+        }
+        for error in &errors {
+            warn!("Skipping {} in {}", error, source);
+        }
+    }
+
+    info!("Loaded {} stocks from {}", stocks.len(), source);
+    Ok(stocks)
+}
+
+fn read_stocks_json(json_path: &str) -> Result<Vec<Stock>, LoadError> {
+    parse_stocks_json(File::open(json_path)?, json_path)
+}
+
+/// Parses a JSON array of `Stock`s, checking ids and ports are unique.
+fn parse_stocks_json(input: impl Read, source: &str) -> Result<Vec<Stock>, LoadError> {
+    let stocks: Vec<Stock> =
+        serde_json::from_reader(io::BufReader::new(input)).map_err(LoadError::Json)?;
+    validate_stocks(&stocks)?;
+
+    info!("Loaded {} stocks from {}", stocks.len(), source);
+    Ok(stocks)
+}
+
+fn read_stocks_jsonl(jsonl_path: &str) -> Result<Vec<Stock>, LoadError> {
+    let contents = std::fs::read_to_string(jsonl_path)?;
+    let mut stocks = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let stock = serde_json::from_str(line).map_err(|source| LoadError::JsonLine {
+            line: i + 1,
+            source,
+        })?;
+        stocks.push(stock);
+    }
+    validate_stocks(&stocks)?;
+
+    info!("Loaded {} stocks from {}", stocks.len(), jsonl_path);
+    Ok(stocks)
+}
+
+/// Rejects stock lists that reuse an id or a sentiment port. Shared by
+/// every loader and the builder.
+fn validate_stocks(stocks: &[Stock]) -> Result<(), LoadError> {
+    let mut validator = StockValidator::default();
+    for (index, stock) in stocks.iter().enumerate() {
+        validator.check(stock, index)?;
+    }
+    Ok(())
+}
+
+/// The ids and ports seen so far while validating a stock list.
+#[derive(Default)]
+struct StockValidator {
+    ids: Vec<u64>,
+    ports: Vec<u16>,
+}
+
+impl StockValidator {
+    /// Accepts `stock` if neither its id nor its port has been seen, and
+    /// remembers both. `index` is only used in the error.
+    fn check(&mut self, stock: &Stock, index: usize) -> Result<(), LoadError> {
+        if self.ids.contains(&stock.id) {
+            return Err(LoadError::DuplicateId {
+                id: stock.id,
+                index,
+            });
+        }
+        if self.ports.contains(&stock.sentiment_port) {
+            return Err(LoadError::DuplicatePort {
+                port: stock.sentiment_port,
+                index,
+            });
+        }
+        self.ids.push(stock.id);
+        self.ports.push(stock.sentiment_port);
+        Ok(())
+    }
+}
+
+fn current_stocks(stocks: &RwLock<Arc<Vec<Stock>>>) -> Arc<Vec<Stock>> {
+    stocks
+        .read()
+        .map(|list| Arc::clone(&list))
+        .unwrap_or_default()
+}
+
+/// Current sentiment per stock id. Readers load the latest published map
+/// without locking; writers publish a modified copy, so broadcasters never
+/// hold up the engine and never see a half-written tick.
+#[derive(Default)]
+struct Sentiments(ArcSwap<HashMap<u64, f64>>);
+
+impl Sentiments {
+    fn new(map: HashMap<u64, f64>) -> Self {
+        Self(ArcSwap::from_pointee(map))
+    }
+
+    /// The latest published map. Later updates don't affect it.
+    fn load(&self) -> Arc<HashMap<u64, f64>> {
+        self.0.load_full()
+    }
+
+    fn get(&self, stock_id: u64) -> Option<f64> {
+        self.0.load().get(&stock_id).copied()
+    }
+
+    /// Publishes a copy of the map with `update` applied. If another writer
+    /// publishes first, `update` runs again on its map, so no write is lost.
+    fn update(&self, mut update: impl FnMut(&mut HashMap<u64, f64>)) {
+        self.0.rcu(|map| {
+            let mut map = HashMap::clone(map);
+            update(&mut map);
+            map
+        });
+    }
+}
+
+/// The sentiments before the latest engine tick and when the tick was
+/// published. Broadcasters use it to ease each stock linearly from its old
+/// value to its new one over one tick interval.
+struct Interpolation {
+    tick_interval: Duration,
+    last_tick: ArcSwapOption<LastTick>,
+}
+
+struct LastTick {
+    previous: Arc<HashMap<u64, f64>>,
+    at: Instant,
+}
+
+impl Interpolation {
+    fn new(tick_interval: Duration) -> Self {
+        Self {
+            tick_interval,
+            last_tick: ArcSwapOption::empty(),
+        }
+    }
+
+    /// Notes that a tick is about to be published, replacing `previous`.
+    fn record(&self, previous: Arc<HashMap<u64, f64>>) {
+        self.last_tick.store(Some(Arc::new(LastTick {
+            previous,
+            at: Instant::now(),
+        })));
+    }
+
+    /// Where `stock_id` is at `now` on its way from its pre-tick value to
+    /// `current`. Before the first tick, for a stock the tick added, and
+    /// from a tick interval after the tick on, this is `current`.
+    fn value(&self, stock_id: u64, current: f64, now: Instant) -> f64 {
+        let last_tick = self.last_tick.load();
+        let Some(tick) = last_tick.as_ref() else {
+            return current;
+        };
+        let Some(&previous) = tick.previous.get(&stock_id) else {
+            return current;
+        };
+        let progress =
+            now.saturating_duration_since(tick.at).as_secs_f64() / self.tick_interval.as_secs_f64();
+        // previous + (current - previous) needn't round back to current
+        if progress >= 1.0 {
+            return current;
+        }
+        previous + (current - previous) * progress
+    }
+}
+
+/// Applies a tick's `update` to `sentiments`. With `interpolation`, the
+/// values it replaces are recorded first, so a broadcaster that sees the
+/// new values also sees the tick they ease from.
+fn publish_tick(
+    sentiments: &Sentiments,
+    interpolation: Option<&Interpolation>,
+    update: impl FnMut(&mut HashMap<u64, f64>),
+) {
+    if let Some(interpolation) = interpolation {
+        interpolation.record(sentiments.load());
+    }
+    sentiments.update(update);
+}
+
+/// `current` eased by `interpolation`, if interpolating.
+fn interpolated(interpolation: Option<&Interpolation>, stock_id: u64, current: f64) -> f64 {
+    interpolation.map_or(current, |interpolation| {
+        interpolation.value(stock_id, current, Instant::now())
+    })
+}
+
+/// Binds an ephemeral UDP socket for sending over `config.transport`.
+/// Failures are attributed to `stock`.
+fn open_broadcast_socket(
+    stock: &Stock,
+    transport: &Transport,
+    config: &SentimentConfig,
+) -> Result<UdpSocket, BindError> {
+    let socket = UdpSocket::bind((config.ip_family.unspecified(), 0)).map_err(|source| {
+        BindError::Socket {
+            ticker: stock.ticker.clone(),
+            sentiment_port: stock.sentiment_port,
+            source,
+        }
+    })?;
+    if let Transport::Multicast { addr, ttl } = transport {
+        // The default TTL of 1 prevents packets from leaving the local network
+        let configured = match addr {
+            IpAddr::V4(_) => socket
+                .set_multicast_ttl_v4(*ttl)
+                .and_then(|()| socket.set_multicast_loop_v4(config.multicast_loop)),
+            IpAddr::V6(_) => {
+                let socket = socket2::SockRef::from(&socket);
+                socket
+                    .set_multicast_hops_v6(*ttl)
+                    .and_then(|()| socket.set_multicast_loop_v6(config.multicast_loop))
+            }
+        };
+        configured.map_err(|source| BindError::MulticastTtl {
+            ticker: stock.ticker.clone(),
+            sentiment_port: stock.sentiment_port,
+            source,
+        })?;
+        if let (IpAddr::V4(_), Some(interface)) = (addr, config.multicast_interface) {
+            socket2::SockRef::from(&socket)
+                .set_multicast_if_v4(&interface)
+                .map_err(|source| BindError::MulticastInterface {
+                    ticker: stock.ticker.clone(),
+                    sentiment_port: stock.sentiment_port,
+                    interface,
+                    source,
+                })?;
+        }
+    }
+    Ok(socket)
+}
+
+/// Replaces a failing broadcast socket with a fresh one, logging the
+/// outcome. `None` leaves the caller on its old socket.
+fn rebind_broadcast_socket(
+    stock: &Stock,
+    transport: &Transport,
+    config: &SentimentConfig,
+) -> Option<UdpSocket> {
+    match open_broadcast_socket(stock, transport, config) {
+        Ok(socket) => {
+            info!(
+                "Rebound broadcast socket ticker={} port={}",
+                stock.ticker, stock.sentiment_port
+            );
+            Some(socket)
+        }
+        Err(e) => {
+            warn!("Failed to rebind broadcast socket: {}", e);
+            None
+        }
+    }
+}
+
+/// Coalesces one stock's send failures so that a dead interface logs a
+/// warning every `SEND_ERROR_LOG_INTERVAL` instead of one per packet.
+#[derive(Default)]
+struct SendErrors {
+    consecutive: u64,
+    /// Failures since the last warning that were not logged.
+    suppressed: u64,
+    last_logged: Option<Instant>,
+}
+
+impl SendErrors {
+    /// Counts a failure and returns the warning to log, if one is due.
+    fn failure(
+        &mut self,
+        now: Instant,
+        ticker: &str,
+        addr: SocketAddr,
+        error: &io::Error,
+    ) -> Option<String> {
+        self.consecutive += 1;
+        if let Some(logged) = self.last_logged {
+            if now.duration_since(logged) < SEND_ERROR_LOG_INTERVAL {
+                self.suppressed += 1;
+                return None;
+            }
+        }
+        let mut message = format!(
+            "Failed to broadcast sentiment ticker={} dest={}: {}",
+            ticker, addr, error
+        );
+        if self.suppressed > 0 {
+            message.push_str(&format!(
+                " ({} more failures since the last report)",
+                self.suppressed
+            ));
+        }
+        self.suppressed = 0;
+        self.last_logged = Some(now);
+        Some(message)
+    }
+
+    /// Ends a failure streak, returning a recovery message if there was one.
+    fn success(&mut self, ticker: &str) -> Option<String> {
+        if self.consecutive == 0 {
+            return None;
+        }
+        let message = format!(
+            "Broadcasting recovered ticker={} after {} failed sends",
+            ticker, self.consecutive
+        );
+        *self = SendErrors::default();
+        Some(message)
+    }
+}
+
+/// Logs and counts the outcome of one send for `stock`. Returns true when
+/// its failure streak has reached another multiple of
+/// `config.max_send_failures`, i.e. when the caller should rebind.
+fn track_send(
+    metrics: &Metrics,
+    errors: &mut SendErrors,
+    stock: &Stock,
+    addr: SocketAddr,
+    result: io::Result<usize>,
+    config: &SentimentConfig,
+) -> bool {
+    match result {
+        Ok(_) => {
+            metrics.packet_sent(stock.id);
+            if let Some(message) = errors.success(&stock.ticker) {
+                info!("{}", message);
+                metrics.set_send_failures(stock.id, 0);
+            }
+            false
+        }
+        Err(e) => {
+            if let Some(message) = errors.failure(Instant::now(), &stock.ticker, addr, &e) {
+                warn!("{}", message);
+            }
+            metrics.set_send_failures(stock.id, errors.consecutive);
+            // Never true for a limit of 0, since the streak is at least 1
+            errors
+                .consecutive
+                .is_multiple_of(u64::from(config.max_send_failures))
+        }
+    }
+}
+
+/// Appends the current value of every stock to the active recording, if
+/// there is one. A write failure ends the recording.
+fn record_tick(
+    recorder: &Mutex<Option<csv::Writer<File>>>,
+    stocks: &[Stock],
+    sentiments: &Sentiments,
+) {
+    let Ok(mut recorder) = recorder.lock() else {
+        return;
+    };
+    let Some(writer) = recorder.as_mut() else {
+        return;
+    };
+
+    let timestamp_ms = wire::now_ms();
+    let snapshot = sentiments.load();
+    let result = stocks
+        .iter()
+        .filter_map(|stock| {
+            snapshot.get(&stock.id).map(|&value| RecordedSample {
+                timestamp_ms,
+                stock_id: stock.id,
+                value,
+            })
+        })
+        .try_for_each(|sample| writer.serialize(sample))
+        .and_then(|()| writer.flush().map_err(csv::Error::from));
+
+    if let Err(e) = result {
+        error!("Recording stopped: {}", e);
+        *recorder = None;
+    }
+}
+
+/// Applies `update` to `stock_id`'s counter, creating it on first use. Only
+/// the first use takes the write lock.
+fn update_counter(
+    counters: &RwLock<HashMap<u64, AtomicU64>>,
+    stock_id: u64,
+    update: impl Fn(&AtomicU64),
+) {
+    if let Ok(counts) = counters.read() {
+        if let Some(count) = counts.get(&stock_id) {
+            update(count);
+            return;
+        }
+    }
+    if let Ok(mut counts) = counters.write() {
+        update(counts.entry(stock_id).or_default());
+    }
+}
+
+/// Counters the service keeps for its metrics endpoint.
+#[derive(Default)]
+struct Metrics {
+    /// Keyed by stock id. Entries are added on a stock's first packet.
+    packets_sent: RwLock<HashMap<u64, AtomicU64>>,
+    /// Consecutive failed sends per stock id.
+    send_failures: RwLock<HashMap<u64, AtomicU64>>,
+    /// Unix time in milliseconds of each stock's last successful send.
+    last_sent_ms: RwLock<HashMap<u64, AtomicU64>>,
+    bind_failures: AtomicU64,
+    engine_ticks: AtomicU64,
+    /// Unix time in milliseconds the engine loop last ran; 0 before it
+    /// starts.
+    engine_heartbeat_ms: AtomicU64,
+    /// Times the watchdog replaced an engine thread that panicked.
+    engine_restarts: AtomicU64,
+    /// Unix time in milliseconds the engine's latest step began; 0 before
+    /// the first.
+    last_tick_ms: AtomicU64,
+    /// Stream frame payload bytes before and after compression.
+    stream_raw_bytes: AtomicU64,
+    stream_sent_bytes: AtomicU64,
+}
+
+impl Metrics {
+    fn packet_sent(&self, stock_id: u64) {
+        update_counter(&self.packets_sent, stock_id, |count| {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+        let now = wire::now_ms();
+        update_counter(&self.last_sent_ms, stock_id, |sent| {
+            sent.store(now, Ordering::Relaxed);
+        });
+    }
+
+    fn last_sent_ms(&self, stock_id: u64) -> Option<u64> {
+        self.last_sent_ms
+            .read()
+            .ok()?
+            .get(&stock_id)
+            .map(|sent| sent.load(Ordering::Relaxed))
+    }
+
+    /// Whether the engine has taken at least `warmup_ticks` steps.
+    fn warmed_up(&self, warmup_ticks: u64) -> bool {
+        self.engine_ticks.load(Ordering::Relaxed) >= warmup_ticks
+    }
+
+    /// Notes that the engine is about to step. Stored before the step's
+    /// values are published, so a broadcaster that sees them also sees
+    /// this.
+    fn tick_started(&self) {
+        self.last_tick_ms.store(wire::now_ms(), Ordering::Release);
+    }
+
+    /// Whether `phase` has passed since the latest tick began. `None`
+    /// always has.
+    fn past_phase(&self, phase: Option<Duration>) -> bool {
+        phase.is_none_or(|phase| {
+            let tick_ms = self.last_tick_ms.load(Ordering::Acquire);
+            wire::now_ms() >= tick_ms + phase.as_millis() as u64
+        })
+    }
+
+    fn frame_sent(&self, raw_len: usize, frame_len: usize) {
+        self.stream_raw_bytes
+            .fetch_add(raw_len as u64, Ordering::Relaxed);
+        self.stream_sent_bytes.fetch_add(
+            (frame_len - wire::FRAME_HEADER_LEN) as u64,
+            Ordering::Relaxed,
+        );
+    }
+
+    fn compression_ratio(&self) -> f64 {
+        let sent = self.stream_sent_bytes.load(Ordering::Relaxed);
+        if sent == 0 {
+            return 1.0;
+        }
+        self.stream_raw_bytes.load(Ordering::Relaxed) as f64 / sent as f64
+    }
+
+    fn heartbeat(&self) {
+        self.engine_heartbeat_ms
+            .store(wire::now_ms(), Ordering::Relaxed);
+    }
+
+    fn total_packets_sent(&self) -> u64 {
+        self.packets_sent.read().map_or(0, |counts| {
+            counts
+                .values()
+                .map(|count| count.load(Ordering::Relaxed))
+                .sum()
+        })
+    }
+
+    fn packets_sent(&self, stock_id: u64) -> u64 {
+        self.packets_sent
+            .read()
+            .ok()
+            .and_then(|counts| {
+                counts
+                    .get(&stock_id)
+                    .map(|count| count.load(Ordering::Relaxed))
+            })
+            .unwrap_or(0)
+    }
+
+    fn set_send_failures(&self, stock_id: u64, failures: u64) {
+        update_counter(&self.send_failures, stock_id, |count| {
+            count.store(failures, Ordering::Relaxed);
+        });
+    }
+
+    fn send_failures(&self, stock_id: u64) -> u64 {
+        self.send_failures
+            .read()
+            .ok()
+            .and_then(|counts| {
+                counts
+                    .get(&stock_id)
+                    .map(|count| count.load(Ordering::Relaxed))
+            })
+            .unwrap_or(0)
+    }
+
+    /// The Prometheus text for these counters, with `stocks`' current
+    /// values reported through `config`'s `output_value`.
+    fn render(
+        &self,
+        stocks: &[Stock],
+        sentiments: &Sentiments,
+        config: &SentimentConfig,
+    ) -> String {
+        use std::fmt::Write as _;
+
+        let snapshot = sentiments.load();
+        // A writer that panicked leaves its counts intact; keep serving them.
+        let packets_sent = self
+            .packets_sent
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP sentiment_packets_sent_total Packets sent per ticker."
+        );
+        let _ = writeln!(out, "# TYPE sentiment_packets_sent_total counter");
+        for stock in stocks {
+            let count = packets_sent
+                .get(&stock.id)
+                .map_or(0, |count| count.load(Ordering::Relaxed));
+            let _ = writeln!(
+                out,
+                "sentiment_packets_sent_total{{ticker=\"{}\"}} {}",
+                stock.ticker, count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP sentiment_bind_failures_total Broadcasters that failed to bind."
+        );
+        let _ = writeln!(out, "# TYPE sentiment_bind_failures_total counter");
+        let _ = writeln!(
+            out,
+            "sentiment_bind_failures_total {}",
+            self.bind_failures.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sentiment_engine_ticks_total Sentiment engine steps taken."
+        );
+        let _ = writeln!(out, "# TYPE sentiment_engine_ticks_total counter");
+        let _ = writeln!(
+            out,
+            "sentiment_engine_ticks_total {}",
+            self.engine_ticks.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sentiment_engine_restarts_total Engine threads restarted after a panic."
+        );
+        let _ = writeln!(out, "# TYPE sentiment_engine_restarts_total counter");
+        let _ = writeln!(
+            out,
+            "sentiment_engine_restarts_total {}",
+            self.engine_restarts.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sentiment_stream_bytes_total Stream frame payload bytes, before and after compression."
+        );
+        let _ = writeln!(out, "# TYPE sentiment_stream_bytes_total counter");
+        let _ = writeln!(
+            out,
+            "sentiment_stream_bytes_total{{stage=\"raw\"}} {}",
+            self.stream_raw_bytes.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "sentiment_stream_bytes_total{{stage=\"sent\"}} {}",
+            self.stream_sent_bytes.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sentiment_current_value Latest sentiment per ticker."
+        );
+        let _ = writeln!(out, "# TYPE sentiment_current_value gauge");
+        for stock in stocks {
+            let value = config.output_value(snapshot.get(&stock.id).copied().unwrap_or(0.0));
+            let _ = writeln!(
+                out,
+                "sentiment_current_value{{ticker=\"{}\"}} {}",
+                stock.ticker, value
+            );
+        }
+
+        out
+    }
+}
+
+/// Answers a single HTTP request on `stream`: `GET /metrics` gets the
+/// body produced by `render`, anything else a 404.
+#[cfg(feature = "metrics")]
+fn serve_metrics_request(stream: TcpStream, render: impl FnOnce() -> String) -> io::Result<()> {
+    use std::io::{BufRead, BufReader};
+
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    (&stream).write_all(response.as_bytes())
+}
+
+/// Reads one HTTP request from `stream` and, for a `GET`, answers with the
+/// `text/event-stream` headers and returns the writer to stream events to.
+/// Anything else gets a 405 and `None`. A request longer than
+/// `SSE_MAX_REQUEST_LEN`, or cut off before its blank line, is an
+/// `InvalidData` error.
+fn accept_sse_client(stream: TcpStream) -> io::Result<Option<BufWriter<TcpStream>>> {
+    use std::io::{BufRead, BufReader};
+
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    // A stalled client must not hold up the others on the writer thread.
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+
+    let mut reader = BufReader::new((&stream).take(SSE_MAX_REQUEST_LEN));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers up to the blank line
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "SSE request too long or incomplete",
+            ));
+        }
+        if header.trim().is_empty() {
+            break;
+        }
+    }
+
+    if request_line.split_whitespace().next() != Some("GET") {
+        (&stream).write_all(
+            b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )?;
+        return Ok(None);
+    }
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+    )?;
+    writer.flush()?;
+    Ok(Some(writer))
+}
+
+/// Formats `packet` as a Server-Sent Event named after its ticker.
+fn sse_event(packet: &SentimentPacket) -> String {
+    let json = String::from_utf8(wire::encode_packet(packet)).unwrap_or_default();
+    format!("event: {}\ndata: {}\n\n", packet.ticker, json)
+}
+
+/// Writes a line per stock every `config.broadcast_interval` to one TCP
+/// client until it disconnects or the service stops. With backfill, the
+/// lines are the history's ticks instead: all kept ones straight away,
+/// then each new one once. With `stream_compression`, a cycle's lines go
+/// out as one frame.
+fn stream_to_tcp_client(
+    stream: TcpStream,
+    stocks: &RwLock<Arc<Vec<Stock>>>,
+    sentiments: &Sentiments,
+    history: &TickHistory,
+    shutdown: &AtomicBool,
+    metrics: &Metrics,
+    config: &SentimentConfig,
+) {
+    // The listener is non-blocking; the per-client stream should block.
+    if stream.set_nonblocking(false).is_err() {
+        return;
+    }
+    let mut writer = BufWriter::new(stream);
+    let mut cursor = 0;
+
+    let mut write_cycle = |writer: &mut BufWriter<TcpStream>| -> io::Result<()> {
+        let packets: Vec<SentimentPacket> = if history.enabled() {
+            let (ticks, last) = history.since(cursor);
+            cursor = last;
+            ticks.iter().flat_map(|tick| tick.iter().cloned()).collect()
+        } else {
+            let snapshot = sentiments.load();
+            current_stocks(stocks)
+                .iter()
+                .map(|stock| {
+                    let value = snapshot.get(&stock.id).copied().unwrap_or(0.0);
+                    SentimentPacket::now(&stock.ticker, stock.id, config.output_value(value))
+                })
+                .collect()
+        };
+        if let Some(compression) = config.stream_compression {
+            if packets.is_empty() {
+                return Ok(());
+            }
+            let (frame, raw_len) =
+                wire::encode_frame(&packets, Some(compression), config.compression_threshold);
+            writer.write_all(&frame)?;
+            metrics.frame_sent(raw_len, frame.len());
+            return writer.flush();
+        }
+        for packet in &packets {
+            let mut line = wire::encode_packet(packet);
+            line.push(b'\n');
+            writer.write_all(&line)?;
+        }
+        writer.flush()
+    };
+
+    if history.enabled() && write_cycle(&mut writer).is_err() {
+        return;
+    }
+    while sleep_unless_shutdown(shutdown, config.broadcast_interval) {
+        if write_cycle(&mut writer).is_err() {
+            return;
+        }
+    }
+}
+
+/// Message a WebSocket client sends to restrict which tickers it receives.
+#[cfg(feature = "websocket")]
+#[derive(Debug, Deserialize)]
+struct SubscribeRequest {
+    subscribe: Vec<String>,
+}
+
+/// Runs one WebSocket connection: applies subscribe requests and forwards
+/// queued batches until the client goes away, is dropped for being slow
+/// (its queue sender disappears), or the service stops. With `compression`,
+/// each batch is sent as one binary frame.
+#[cfg(feature = "websocket")]
+fn serve_websocket_client(
+    stream: TcpStream,
+    rx: std::sync::mpsc::Receiver<Arc<Vec<SentimentPacket>>>,
+    shutdown: &AtomicBool,
+    metrics: &Metrics,
+    compression: Option<StreamCompression>,
+    threshold: usize,
+) {
+    use std::collections::HashSet;
+    use std::sync::mpsc::TryRecvError;
+    use tungstenite::{Error, Message};
+
+    if stream.set_nonblocking(false).is_err() {
+        return;
+    }
+    let Ok(mut socket) = tungstenite::accept(stream) else {
+        return;
+    };
+    // Short reads so the loop can interleave subscribe handling and sends;
+    // a write timeout stops a stalled client from pinning this thread.
+    let _ = socket
+        .get_ref()
+        .set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL));
+    let _ = socket
+        .get_ref()
+        .set_write_timeout(Some(Duration::from_secs(1)));
+    let mut tickers: Option<HashSet<String>> = None;
+
+    while !shutdown.load(Ordering::SeqCst) {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if let Ok(request) = serde_json::from_str::<SubscribeRequest>(&text) {
+                    tickers = Some(request.subscribe.into_iter().collect());
+                }
+            }
+            Ok(Message::Close(_)) => return,
+            Ok(_) => {}
+            Err(Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(_) => return,
+        }
+
+        let batch = match rx.try_recv() {
+            Ok(batch) => batch,
+            Err(TryRecvError::Empty) => continue,
+            Err(TryRecvError::Disconnected) => break,
+        };
+        let wanted =
+            |packet: &&SentimentPacket| tickers.as_ref().is_none_or(|t| t.contains(&packet.ticker));
+        if compression.is_some() {
+            let packets: Vec<SentimentPacket> = batch.iter().filter(wanted).cloned().collect();
+            if packets.is_empty() {
+                continue;
+            }
+            let (frame, raw_len) = wire::encode_frame(&packets, compression, threshold);
+            let frame_len = frame.len();
+            if socket.send(Message::binary(frame)).is_err() {
+                return;
+            }
+            metrics.frame_sent(raw_len, frame_len);
+            continue;
+        }
+        for packet in batch.iter().filter(wanted) {
+            let json = String::from_utf8(wire::encode_packet(packet)).unwrap_or_default();
+            if socket.write(Message::text(json)).is_err() {
+                return;
+            }
+        }
+        if socket.flush().is_err() {
+            return;
+        }
+    }
+    let _ = socket.close(None);
+}
+
+/// Resolves where each stock's packets are sent on a broadcast cycle.
+#[derive(Clone)]
+struct Destinations {
+    transport: Transport,
+    subscribers: Arc<RwLock<HashMap<u64, Vec<SocketAddr>>>>,
+}
+
+impl Destinations {
+    /// Replaces the contents of `addrs` with `stock`'s current destinations.
+    /// Broadcasters keep one `addrs` for their lifetime, so this only
+    /// allocates when the list grows.
+    fn fill(&self, stock: &Stock, addrs: &mut Vec<SocketAddr>) {
+        addrs.clear();
+        match &self.transport {
+            Transport::Multicast { addr, .. } => {
+                addrs.push(SocketAddr::new(*addr, stock.sentiment_port));
+            }
+            Transport::Unicast { subscribers } => addrs.extend(
+                subscribers
+                    .iter()
+                    .map(|subscriber| SocketAddr::new(subscriber.ip(), stock.sentiment_port)),
+            ),
+            Transport::InProcess => {}
+        }
+        if let Ok(registered) = self.subscribers.read() {
+            addrs.extend(registered.get(&stock.id).into_iter().flatten());
+        }
+    }
+}
+
+/// Where a broadcaster sends each encoded packet. Sending is fire and
+/// forget: a sink deals with its own failures. One sink may be shared by
+/// several broadcasters, so it is told which stock each payload is for.
+pub trait Sink: Send + Sync + 'static {
+    fn send(&self, stock: &Stock, payload: &[u8]);
+
+    /// Like `send`, also given the packet `payload` encodes. Broadcasters
+    /// call this; sinks that want the packet rather than its encoding
+    /// override it.
+    fn send_packet(&self, stock: &Stock, packet: &SentimentPacket, payload: &[u8]) {
+        let _ = packet;
+        self.send(stock, payload);
+    }
+}
+
+/// Sends each payload to every one of its sinks, in order.
+struct FanOut(Vec<Arc<dyn Sink>>);
+
+impl Sink for FanOut {
+    fn send(&self, stock: &Stock, payload: &[u8]) {
+        for sink in &self.0 {
+            sink.send(stock, payload);
+        }
+    }
+
+    fn send_packet(&self, stock: &Stock, packet: &SentimentPacket, payload: &[u8]) {
+        for sink in &self.0 {
+            sink.send_packet(stock, packet, payload);
+        }
+    }
+}
+
+/// Delivers broadcast packets to receivers in the same process, without
+/// sockets or encoding. Clones share one bus. Each receiver holds up to
+/// `SUBSCRIBER_QUEUE_CAPACITY` packets; further packets for it are dropped
+/// until it is drained, and dropping it unsubscribes it.
+#[derive(Clone, Default)]
+pub struct InProcessBus {
+    receivers: Arc<Mutex<Vec<BusReceiver>>>,
+}
+
+struct BusReceiver {
+    /// `None` receives every stock.
+    stock_id: Option<u64>,
+    tx: SyncSender<SentimentPacket>,
+}
+
+impl InProcessBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A receiver for every stock's packets from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<SentimentPacket> {
+        self.add_receiver(None)
+    }
+
+    /// A receiver for `stock_id`'s packets only.
+    pub fn subscribe_stock(&self, stock_id: u64) -> mpsc::Receiver<SentimentPacket> {
+        self.add_receiver(Some(stock_id))
+    }
+
+    fn add_receiver(&self, stock_id: Option<u64>) -> mpsc::Receiver<SentimentPacket> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE_CAPACITY);
+        if let Ok(mut receivers) = self.receivers.lock() {
+            receivers.push(BusReceiver { stock_id, tx });
+        }
+        rx
+    }
+
+    /// Offers `packet` to every receiver of its stock.
+    pub fn publish(&self, packet: &SentimentPacket) {
+        let Ok(mut receivers) = self.receivers.lock() else {
+            return;
+        };
+        receivers.retain(|receiver| {
+            if receiver
+                .stock_id
+                .is_some_and(|stock_id| stock_id != packet.stock_id)
+            {
+                return true;
+            }
+            !matches!(
+                receiver.tx.try_send(packet.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+    }
+}
+
+impl Sink for InProcessBus {
+    /// Decodes `payload` and publishes it, for callers that only have the
+    /// encoding. Payloads `wire::decode_any` doesn't recognise are dropped.
+    fn send(&self, _stock: &Stock, payload: &[u8]) {
+        if let Ok(packet) = wire::decode_any(payload) {
+            self.publish(&packet);
+        }
+    }
+
+    fn send_packet(&self, _stock: &Stock, packet: &SentimentPacket, _payload: &[u8]) {
+        self.publish(packet);
+    }
+}
+
+/// Sends over UDP to each of a stock's `Destinations`, counting the outcome
+/// in `Metrics` and rebinding after `max_send_failures` failures in a row.
+struct UdpSink {
+    destinations: Destinations,
+    metrics: Arc<Metrics>,
+    config: SentimentConfig,
+    /// Only the broadcaster thread sends, so this is never contended.
+    state: Mutex<UdpSinkState>,
+}
+
+struct UdpSinkState {
+    socket: UdpSocket,
+    /// Reused across sends so the broadcast loop doesn't allocate.
+    addrs: Vec<SocketAddr>,
+    /// Failure streaks by stock id.
+    errors: HashMap<u64, SendErrors>,
+}
+
+impl UdpSink {
+    fn new(
+        socket: UdpSocket,
+        destinations: Destinations,
+        metrics: Arc<Metrics>,
+        config: SentimentConfig,
+    ) -> Self {
+        Self {
+            destinations,
+            metrics,
+            config,
+            state: Mutex::new(UdpSinkState {
+                socket,
+                addrs: Vec::new(),
+                errors: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl Sink for UdpSink {
+    fn send(&self, stock: &Stock, payload: &[u8]) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let UdpSinkState {
+            socket,
+            addrs,
+            errors,
+        } = &mut *state;
+        let errors = errors.entry(stock.id).or_default();
+        self.destinations.fill(stock, addrs);
+        for &addr in addrs.iter() {
+            let result = socket.send_to(payload, addr);
+            if track_send(&self.metrics, errors, stock, addr, result, &self.config) {
+                let transport = &self.destinations.transport;
+                if let Some(fresh) = rebind_broadcast_socket(stock, transport, &self.config) {
+                    *socket = fresh;
+                }
+            }
+        }
+    }
+}
+
+/// One `subscribe` receiver's sending half and how often it wants ticks.
+struct Subscription {
+    tx: SyncSender<SentimentPacket>,
+    /// `None` forwards every tick.
+    every: Option<Duration>,
+    /// When the next tick should be forwarded.
+    due: Instant,
+}
+
+impl Subscription {
+    /// Whether a tick at `now` should be forwarded, moving `due` on by one
+    /// interval if so. A subscriber that fell more than an interval behind
+    /// resumes from `now` rather than catching up in a burst.
+    fn take_due(&mut self, now: Instant) -> bool {
+        let Some(every) = self.every else {
+            return true;
+        };
+        if now < self.due {
+            return false;
+        }
+        let next = self.due + every;
+        self.due = if next > now { next } else { now + every };
+        true
+    }
+}
+
+/// The last `capacity` engine ticks, each kept as one packet per stock and
+/// numbered from 1 in the packets' `seq`. Stream clients keep a cursor,
+/// the last tick they sent, and ask for everything after it.
+struct TickHistory {
+    capacity: usize,
+    ticks: Mutex<TickRing>,
+}
+
+#[derive(Default)]
+struct TickRing {
+    last_seq: u64,
+    ticks: VecDeque<(u64, Arc<Vec<SentimentPacket>>)>,
+}
+
+impl TickHistory {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ticks: Mutex::new(TickRing::default()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Numbers `samples` as the next tick and keeps them, dropping the
+    /// oldest tick beyond `capacity`.
+    fn push(&self, mut samples: Vec<SentimentPacket>) {
+        if !self.enabled() {
+            return;
+        }
+        let Ok(mut ring) = self.ticks.lock() else {
+            return;
+        };
+        ring.last_seq += 1;
+        let seq = ring.last_seq;
+        for sample in &mut samples {
+            sample.seq = seq;
+        }
+        ring.ticks.push_back((seq, Arc::new(samples)));
+        while ring.ticks.len() > self.capacity {
+            ring.ticks.pop_front();
+        }
+    }
+
+    /// Kept ticks numbered above `after`, oldest first, and the number of
+    /// the newest (`after` if there are none).
+    fn since(&self, after: u64) -> (Vec<Arc<Vec<SentimentPacket>>>, u64) {
+        let Ok(ring) = self.ticks.lock() else {
+            return (Vec::new(), after);
+        };
+        let ticks = ring
+            .ticks
+            .iter()
+            .filter(|(seq, _)| *seq > after)
+            .map(|(_, samples)| Arc::clone(samples))
+            .collect();
+        (ticks, ring.last_seq.max(after))
+    }
+}
+
+/// A stock's outgoing packet and its encoded bytes, both reused from one
+/// send to the next so the broadcast loop doesn't allocate.
+struct Outgoing {
+    packet: SentimentPacket,
+    buf: Vec<u8>,
+}
+
+impl Outgoing {
+    fn new(stock: &Stock) -> Self {
+        Self {
+            packet: SentimentPacket::now(&stock.ticker, stock.id, 0.0),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Stamps the next packet in the stock's sequence with `value`, `regime`
+    /// and the current time, and returns it encoded as `format`.
+    fn next(&mut self, value: f64, regime: Option<Regime>, format: WireFormat) -> &[u8] {
+        self.packet.value = value;
+        self.packet.regime = regime;
+        self.packet.timestamp_ms = wire::now_ms();
+        self.packet.seq += 1;
+        format.encode_into(&self.packet, &mut self.buf);
+        &self.buf
+    }
+
+    /// The packet `next` last stamped and its encoding.
+    fn last(&self) -> (&SentimentPacket, &[u8]) {
+        (&self.packet, &self.buf)
+    }
+}
+
+/// Sleeps for `duration` in slices of at most `SHUTDOWN_POLL_INTERVAL`,
+/// returning early once `shutdown` is set. Returns `true` if the caller
+/// should keep running.
+fn sleep_unless_shutdown(shutdown: &AtomicBool, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if shutdown.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(SHUTDOWN_POLL_INTERVAL));
+    }
+}
+
+/// Cholesky-decomposes a symmetric matrix into a lower-triangular `L` with
+/// `L * L^T == matrix`. Zero pivots are allowed so that semidefinite
+/// matrices (e.g. perfectly correlated stocks) are accepted.
+fn cholesky(matrix: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, CorrelationError> {
+    let n = matrix.len();
+    let mut factor: Vec<Vec<f64>> = vec![vec![0.0; n]; n];
+
+    for j in 0..n {
+        let pivot = matrix[j][j] - (0..j).map(|k| factor[j][k].powi(2)).sum::<f64>();
+        if pivot < -CHOLESKY_EPSILON {
+            return Err(CorrelationError::NotPositiveSemidefinite);
+        }
+        let diagonal = pivot.max(0.0).sqrt();
+        factor[j][j] = diagonal;
+
+        for i in j + 1..n {
+            let residual = matrix[i][j] - (0..j).map(|k| factor[i][k] * factor[j][k]).sum::<f64>();
+            factor[i][j] = if diagonal > CHOLESKY_EPSILON {
+                residual / diagonal
+            } else if residual.abs() <= CHOLESKY_EPSILON {
+                0.0
+            } else {
+                return Err(CorrelationError::NotPositiveSemidefinite);
+            };
+        }
+    }
+
+    Ok(factor)
+}
+
+/// Owns the RNG and shared state handles for the sentiment update loop, so a
+/// tick can be driven either by the engine thread or directly from tests.
+///
+/// Each stock follows its own Ornstein-Uhlenbeck process that mean-reverts
+/// toward the market mood. The `sentiments` map holds that per-stock state
+/// directly, so what is published is exactly what the next tick evolves.
+/// Stocks in a sector revert toward the market mood plus their sector's
+/// mood, which follows its own process around zero. Prices follow the new
+/// sentiments; they never feed back into them.
+#[derive(Clone)]
+struct SentimentEngine {
+    /// Ticks kept for stream clients, filled after each step.
+    history: Arc<TickHistory>,
+    subscriptions: Subscriptions,
+    /// Told the pre-tick values after each step, if interpolating.
+    interpolation: Option<Arc<Interpolation>>,
+    stocks: StockList,
+    config: SentimentConfig,
+    sentiments: Arc<Sentiments>,
+    market_mood: Arc<RwLock<f64>>,
+    sector_moods: Arc<RwLock<HashMap<String, f64>>>,
+    markov_regime: Arc<RwLock<Regime>>,
+    prices: Arc<RwLock<HashMap<u64, f64>>>,
+    rng: StdRng,
+    /// Jump count and jump size distributions, present only when
+    /// `jump_intensity` is positive so a jump-free config draws exactly the
+    /// same random numbers as before jumps existed.
+    jumps: Option<(Poisson<f64>, Normal<f64>)>,
+    /// News event count per tick, present only when `news_rate` is
+    /// positive, for the same reason as `jumps`.
+    news: Option<Poisson<f64>>,
+    news_subscriptions: NewsSubscriptions,
+    correlation: Option<Correlation>,
+    callbacks: Arc<Vec<UpdateCallback>>,
+}
+
+impl SentimentEngine {
+    /// An engine stepping `service`'s shared state.
+    fn new(service: &SentimentService) -> Self {
+        let config = service.config.clone();
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let jumps = (config.jump_intensity > 0.0).then(|| {
+            (
+                Poisson::new(config.jump_intensity).unwrap(),
+                Normal::new(0.0, config.jump_scale * config.half_width()).unwrap(),
+            )
+        });
+        let news = (config.news_rate > 0.0)
+            .then(|| Poisson::new(config.news_rate * config.tick_interval.as_secs_f64()).unwrap());
+
+        Self {
+            stocks: Arc::clone(&service.stocks),
+            config,
+            sentiments: Arc::clone(&service.sentiments),
+            market_mood: Arc::clone(&service.market_mood),
+            sector_moods: Arc::clone(&service.sector_moods),
+            markov_regime: Arc::clone(&service.markov_regime),
+            prices: Arc::clone(&service.prices),
+            rng,
+            jumps,
+            news,
+            news_subscriptions: Arc::clone(&service.news_subscriptions),
+            correlation: service.correlation.clone(),
+            callbacks: Arc::clone(&service.callbacks),
+            history: Arc::clone(&service.history),
+            subscriptions: Arc::clone(&service.subscriptions),
+            interpolation: service.interpolation.clone(),
+        }
+    }
+
+    /// A copy of this engine to replace one that panicked. It draws from a
+    /// new RNG seeded from this engine's, so a seeded run stays
+    /// reproducible without replaying the draws that led to the panic.
+    /// Clears the poison the panic may have left on the shared locks.
+    fn respawn(&mut self) -> SentimentEngine {
+        self.market_mood.clear_poison();
+        self.sector_moods.clear_poison();
+        self.markov_regime.clear_poison();
+        self.prices.clear_poison();
+        self.subscriptions.clear_poison();
+        self.news_subscriptions.clear_poison();
+        self.history.ticks.clear_poison();
+        let mut engine = self.clone();
+        engine.rng = StdRng::from_rng(&mut self.rng).expect("StdRng seeds from any RNG");
+        engine
+    }
+
+    /// Offers `samples` to every `subscribe` receiver that is due one. A
+    /// full receiver misses them; a dropped one is unsubscribed.
+    fn publish(&self, samples: &[SentimentPacket]) {
+        let Ok(mut subscriptions) = self.subscriptions.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        subscriptions.retain_mut(|subscription| {
+            if !subscription.take_due(now) {
+                return true;
+            }
+            for sample in samples {
+                match subscription.tx.try_send(sample.clone()) {
+                    Ok(()) | Err(TrySendError::Full(_)) => {}
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            true
+        });
+    }
+
+    /// Offers `events` to every `subscribe_news` receiver, dropping them for
+    /// full ones and unsubscribing dropped ones.
+    fn publish_news(&self, events: &[NewsEvent]) {
+        let Ok(mut subscriptions) = self.news_subscriptions.lock() else {
+            return;
+        };
+        subscriptions.retain(|tx| {
+            events.iter().all(|event| {
+                !matches!(
+                    tx.try_send(event.clone()),
+                    Err(TrySendError::Disconnected(_))
+                )
+            })
+        });
+    }
+
+    /// The news events arriving this tick, each for a stock picked
+    /// uniformly from `stocks` with a headline kind picked uniformly and a
+    /// shock of `news_impact` times the half-width, scaled by a uniform
+    /// draw from [0.5, 1.5] and signed by the headline.
+    fn draw_news(&mut self, stocks: &[Stock]) -> Vec<NewsEvent> {
+        let Some(count_dist) = &self.news else {
+            return Vec::new();
+        };
+        if stocks.is_empty() {
+            return Vec::new();
+        }
+        let count = count_dist.sample(&mut self.rng) as usize;
+        let scale = self.config.news_impact * self.config.half_width();
+        (0..count)
+            .map(|_| {
+                let stock = &stocks[self.rng.gen_range(0..stocks.len())];
+                let headline_kind =
+                    HeadlineKind::ALL[self.rng.gen_range(0..HeadlineKind::ALL.len())];
+                let size = scale * self.rng.gen_range(0.5..=1.5);
+                NewsEvent {
+                    stock_id: stock.id,
+                    ticker: stock.ticker.clone(),
+                    headline_kind,
+                    sentiment_impact: if headline_kind.is_positive() {
+                        size
+                    } else {
+                        -size
+                    },
+                    timestamp_ms: wire::now_ms(),
+                }
+            })
+            .collect()
+    }
+
+    /// One standard normal draw per stock, in `stocks` order, correlated
+    /// through the Cholesky factor when one is configured for exactly these
+    /// stocks. Callers scale by `volatility * sqrt(dt)` so variance per unit
+    /// time is independent of the tick rate.
+    fn stock_noise(&mut self, stocks: &[Stock]) -> Vec<f64> {
+        let independent: Vec<f64> = (0..stocks.len())
+            .map(|_| self.rng.sample(StandardNormal))
+            .collect();
+        match &self.correlation {
+            Some(correlation)
+                if stocks
+                    .iter()
+                    .map(|stock| stock.id)
+                    .eq(correlation.ids.iter().copied()) =>
+            {
+                correlation
+                    .factor
+                    .iter()
+                    .map(|row| row.iter().zip(&independent).map(|(l, z)| l * z).sum())
+                    .collect()
+            }
+            _ => independent,
+        }
+    }
+
+    /// Steps the mood of every sector among `stocks`, in name order so that
+    /// seeded runs are reproducible, and returns the new moods. Sectors no
+    /// stock names any more are dropped, and new ones start at zero.
+    fn step_sectors(&mut self, stocks: &[Stock], dt: f64) -> HashMap<String, f64> {
+        let sectors: BTreeSet<&str> = stocks
+            .iter()
+            .filter_map(|stock| stock.sector.as_deref())
+            .collect();
+        let Ok(mut moods) = self.sector_moods.write() else {
+            return HashMap::new();
+        };
+        moods.retain(|sector, _| sectors.contains(sector.as_str()));
+        for sector in sectors {
+            let noise = self.rng.sample(StandardNormal);
+            let mood = moods.entry(sector.to_string()).or_insert(0.0);
+            *mood = finite_or(
+                step_sector_mood(*mood, &self.config, dt, noise),
+                0.0,
+                sector,
+            );
+        }
+        moods.clone()
+    }
+
+    /// Moves the `regimes` chain on by one tick, if there is one, and
+    /// returns the mean the mood reverts toward this tick.
+    fn step_regime(&mut self) -> f64 {
+        let Some(model) = &self.config.regimes else {
+            return self.config.mean;
+        };
+        let Ok(mut regime) = self.markov_regime.write() else {
+            return self.config.mean;
+        };
+        if self.rng.gen::<f64>() >= model.stay(*regime) {
+            *regime = match *regime {
+                Regime::Bearish => Regime::Bullish,
+                _ => Regime::Bearish,
+            };
+        }
+        model.mean(*regime)
+    }
+
+    fn step(&mut self) {
+        let dt = self.config.tick_interval.as_secs_f64();
+        let stocks = current_stocks(&self.stocks);
+        let stock_draws = self.stock_noise(&stocks);
+        let sector_moods = self.step_sectors(&stocks, dt);
+        let mean = self.step_regime();
+        let news = self.draw_news(&stocks);
+
+        let mut mood = self.market_mood.write().unwrap();
+        let noise = self.rng.sample(StandardNormal);
+        let jump = match &self.jumps {
+            Some((count_dist, size_dist)) => {
+                let count = count_dist.sample(&mut self.rng) as u64;
+                (0..count).map(|_| size_dist.sample(&mut self.rng)).sum()
+            }
+            None => 0.0,
+        };
+        *mood = step_mood(*mood, mean, &self.config, dt, noise, jump);
+        *mood = finite_or(*mood, self.config.mean, "market mood");
+
+        let mut updates = Vec::with_capacity(stocks.len());
+        let mut samples = Vec::new();
+        let subscribed = self
+            .subscriptions
+            .lock()
+            .is_ok_and(|subscriptions| !subscriptions.is_empty());
+        publish_tick(
+            &self.sentiments,
+            self.interpolation.as_deref(),
+            |sentiment_map| {
+                updates.clear();
+                samples.clear();
+                for (stock, &draw) in stocks.iter().zip(&stock_draws) {
+                    let sector_mood = stock
+                        .sector
+                        .as_ref()
+                        .and_then(|sector| sector_moods.get(sector))
+                        .unwrap_or(&0.0);
+                    let target = *mood + sector_mood;
+                    if let Some(state) = sentiment_map.get_mut(&stock.id) {
+                        *state = step_stock(*state, target, draw, &self.config, stock.id, dt);
+                        for event in news.iter().filter(|event| event.stock_id == stock.id) {
+                            *state = shocked(*state, event.sentiment_impact, &self.config);
+                        }
+                        *state = finite_or(*state, self.config.mean, &stock.ticker);
+                        updates.push((stock.id, *state));
+                        if self.history.enabled() || subscribed {
+                            let value = self.config.output_value(*state);
+                            samples.push(SentimentPacket::now(&stock.ticker, stock.id, value));
+                        }
+                    }
+                }
+            },
+        );
+        drop(mood);
+        if subscribed {
+            self.publish(&samples);
+        }
+        if !news.is_empty() {
+            self.publish_news(&news);
+        }
+        self.history.push(samples);
+
+        if let Ok(mut prices) = self.prices.write() {
+            for &(stock_id, sentiment) in &updates {
+                if let Some(price) = prices.get_mut(&stock_id) {
+                    *price = step_price(*price, sentiment, &self.config, dt);
+                }
+            }
+        }
+
+        for &(stock_id, value) in &updates {
+            for callback in self.callbacks.iter() {
+                callback(stock_id, value);
+            }
+        }
+    }
+}
+
+/// A phase in [0, 1) for a stock without one configured, spread evenly
+/// over stock ids by SplitMix64's finaliser.
+fn hashed_phase(stock_id: u64) -> f64 {
+    let mut z = stock_id.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// `value`, or `fallback` if it is NaN or infinite, so that one bad value
+/// can't spread through the engine's state. Clamping doesn't catch NaN.
+/// Logs the reset against `what`.
+fn finite_or(value: f64, fallback: f64, what: &str) -> f64 {
+    if value.is_finite() {
+        return value;
+    }
+    warn!("Reset {} from {} to {}", what, value, fallback);
+    fallback
+}
+
+/// One Euler step of the market mood over `dt` seconds: revert toward
+/// `mean` at `reversion_speed`, add `noise` (a standard normal draw) scaled
+/// by `volatility * sqrt(dt)`, add any `jump`, and clamp to the sentiment
+/// range.
+fn step_mood(
+    mood: f64,
+    mean: f64,
+    config: &SentimentConfig,
+    dt: f64,
+    noise: f64,
+    jump: f64,
+) -> f64 {
+    let reversion = config.reversion_speed * (mean - mood) * dt;
+    let noise = config.volatility * config.half_width() * noise * dt.sqrt();
+    config.clamp(mood + reversion + noise + jump)
+}
+
+/// One Euler step of a sector's mood over `dt` seconds. Like `step_mood`,
+/// but it reverts toward zero, since it is an offset from the market mood,
+/// and is clamped to plus or minus the range's half-width.
+fn step_sector_mood(mood: f64, config: &SentimentConfig, dt: f64, noise: f64) -> f64 {
+    let scale = config.half_width();
+    let reversion = -config.reversion_speed * mood * dt;
+    let noise = config.volatility * scale * noise * dt.sqrt();
+    (mood + reversion + noise).clamp(-scale, scale)
+}
+
+/// One Euler step of a stock's sentiment `state` over `dt` seconds: revert
+/// toward `mood` plus its baseline offset (and its override's `mean` less
+/// `config`'s) and add `stock_noise` (a standard normal draw), using
+/// `stock_id`'s overrides from `config`. The result is clamped to `config`'s
+/// sentiment range.
+fn step_stock(
+    state: f64,
+    mood: f64,
+    stock_noise: f64,
+    config: &SentimentConfig,
+    stock_id: u64,
+    dt: f64,
+) -> f64 {
+    let stock_config = config.for_stock(stock_id);
+    let scale = config.half_width();
+    let target = mood + (stock_config.mean - config.mean) + stock_config.baseline_offset * scale;
+    let reversion = stock_config.reversion_speed * (target - state) * dt;
+    let noise = stock_config.volatility * scale * stock_noise * dt.sqrt();
+    config.clamp(state + reversion + noise)
+}
+
+/// `state` after a shock of `magnitude`, as applied by `inject_shock` and
+/// by news events, clamped to `config`'s sentiment range.
+fn shocked(state: f64, magnitude: f64, config: &SentimentConfig) -> f64 {
+    config.clamp(state + magnitude)
+}
+
+/// Compounds `price` over `dt` seconds at `config.price_drift` times
+/// `sentiment` mapped onto [-1, 1], so a neutral stock holds its price and
+/// the price never goes negative.
+fn step_price(price: f64, sentiment: f64, config: &SentimentConfig, dt: f64) -> f64 {
+    price * (config.price_drift * config.normalize(sentiment) * dt).exp()
+}
+
+/// Command-line options for the `sentiment_service` binary.
+#[derive(Debug, clap::Parser)]
+#[command(about = "Broadcasts simulated per-stock sentiment over UDP")]
+struct Cli {
+    /// TOML config file; replaces the engine options below when given
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Stock list to broadcast; `-` reads it from stdin
+    #[arg(long, default_value = "stock.csv")]
+    csv: String,
+    /// Milliseconds between engine steps
+    #[arg(long, default_value_t = 100)]
+    tick_ms: u64,
+    /// Long-run level the market mood reverts to
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    mean: f64,
+    /// Mean reversion speed, in [0, 1]
+    #[arg(long, default_value_t = 0.05)]
+    reversion: f64,
+    /// Noise per unit time
+    #[arg(long, default_value_t = 0.5)]
+    volatility: f64,
+    /// Multicast group to send to, IPv4 or IPv6
+    #[arg(long)]
+    multicast_addr: Option<IpAddr>,
+    /// Seed for a reproducible stream
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Field separator of the stock list
+    #[arg(long, default_value_t = ',')]
+    csv_delimiter: char,
+}
+
+impl Cli {
+    fn sentiment_config(&self) -> Result<SentimentConfig, Box<dyn std::error::Error>> {
+        if let Some(path) = &self.config {
+            return SentimentConfig::from_toml(path);
+        }
+        let mut config = SentimentConfig {
+            tick_interval: Duration::from_millis(self.tick_ms),
+            mean: self.mean,
+            reversion_speed: self.reversion,
+            volatility: self.volatility,
+            seed: self.seed,
+            csv_delimiter: self.csv_delimiter,
+            ..SentimentConfig::default()
+        };
+        if let Some(addr) = self.multicast_addr {
+            config.transport = Transport::Multicast {
+                addr,
+                ttl: MULTICAST_TTL,
+            };
+            config.ip_family = IpFamily::of(addr);
+        }
+        Ok(config)
+    }
+}
+
+// CLI runner
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let cli = <Cli as clap::Parser>::parse();
+    let config = cli.sentiment_config()?;
+    info!("Starting with {}", config);
+    let service = if cli.csv == "-" {
+        SentimentService::from_csv_reader(io::stdin().lock(), Some(config))?
+    } else {
+        SentimentService::from_csv(&cli.csv, Some(config))?
+    };
+
+    let handle = service.start();
+    let failed = handle.failed_bindings().len();
+    if failed > 0 {
+        warn!(
+            "[FAIL] {} of {} broadcasters failed to start",
+            failed,
+            handle.bindings().len()
+        );
+    } else {
+        info!("[OK] Sentiment microservice running");
+    }
+
+    #[cfg(feature = "metrics")]
+    service.start_metrics_server(SocketAddr::from(([0, 0, 0, 0], METRICS_PORT)))?;
+
+    // Ctrl-C and SIGTERM both end the run cleanly
+    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = shutdown_tx.send(());
+    })?;
+
+    for line in run_until_shutdown(&service, handle, &shutdown_rx) {
+        info!("{}", line);
+    }
+    info!("[OK] Sentiment microservice stopped");
+    Ok(())
+}
+
+/// Blocks until `shutdown` receives a message or its sender is dropped,
+/// then stops the service, waits for its threads and returns one summary
+/// line per stock.
+fn run_until_shutdown(
+    service: &SentimentService,
+    handle: ServiceHandle,
+    shutdown: &std::sync::mpsc::Receiver<()>,
+) -> Vec<String> {
+    let _ = shutdown.recv();
+    info!("Shutting down");
+    service.stop();
+    handle.join();
+
+    service
+        .stocks()
+        .iter()
+        .map(|stock| {
+            format!(
+                "ticker={} packets_sent={}",
+                stock.ticker,
+                service.packets_sent(stock.id)
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Counts each thread's heap allocations, so that tests running in
+    /// parallel don't disturb each other's counts.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    fn write_temp_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("sentiment_test_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn create_test_stocks() -> Vec<Stock> {
+        vec![
+            Stock {
+                ticker: "AAPL".to_string(),
+                id: 1,
+                company_name: "Apple Inc.".to_string(),
+                total_float: 15_982_000_000,
+                initial_price: 195.37,
+                sentiment_port: 18001,
+                initial_sentiment: None,
+                sector: None,
+            },
+            Stock {
+                ticker: "GOOGL".to_string(),
+                id: 2,
+                company_name: "Alphabet Inc.".to_string(),
+                total_float: 15_982_000_000,
+                initial_price: 2800.0,
+                sentiment_port: 18002,
+                initial_sentiment: None,
+                sector: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_service_creation() {
+        let stocks = create_test_stocks();
+        let service = SentimentService::new(stocks, None).unwrap();
+
+        assert_eq!(service.try_get_sentiment(1), Some(0.0));
+        assert_eq!(service.try_get_sentiment(2), Some(0.0));
+        assert_eq!(service.try_get_sentiment(999), None); // Non-existent stock
+    }
+
+    #[test]
+    fn test_initial_sentiment_seeds_from_csv() {
+        let path = write_temp_file(
+            "initial_sentiment.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port,initial_sentiment\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,3001,0.4\n\
+             GOOGL,2,Alphabet Inc.,15982000000,2800.0,3002,\n\
+             MSFT,3,Microsoft Corp.,7430000000,420.0,3003,-3.0\n",
+        );
+
+        let service = SentimentService::from_csv(path.to_str().unwrap(), None).unwrap();
+        assert_eq!(service.get_sentiment(1), 0.4);
+        assert_eq!(service.get_sentiment(2), 0.0);
+        assert_eq!(service.get_sentiment(3), -1.0);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_cli_builds_config() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from([
+            "sentiment_service",
+            "--csv",
+            "stocks/nyse.csv",
+            "--tick-ms",
+            "50",
+            "--mean",
+            "-0.25",
+            "--reversion",
+            "0.2",
+            "--volatility",
+            "0.1",
+            "--multicast-addr",
+            "ff02::123",
+            "--seed",
+            "9",
+        ])
+        .unwrap();
+        assert_eq!(cli.csv, "stocks/nyse.csv");
+
+        let config = cli.sentiment_config().unwrap();
+        assert_eq!(config.tick_interval, Duration::from_millis(50));
+        assert_eq!(config.mean, -0.25);
+        assert_eq!(config.reversion_speed, 0.2);
+        assert_eq!(config.volatility, 0.1);
+        assert_eq!(config.transport, Transport::multicast(IpFamily::V6));
+        assert_eq!(config.ip_family, IpFamily::V6);
+        assert_eq!(config.seed, Some(9));
+        assert_eq!(config.validate(), Ok(()));
+
+        let defaults = Cli::try_parse_from(["sentiment_service"]).unwrap();
+        assert_eq!(defaults.csv, "stock.csv");
+        assert_eq!(
+            defaults.sentiment_config().unwrap().transport,
+            Transport::default()
+        );
+    }
+
+    #[test]
+    fn test_config_toml_round_trip() {
+        let mut config = SentimentConfig {
+            tick_interval: Duration::from_millis(250),
+            mean: 0.1,
+            seed: Some(42),
+            scale: SentimentScale::Unit,
+            broadcast_mode: BroadcastMode::Shared,
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:9000".parse().unwrap()],
+            },
+            warmup_ticks: 20,
+            ..SentimentConfig::default()
+        };
+        config.per_stock.insert(
+            7,
+            SentimentConfig {
+                volatility: 0.05,
+                ..SentimentConfig::default()
+            },
+        );
+
+        let text = toml::to_string(&config).unwrap();
+        assert!(text.contains("tick_interval_ms = 250"), "{}", text);
+        let path = write_temp_file("config.toml", &text);
+        assert_eq!(SentimentConfig::from_toml(&path).unwrap(), config);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_config_json_round_trip() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(40),
+            seed: Some(3),
+            quantum: Some(0.01),
+            ..SentimentConfig::default()
+        };
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["tick_interval_ms"], 40);
+        assert!(json.get("tick_interval").is_none());
+        let back: SentimentConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(back, config);
+    }
+
+    #[test]
+    fn test_config_display_is_compact() {
+        let config = SentimentConfig {
+            seed: Some(7),
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:9000".parse().unwrap()],
+            },
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.to_string(),
+            "tick=100ms mean=0 reversion=0.5 volatility=0.2 range=[-1, 1] seed=7 \
+             scale=Signed format=Json mode=PerStock transport=1 unicast subscribers"
+        );
+        let entropy = SentimentConfig::default().to_string();
+        assert!(entropy.contains("seed=entropy"), "{}", entropy);
+    }
+
+    #[test]
+    fn test_config_toml_is_validated() {
+        let path = write_temp_file(
+            "bad_config.toml",
+            "tick_interval_ms = 50\nreversion_speed = 2.0\n",
+        );
+        let err = SentimentConfig::from_toml(&path).err().unwrap();
+        assert!(err.to_string().contains("reversion speed"), "{}", err);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_from_csv_reader_loads_in_memory_csv() {
+        let csv = "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+                   AAPL,1,Apple Inc.,15982000000,195.37,18001\n\
+                   GOOGL,2,Alphabet Inc.,15982000000,2800.0,18002\n";
+        let service = SentimentService::from_csv_reader(io::Cursor::new(csv), None).unwrap();
+        assert_eq!(*service.stocks(), create_test_stocks());
+
+        let err = SentimentService::from_csv_reader(io::Cursor::new("ticker,id\nAAPL,x\n"), None)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("company_name"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_json_reader_loads_in_memory_json() {
+        let json = serde_json::to_vec(&create_test_stocks()).unwrap();
+        let service = SentimentService::from_json_reader(json.as_slice(), None).unwrap();
+        assert_eq!(*service.stocks(), create_test_stocks());
+    }
+
+    #[test]
+    fn test_from_csv_reads_semicolon_delimited_file() {
+        let path = write_temp_file(
+            "semicolon.csv",
+            "sentiment_port;ticker;id;company_name;initial_price;total_float;exchange\n\
+             18001;AAPL;1;Apple Inc.;195.37;15982000000;NASDAQ\n\
+             18002; GOOGL ;2;Alphabet Inc.;2800.0;15982000000;NASDAQ\n",
+        );
+        let config = SentimentConfig {
+            csv_delimiter: ';',
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::from_csv(path.to_str().unwrap(), Some(config)).unwrap();
+        assert_eq!(service.stocks()[..], create_test_stocks()[..]);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_from_csv_skips_comment_lines() {
+        let path = write_temp_file(
+            "commented.csv",
+            "# Stocks broadcast by the demo deployment\n\
+             # Ports must be unique\n\
+             ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,3001\n\
+             # GOOGL,2,disabled,0,0.0,4001\n",
+        );
+        let service = SentimentService::from_csv(path.to_str().unwrap(), None).unwrap();
+        let tickers: Vec<String> = service.stocks().iter().map(|s| s.ticker.clone()).collect();
+        assert_eq!(tickers, ["AAPL"]);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_from_csv_reports_missing_column_and_bad_delimiter() {
+        let path = write_temp_file(
+            "no_port.csv",
+            "ticker,id,company_name,total_float,initial_price\n\
+             AAPL,1,Apple Inc.,15982000000,195.37\n",
+        );
+        let err = SentimentService::from_csv(path.to_str().unwrap(), None)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<LoadError>(),
+            Some(LoadError::MissingColumn("sentiment_port"))
+        ));
+        assert_eq!(err.to_string(), "stock list has no `sentiment_port` column");
+
+        let config = SentimentConfig {
+            csv_delimiter: '§',
+            ..SentimentConfig::default()
+        };
+        let err = SentimentService::from_csv(path.to_str().unwrap(), Some(config))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<LoadError>(),
+            Some(LoadError::InvalidDelimiter('§'))
+        ));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_from_csv_rejects_out_of_range_port() {
+        let path = write_temp_file(
+            "bad_port.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,3001\n\
+             GOOGL,2,Alphabet Inc.,15982000000,2800.0,70000\n",
+        );
+
+        let err = SentimentService::from_csv(path.to_str().unwrap(), None)
+            .err()
+            .expect("port 70000 should be rejected");
+        let message = err.to_string();
+        assert!(message.contains("row 3"), "{}", message);
+        assert!(message.contains("70000"), "{}", message);
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_from_csv_rejects_duplicate_port() {
+        let path = write_temp_file(
+            "duplicate_port.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,3001\n\
+             GOOGL,2,Alphabet Inc.,15982000000,2800.0,3001\n",
+        );
+
+        let err = SentimentService::from_csv(path.to_str().unwrap(), None)
+            .err()
+            .expect("a reused port should be rejected");
+        let Some(LoadError::Rows(err)) = err.downcast_ref::<LoadError>() else {
+            panic!("expected row errors, got {}", err);
+        };
+        assert_eq!(err.rows.len(), 1);
+        assert_eq!(err.rows[0].line, 3);
+        assert!(err.rows[0]
+            .message
+            .contains("duplicate sentiment_port 3001"));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_bad_numeric_field_is_reported_or_skipped() {
+        let path = write_temp_file(
+            "bad_price.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,not-a-price,3001\n\
+             GOOGL,2,Alphabet Inc.,15982000000,2800.0,4001\n",
+        );
+
+        let err = SentimentService::from_csv(path.to_str().unwrap(), None)
+            .err()
+            .expect("a non-numeric price should be rejected");
+        let Some(LoadError::Rows(err)) = err.downcast_ref::<LoadError>() else {
+            panic!("expected row errors, got {}", err);
+        };
+        assert_eq!(err.rows.len(), 1);
+        assert_eq!(err.rows[0].line, 2);
+
+        let service = SentimentService::from_csv_lenient(path.to_str().unwrap(), None).unwrap();
+        let stocks = service.stocks();
+        assert_eq!(stocks.len(), 1);
+        assert_eq!(stocks[0].ticker, "GOOGL");
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_builder_mixes_csv_and_manual_stocks() {
+        let path = write_temp_file(
+            "builder.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,3001\n",
+        );
+        let pltr = Stock {
+            ticker: "PLTR".to_string(),
+            id: 3,
+            company_name: "Palantir".to_string(),
+            total_float: 2_000_000_000,
+            initial_price: 20.0,
+            sentiment_port: 5001,
+            initial_sentiment: None,
+            sector: None,
+        };
+        let quiet = SentimentConfig {
+            volatility: 0.0,
+            ..SentimentConfig::default()
+        };
+
+        let service = SentimentService::builder()
+            .per_stock_config(3, quiet)
+            .stocks_from_csv(path.to_str().unwrap())
+            .add_stock(pltr.clone())
+            .config(SentimentConfig {
+                seed: Some(5),
+                ..SentimentConfig::default()
+            })
+            .build()
+            .unwrap();
+        let tickers: Vec<String> = service.stocks().iter().map(|s| s.ticker.clone()).collect();
+        assert_eq!(tickers, ["AAPL", "PLTR"]);
+        assert_eq!(service.config.for_stock(3).volatility, 0.0);
+        assert_eq!(service.config.seed, Some(5));
+
+        let duplicate = SentimentService::builder()
+            .stocks_from_csv(path.to_str().unwrap())
+            .add_stock(Stock {
+                sentiment_port: 3001,
+                ..pltr
+            })
+            .build();
+        assert!(matches!(
+            duplicate,
+            Err(BuildError::Load(LoadError::DuplicatePort {
+                port: 3001,
+                ..
+            }))
+        ));
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_csv_json_and_jsonl_loaders_agree() {
+        let stocks = [
+            ("AAPL", 1, "Apple Inc.", 3001),
+            ("GOOGL", 2, "Alphabet Inc.", 4001),
+            ("PLTR", 3, "Palantir", 5001),
+        ];
+        let csv: String = stocks
+            .iter()
+            .map(|(ticker, id, name, port)| format!("{ticker},{id},{name},1000,10.0,{port}\n"))
+            .collect();
+        let json_objects: Vec<String> = stocks
+            .iter()
+            .map(|(ticker, id, name, port)| {
+                format!(
+                    r#"{{"ticker":"{ticker}","id":{id},"company_name":"{name}","total_float":1000,"initial_price":10.0,"sentiment_port":{port}}}"#
+                )
+            })
+            .collect();
+        let csv_path = write_temp_file(
+            "stocks.csv",
+            &format!("ticker,id,company_name,total_float,initial_price,sentiment_port\n{csv}"),
+        );
+        let json_path = write_temp_file("stocks.json", &format!("[{}]", json_objects.join(",")));
+        let jsonl_path = write_temp_file("stocks.jsonl", &json_objects.join("\n"));
+
+        let from_csv = SentimentService::from_csv(csv_path.to_str().unwrap(), None).unwrap();
+        let from_json = SentimentService::from_json(json_path.to_str().unwrap(), None).unwrap();
+        let from_jsonl = SentimentService::from_jsonl(jsonl_path.to_str().unwrap(), None).unwrap();
+        for path in [csv_path, json_path, jsonl_path] {
+            std::fs::remove_file(path).ok();
+        }
+
+        assert_eq!(from_csv.stocks().len(), 3);
+        assert_eq!(from_json.stocks(), from_csv.stocks());
+        assert_eq!(from_jsonl.stocks(), from_csv.stocks());
+        assert_eq!(from_json.snapshot(), from_csv.snapshot());
+        assert_eq!(from_jsonl.snapshot(), from_csv.snapshot());
+    }
+
+    #[test]
+    fn test_seeded_engine_is_reproducible() {
+        let config = SentimentConfig {
+            seed: Some(42),
+            ..SentimentConfig::default()
+        };
+        let run = || {
+            let service =
+                SentimentService::new(create_test_stocks(), Some(config.clone())).unwrap();
+            let mut engine = service.engine();
+            (0..100)
+                .map(|_| {
+                    engine.step();
+                    [
+                        service.get_sentiment(1).to_bits(),
+                        service.get_sentiment(2).to_bits(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn test_step_mood_reverts_toward_mean() {
+        let config = SentimentConfig {
+            mean: 0.3,
+            ..SentimentConfig::default()
+        };
+        let below = step_mood(-0.5, config.mean, &config, 0.1, 0.0, 0.0);
+        assert!(below > -0.5 && below < 0.3, "{}", below);
+        let above = step_mood(0.9, config.mean, &config, 0.1, 0.0, 0.0);
+        assert!(above < 0.9 && above > 0.3, "{}", above);
+        assert_eq!(step_mood(0.3, config.mean, &config, 0.1, 0.0, 0.0), 0.3);
+    }
+
+    #[test]
+    fn test_step_functions_clamp_to_range() {
+        let config = SentimentConfig::default();
+        assert_eq!(step_mood(0.9, config.mean, &config, 1.0, 50.0, 0.0), 1.0);
+        assert_eq!(step_mood(-0.9, config.mean, &config, 1.0, -50.0, 0.0), -1.0);
+        // The jump is added before the single clamp, so a jump back into
+        // range still counts the part of the noise beyond the edge.
+        let jumped = step_mood(0.9, config.mean, &config, 1.0, 50.0, -0.5);
+        assert!(jumped > 0.5, "{}", jumped);
+        assert_eq!(step_stock(0.9, 0.9, 50.0, &config, 1, 1.0), 1.0);
+        assert_eq!(step_stock(-0.9, -0.9, -50.0, &config, 1, 1.0), -1.0);
+    }
+
+    #[test]
+    fn test_zero_volatility_steps_ignore_noise() {
+        let config = SentimentConfig {
+            volatility: 0.0,
+            ..SentimentConfig::default()
+        };
+        for noise in [-3.0, 0.0, 2.5] {
+            assert_eq!(
+                step_mood(0.5, config.mean, &config, 0.1, noise, 0.0),
+                step_mood(0.5, config.mean, &config, 0.1, 0.0, 0.0)
+            );
+            assert_eq!(
+                step_stock(-0.2, 0.4, noise, &config, 1, 0.1),
+                step_stock(-0.2, 0.4, 0.0, &config, 1, 0.1)
+            );
+        }
+        let stepped = step_stock(-0.2, 0.4, 0.0, &config, 1, 0.1);
+        assert!(stepped > -0.2 && stepped < 0.4, "{}", stepped);
+    }
+
+    #[test]
+    fn test_per_stock_mean_shifts_reversion_target() {
+        let mut config = SentimentConfig {
+            mean: 0.2,
+            volatility: 0.0,
+            ..SentimentConfig::default()
+        };
+        config.per_stock.insert(
+            1,
+            SentimentConfig {
+                mean: 0.5,
+                volatility: 0.0,
+                ..SentimentConfig::default()
+            },
+        );
+        // At the market mood, stock 1 keeps reverting toward 0.3 above it
+        // while a stock without an override stays put.
+        let mut state = 0.2;
+        for _ in 0..200 {
+            state = step_stock(state, 0.2, 0.0, &config, 1, 0.1);
+        }
+        assert!((state - 0.5).abs() < 1e-3, "{}", state);
+        assert_eq!(step_stock(0.2, 0.2, 0.0, &config, 2, 0.1), 0.2);
+    }
+
+    #[test]
+    fn test_sentiment_is_centered_on_mean() {
+        let config = SentimentConfig {
+            mean: 0.0,
+            seed: Some(7),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+
+        let ticks = 10_000;
+        let mut total = 0.0;
+        for _ in 0..ticks {
+            engine.step();
+            total += service.get_sentiment(1) + service.get_sentiment(2);
+        }
+        let empirical_mean = total / (2 * ticks) as f64;
+
+        assert!(
+            empirical_mean.abs() < 0.1,
+            "empirical mean {} drifted away from 0",
+            empirical_mean
+        );
+    }
+
+    #[test]
+    fn test_price_follows_sustained_sentiment() {
+        let config = SentimentConfig {
+            mean: 0.5,
+            volatility: 0.0,
+            seed: Some(3),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        assert_eq!(service.get_price(1), 195.37);
+        assert_eq!(service.get_price(2), 2800.0);
+        assert_eq!(service.get_price(99), 0.0);
+
+        let mut engine = service.engine();
+        let mut last = service.get_price(1);
+        for _ in 0..100 {
+            engine.step();
+            assert!(service.get_price(1) > last);
+            last = service.get_price(1);
+        }
+
+        // 10 simulated seconds at half the top rate: about +5%
+        let expected = 195.37 * (0.01 * 0.5 * 10.0_f64).exp();
+        assert!((last - expected).abs() < 1e-9, "{} vs {}", last, expected);
+    }
+
+    #[test]
+    fn test_market_index_is_float_weighted() {
+        let mut stocks = create_test_stocks();
+        stocks[0].total_float = 3_000;
+        stocks[1].total_float = 1_000;
+        stocks.push(Stock {
+            ticker: "NOFL".to_string(),
+            id: 3,
+            total_float: 0,
+            ..stocks[0].clone()
+        });
+        let service = SentimentService::new(stocks, None).unwrap();
+        assert_eq!(service.market_index(), 0.0);
+
+        service.set_sentiment(1, 0.4);
+        service.set_sentiment(2, -0.8);
+        service.set_sentiment(3, 1.0);
+        let expected = (0.4 * 3_000.0 - 0.8 * 1_000.0) / 4_000.0;
+        assert!((service.market_index() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_market_index_is_broadcast_on_index_port() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:0".parse().unwrap()],
+            },
+            index_port: Some(receiver.local_addr().unwrap().port()),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+
+        let mut buf = [0; 512];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        let packet = wire::decode_any(&buf[..len]).unwrap();
+        assert_eq!(packet.ticker, INDEX_TICKER);
+        assert_eq!(packet.stock_id, INDEX_STOCK_ID);
+
+        service.stop();
+        handle.join();
+    }
+
+    #[test]
+    fn test_batch_mode_splits_50_stocks_into_bounded_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        // Long ids and values so that 50 samples can't fit in one datagram
+        let stocks: Vec<Stock> = create_many_stocks(50)
+            .into_iter()
+            .map(|stock| Stock {
+                id: 1_000_000_000 + stock.id,
+                initial_sentiment: Some(-0.123_456_789 * stock.id as f64 / 50.0),
+                ..stock
+            })
+            .collect();
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:0".parse().unwrap()],
+            },
+            batch: true,
+            batch_port: receiver.local_addr().unwrap().port(),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(stocks.clone(), Some(config)).unwrap();
+        assert_eq!(service.estimated_thread_count(), 2);
+        let handle = service.start();
+
+        // Datagrams of one batch share its timestamp
+        let mut batches: HashMap<u64, (usize, Vec<(u64, f64)>)> = HashMap::new();
+        let mut buf = [0; 2 * wire::MAX_BATCH_DATAGRAM_LEN];
+        let (datagrams, samples) = loop {
+            let (len, _) = receiver.recv_from(&mut buf).unwrap();
+            assert!(len <= wire::MAX_BATCH_DATAGRAM_LEN, "{} byte datagram", len);
+            let part = wire::decode_batch(&buf[..len]).unwrap();
+            let (datagrams, samples) = batches.entry(part.timestamp_ms).or_default();
+            *datagrams += 1;
+            samples.extend(part.samples);
+            if samples.len() == stocks.len() {
+                break (*datagrams, samples.clone());
+            }
+        };
+        service.stop();
+        handle.join();
+
+        assert!(datagrams > 1);
+        let ids: Vec<u64> = samples.iter().map(|&(id, _)| id).collect();
+        let expected: Vec<u64> = stocks.iter().map(|stock| stock.id).collect();
+        assert_eq!(ids, expected);
+        assert!(service.packets_sent(expected[0]) > 0);
+    }
+
+    #[test]
+    fn test_failing_batches_are_reported_unhealthy() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            transport: Transport::Unicast {
+                subscribers: Vec::new(),
+            },
+            batch: true,
+            max_send_failures: 5,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        // An IPv6 destination can never be reached from the IPv4 socket
+        service.add_subscriber(BATCH_STOCK_ID, "[::1]:18071".parse().unwrap());
+        let handle = service.start();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while handle.unhealthy_broadcasters().len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let health = service.health();
+        handle.join();
+
+        assert!(!health.is_healthy());
+        for broadcaster in &health.broadcasters {
+            assert!(broadcaster.consecutive_failures >= 5, "{:?}", broadcaster);
+            assert_eq!(broadcaster.last_success_ms, None);
+        }
+    }
+
+    #[test]
+    fn test_batch_over_in_process_transport_is_rejected() {
+        let config = SentimentConfig {
+            transport: Transport::InProcess,
+            batch: true,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(config.validate(), Err(ConfigError::InProcessBatch));
+    }
+
+    #[test]
+    fn test_news_events_arrive_at_rate_and_shock_sentiment() {
+        // No noise or reversion, so news is the only thing moving sentiment
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(10),
+            volatility: 0.0,
+            reversion_speed: 0.0,
+            news_rate: 20.0,
+            news_impact: 0.05,
+            seed: Some(5),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let news = service.subscribe_news();
+        let mut engine = service.engine();
+        let mut expected = service.snapshot();
+
+        // 1000 ticks of 10ms expect 200 events; allow about 3.5 sigma
+        let mut count = 0;
+        for _ in 0..1000 {
+            engine.step();
+            for event in news.try_iter() {
+                assert_eq!(
+                    event.sentiment_impact > 0.0,
+                    event.headline_kind.is_positive()
+                );
+                let state = expected.get_mut(&event.stock_id).unwrap();
+                *state = service.config.clamp(*state + event.sentiment_impact);
+                count += 1;
+            }
+            for (id, value) in &expected {
+                assert!((service.get_sentiment(*id) - value).abs() < 1e-12);
+            }
+        }
+        assert!((150..=250).contains(&count), "{} events", count);
+    }
+
+    #[test]
+    fn test_news_events_are_broadcast_on_events_port() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:0".parse().unwrap()],
+            },
+            tick_interval: Duration::from_millis(10),
+            news_rate: 100.0,
+            events_port: Some(receiver.local_addr().unwrap().port()),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+
+        let mut buf = [0; 512];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        let event: NewsEvent = serde_json::from_slice(&buf[..len]).unwrap();
+        assert!([1, 2].contains(&event.stock_id));
+
+        service.stop();
+        handle.join();
+    }
+
+    #[test]
+    fn test_price_is_broadcast_on_offset_port() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let price_port = receiver.local_addr().unwrap().port();
+        let stock = Stock {
+            sentiment_port: price_port - PRICE_PORT_OFFSET,
+            ..create_test_stocks().remove(0)
+        };
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:0".parse().unwrap()],
+            },
+            broadcast_price: true,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(vec![stock], Some(config)).unwrap();
+        let handle = service.start();
+
+        let mut buf = [0; 512];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        let packet = wire::decode_any(&buf[..len]).unwrap();
+        assert_eq!(packet.ticker, "AAPL");
+        assert!((packet.value - 195.37).abs() < 1.0, "{}", packet.value);
+
+        service.stop();
+        handle.join();
+    }
+
+    fn variance(samples: &[f64]) -> f64 {
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64
+    }
+
+    #[test]
+    fn test_per_stock_volatility_override() {
+        let mut config = SentimentConfig {
+            seed: Some(11),
+            ..SentimentConfig::default()
+        };
+        for (id, volatility) in [(1, 0.0), (2, 4.0)] {
+            let stock_config = SentimentConfig {
+                volatility,
+                ..SentimentConfig::default()
+            };
+            config.per_stock.insert(id, stock_config);
+        }
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+
+        let (mut calm, mut wild) = (Vec::new(), Vec::new());
+        for _ in 0..5_000 {
+            engine.step();
+            calm.push(service.get_sentiment(1));
+            wild.push(service.get_sentiment(2));
+        }
+
+        assert!(
+            variance(&wild) > 1.5 * variance(&calm),
+            "calm={} wild={}",
+            variance(&calm),
+            variance(&wild)
+        );
+    }
+
+    #[test]
+    fn test_stocks_drift_independently_and_revert_to_mood() {
+        let service = SentimentService::new(
+            create_test_stocks(),
+            Some(SentimentConfig {
+                seed: Some(5),
+                ..SentimentConfig::default()
+            }),
+        )
+        .unwrap();
+        let mut engine = service.engine();
+
+        let mut diverged = false;
+        for _ in 0..200 {
+            engine.step();
+            diverged |= service.get_sentiment(1) != service.get_sentiment(2);
+        }
+        assert!(diverged, "stocks should not move in lockstep");
+
+        // Without noise each stock closes the gap to the mood every tick.
+        let quiet = SentimentConfig {
+            volatility: 0.0,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(quiet)).unwrap();
+        *service.market_mood.write().unwrap() = 0.5;
+        let mut engine = service.engine();
+        let mut previous_gap = 0.5;
+        for _ in 0..10 {
+            engine.step();
+            let gap = *service.market_mood.read().unwrap() - service.get_sentiment(1);
+            assert!(gap > 0.0 && gap < previous_gap);
+            previous_gap = gap;
+        }
+    }
+
+    fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+        let n = xs.len() as f64;
+        let (mean_x, mean_y) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+        let covariance = xs
+            .iter()
+            .zip(ys)
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f64>()
+            / n;
+        covariance / (variance(xs) * variance(ys)).sqrt()
+    }
+
+    #[test]
+    fn test_correlated_stocks_move_together() {
+        // Freeze the market mood so the only common driver is the
+        // correlated per-stock noise.
+        let mut config = SentimentConfig {
+            volatility: 0.0,
+            seed: Some(3),
+            ..SentimentConfig::default()
+        };
+        for id in [1, 2] {
+            let stock_config = SentimentConfig {
+                volatility: 0.2,
+                ..SentimentConfig::default()
+            };
+            config.per_stock.insert(id, stock_config);
+        }
+        let service = SentimentService::new(create_test_stocks(), Some(config))
+            .unwrap()
+            .with_correlations(vec![(1, 2, 0.9)])
+            .unwrap();
+        let mut engine = service.engine();
+
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        for _ in 0..20_000 {
+            engine.step();
+            first.push(service.get_sentiment(1));
+            second.push(service.get_sentiment(2));
+        }
+
+        let rho = correlation(&first, &second);
+        assert!((rho - 0.9).abs() < 0.05, "sample correlation {}", rho);
+    }
+
+    #[test]
+    fn test_stocks_in_a_sector_move_together() {
+        let mut stocks = create_test_stocks();
+        stocks[0].sector = Some("Tech".to_string());
+        stocks[1].sector = Some("Tech".to_string());
+        stocks.push(Stock {
+            ticker: "XOM".to_string(),
+            id: 3,
+            sector: Some("Energy".to_string()),
+            ..stocks[0].clone()
+        });
+        let config = SentimentConfig {
+            seed: Some(9),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(stocks, Some(config)).unwrap();
+        assert_eq!(service.sector_mood("Tech"), Some(0.0));
+        assert_eq!(service.sector_mood("Retail"), None);
+        let mut engine = service.engine();
+
+        let mut series = [Vec::new(), Vec::new(), Vec::new()];
+        for _ in 0..20_000 {
+            engine.step();
+            for (id, values) in (1..).zip(&mut series) {
+                values.push(service.get_sentiment(id));
+            }
+        }
+
+        let same_sector = correlation(&series[0], &series[1]);
+        let other_sector = correlation(&series[0], &series[2]);
+        assert!(
+            same_sector > other_sector + 0.1,
+            "same sector {} vs other sector {}",
+            same_sector,
+            other_sector
+        );
+        assert_ne!(service.sector_mood("Energy"), Some(0.0));
+    }
+
+    #[test]
+    fn test_inconsistent_correlations_are_rejected() {
+        let mut stocks = create_test_stocks();
+        stocks.push(Stock {
+            ticker: "PLTR".to_string(),
+            id: 3,
+            company_name: "Palantir Technologies".to_string(),
+            total_float: 2_000_000_000,
+            initial_price: 25.0,
+            sentiment_port: 18003,
+            initial_sentiment: None,
+            sector: None,
+        });
+
+        let result = SentimentService::new(stocks.clone(), None)
+            .unwrap()
+            .with_correlations(vec![(1, 2, 0.9), (2, 3, 0.9), (1, 3, -0.9)]);
+        assert_eq!(
+            result.err(),
+            Some(CorrelationError::NotPositiveSemidefinite)
+        );
+
+        let result = SentimentService::new(stocks, None)
+            .unwrap()
+            .with_correlations(vec![(1, 42, 0.5)]);
+        assert_eq!(result.err(), Some(CorrelationError::UnknownStock(42)));
+    }
+
+    #[test]
+    fn test_shock_jumps_then_decays() {
+        let config = SentimentConfig {
+            volatility: 0.0,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+
+        service.inject_shock(1, 1.0);
+        assert_eq!(service.get_sentiment(1), 1.0);
+        assert_eq!(service.get_sentiment(2), 0.0);
+
+        let mut previous = service.get_sentiment(1);
+        for _ in 0..20 {
+            engine.step();
+            let current = service.get_sentiment(1);
+            assert!(current < previous && current > 0.0);
+            previous = current;
+        }
+
+        service.inject_market_shock(0.5);
+        assert_eq!(*service.market_mood.read().unwrap(), 0.5);
+    }
+
+    fn kurtosis(samples: &[f64]) -> f64 {
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let fourth = samples.iter().map(|x| (x - mean).powi(4)).sum::<f64>() / n;
+        fourth / variance(samples).powi(2)
+    }
+
+    fn mood_increments(config: SentimentConfig, ticks: usize) -> Vec<f64> {
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+        let mut previous = 0.0;
+        (0..ticks)
+            .map(|_| {
+                engine.step();
+                let mood = *service.market_mood.read().unwrap();
+                let increment = mood - previous;
+                previous = mood;
+                increment
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_jumps_fatten_tails() {
+        let gaussian = SentimentConfig {
+            volatility: 0.05,
+            seed: Some(9),
+            ..SentimentConfig::default()
+        };
+        let jumpy = SentimentConfig {
+            jump_intensity: 0.05,
+            jump_scale: 0.3,
+            ..gaussian.clone()
+        };
+
+        let gaussian_kurtosis = kurtosis(&mood_increments(gaussian, 20_000));
+        let jumpy_kurtosis = kurtosis(&mood_increments(jumpy, 20_000));
+        assert!(
+            jumpy_kurtosis > gaussian_kurtosis + 1.0,
+            "gaussian={} jumpy={}",
+            gaussian_kurtosis,
+            jumpy_kurtosis
+        );
+    }
+
+    /// Variance per second of stock 1's tick-to-tick increments.
+    fn increment_variance_rate(tick_interval: Duration) -> f64 {
+        let config = SentimentConfig {
+            tick_interval,
+            seed: Some(21),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+        let mut previous = 0.0;
+        let increments: Vec<f64> = (0..20_000)
+            .map(|_| {
+                engine.step();
+                let current = service.get_sentiment(1);
+                let increment = current - previous;
+                previous = current;
+                increment
+            })
+            .collect();
+        variance(&increments) / tick_interval.as_secs_f64()
+    }
+
+    #[test]
+    fn test_stock_variance_is_invariant_to_tick_rate() {
+        let fast = increment_variance_rate(Duration::from_millis(50));
+        let slow = increment_variance_rate(Duration::from_millis(100));
+        let ratio = fast / slow;
+        assert!((0.85..1.15).contains(&ratio), "fast={} slow={}", fast, slow);
+    }
+
+    #[test]
+    fn test_on_update_sees_every_stock() {
+        let (tx, rx) = mpsc::channel();
+        let mut service = SentimentService::new(create_test_stocks(), None).unwrap();
+        service.on_update(move |stock_id, value| {
+            tx.send((stock_id, value)).unwrap();
+        });
+        let mut engine = service.engine();
+
+        engine.step();
+        let mut updates: Vec<(u64, f64)> = rx.try_iter().collect();
+        updates.sort_by_key(|&(stock_id, _)| stock_id);
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0], (1, service.get_sentiment(1)));
+        assert_eq!(updates[1], (2, service.get_sentiment(2)));
+    }
+
+    #[test]
+    fn test_threshold_watch_ignores_noise_within_band() {
+        let mut watch = ThresholdWatch {
+            level: 0.5,
+            band: 0.02,
+            above: None,
+        };
+        let values = [
+            0.4, 0.49, 0.51, 0.49, 0.515, 0.53, 0.51, 0.49, 0.52, 0.47, 0.49, 0.51,
+        ];
+        let crossings: Vec<_> = values
+            .iter()
+            .filter_map(|&value| watch.observe(value))
+            .collect();
+        assert_eq!(
+            crossings,
+            [CrossDirection::Upward, CrossDirection::Downward]
+        );
+    }
+
+    #[test]
+    fn test_on_threshold_cross_fires_once_per_crossing() {
+        let config = SentimentConfig {
+            mean: 0.5,
+            volatility: 0.0,
+            ..SentimentConfig::default()
+        };
+        let mut stocks = create_test_stocks();
+        stocks[0].initial_sentiment = Some(0.0);
+        let mut service = SentimentService::new(stocks, Some(config)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        service.on_threshold_cross(1, 0.25, move |id, value, direction| {
+            tx.send((id, value, direction)).unwrap();
+        });
+        let mut engine = service.engine();
+
+        // Stock 1 climbs from 0.0 toward the 0.5 mood
+        for _ in 0..200 {
+            engine.step();
+        }
+        let alerts: Vec<_> = rx.try_iter().collect();
+        assert_eq!(alerts.len(), 1);
+        let (id, value, direction) = alerts[0];
+        assert_eq!((id, direction), (1, CrossDirection::Upward));
+        assert!(value >= 0.25 + THRESHOLD_HYSTERESIS);
+    }
+
+    #[test]
+    fn test_snapshot_covers_every_stock() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        service.engine().step();
+
+        let (mood, snapshot) = service.snapshot_with_mood();
+        assert_eq!(mood, *service.market_mood.read().unwrap());
+        assert_eq!(snapshot, service.snapshot());
+        for stock in create_test_stocks() {
+            assert_eq!(
+                snapshot.get(&stock.id),
+                Some(&service.get_sentiment(stock.id))
+            );
+        }
+    }
+
+    #[test]
+    fn test_shared_broadcaster_bounds_thread_count() {
+        let stocks: Vec<Stock> = (0..100)
+            .map(|i| Stock {
+                ticker: format!("T{}", i),
+                id: i,
+                company_name: format!("Company {}", i),
+                total_float: 1_000_000,
+                initial_price: 10.0,
+                sentiment_port: 19_000 + i as u16,
+                initial_sentiment: None,
+                sector: None,
+            })
+            .collect();
+        let config = SentimentConfig {
+            broadcast_mode: BroadcastMode::Shared,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(stocks, Some(config)).unwrap();
+
+        let handle = service.start();
+        assert_eq!(handle.bindings().len(), 100);
+        assert!(handle.failed_bindings().is_empty());
+        assert!(
+            handle.thread_count() <= 2,
+            "{} threads",
+            handle.thread_count()
+        );
+        handle.join();
+    }
+
+    /// A receiver on `port` that has joined the service's multicast group.
+    fn join_multicast(port: u16) -> std::net::UdpSocket {
+        let socket = std::net::UdpSocket::bind(("0.0.0.0", port)).unwrap();
+        socket
+            .join_multicast_v4(&MULTICAST_ADDR, &std::net::Ipv4Addr::UNSPECIFIED)
+            .unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket
+    }
+
+    #[test]
+    fn test_multicast_interface_is_applied() {
+        let stock = create_test_stocks().remove(0);
+        let config = SentimentConfig {
+            multicast_interface: Some(Ipv4Addr::LOCALHOST),
+            ..SentimentConfig::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+
+        let socket = open_broadcast_socket(&stock, &config.transport, &config).unwrap();
+        let interface = socket2::SockRef::from(&socket).multicast_if_v4().unwrap();
+        assert_eq!(interface, Ipv4Addr::LOCALHOST);
+
+        let config = SentimentConfig {
+            ip_family: IpFamily::V6,
+            transport: Transport::multicast(IpFamily::V6),
+            ..config
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::IpFamilyMismatch {
+                family: IpFamily::V6,
+                addr: Ipv4Addr::LOCALHOST.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_broadcast_interval_limits_rate() {
+        let stock = Stock {
+            ticker: "SLOW".to_string(),
+            id: 1,
+            company_name: "Slow Corp".to_string(),
+            total_float: 1_000_000,
+            initial_price: 10.0,
+            sentiment_port: 18101,
+            initial_sentiment: None,
+            sector: None,
+        };
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(10),
+            broadcast_interval: Duration::from_millis(250),
+            ..SentimentConfig::default()
+        };
+        let receiver = join_multicast(stock.sentiment_port);
+        let service = SentimentService::new(vec![stock], Some(config)).unwrap();
+        let handle = service.start();
+
+        let mut buf = [0; 512];
+        let mut arrivals = Vec::new();
+        for _ in 0..4 {
+            receiver.recv(&mut buf).unwrap();
+            arrivals.push(Instant::now());
+        }
+        handle.join();
+
+        for pair in arrivals.windows(2) {
+            let gap = pair[1] - pair[0];
+            assert!(gap >= Duration::from_millis(200), "gap {:?}", gap);
+            assert!(gap < Duration::from_millis(600), "gap {:?}", gap);
+        }
+    }
+
+    #[test]
+    fn test_zero_tick_interval_is_rejected() {
+        let config = SentimentConfig {
+            tick_interval: Duration::ZERO,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::InvalidTickInterval(Duration::ZERO))
+        );
+        let result = SentimentService::new(create_test_stocks(), Some(config));
+        assert_eq!(
+            result.err(),
+            Some(ConfigError::InvalidTickInterval(Duration::ZERO))
+        );
+    }
+
+    #[test]
+    fn test_non_finite_volatility_is_a_config_error() {
+        for volatility in [f64::NAN, f64::INFINITY] {
+            let config = SentimentConfig {
+                volatility,
+                ..SentimentConfig::default()
+            };
+            let err = SentimentService::new(create_test_stocks(), Some(config))
+                .err()
+                .unwrap();
+            assert!(
+                matches!(err, ConfigError::NegativeVolatility(v) if v.is_nan() || v == volatility),
+                "{}",
+                err
+            );
+        }
+
+        let config = SentimentConfig {
+            jump_intensity: 0.1,
+            jump_scale: f64::NAN,
+            ..SentimentConfig::default()
+        };
+        let err = SentimentService::new(create_test_stocks(), Some(config))
+            .err()
+            .unwrap();
+        assert!(matches!(err, ConfigError::InvalidJumps { .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_negative_volatility_is_rejected() {
+        let config = SentimentConfig {
+            volatility: -0.1,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::NegativeVolatility(-0.1))
+        );
+
+        let mut config = SentimentConfig::default();
+        config.per_stock.insert(
+            1,
+            SentimentConfig {
+                volatility: -2.0,
+                ..SentimentConfig::default()
+            },
+        );
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::NegativeVolatility(-2.0))
+        );
+    }
+
+    #[test]
+    fn test_distribution_buckets_current_sentiments() {
+        let stocks: Vec<Stock> = [-0.9, -0.5, 0.1, 0.2, 1.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Stock {
+                ticker: format!("S{}", i),
+                id: i as u64,
+                sentiment_port: 18100 + i as u16,
+                initial_sentiment: Some(value),
+                ..create_test_stocks().remove(0)
+            })
+            .collect();
+        let service = SentimentService::new(stocks, None).unwrap();
+
+        // -0.5 opens the second bin and 1.0 closes the last
+        assert_eq!(
+            service.distribution(4),
+            vec![(-0.75, 1), (-0.25, 1), (0.25, 2), (0.75, 1)]
+        );
+        assert_eq!(service.distribution(1), vec![(0.0, 5)]);
+        assert!(service.distribution(0).is_empty());
+    }
+
+    #[test]
+    fn test_staggered_stocks_broadcast_at_their_phase() {
+        let mut config = SentimentConfig {
+            transport: Transport::InProcess,
+            tick_interval: Duration::from_millis(100),
+            stagger: true,
+            phase: Some(0.0),
+            ..SentimentConfig::default()
+        };
+        config.per_stock.insert(
+            2,
+            SentimentConfig {
+                phase: Some(0.5),
+                ..SentimentConfig::default()
+            },
+        );
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let packets = service.bus().subscribe();
+        let handle = service.start();
+        let mut arrivals = Vec::new();
+        while arrivals.len() < 16 {
+            let packet = packets.recv_timeout(Duration::from_secs(2)).unwrap();
+            // The starting values go out before the first tick, together
+            if packet.seq > 1 {
+                arrivals.push((packet.stock_id, Instant::now()));
+            }
+        }
+        handle.join();
+
+        // Each GOOGL packet follows the tick's AAPL packet by about half a
+        // tick, where unstaggered stocks would go out together
+        let mut last_aapl = None;
+        let mut gaps = Vec::new();
+        for (stock_id, at) in arrivals {
+            match (stock_id, last_aapl) {
+                (1, _) => last_aapl = Some(at),
+                (_, Some(aapl)) => gaps.push(at - aapl),
+                _ => {}
+            }
+        }
+        assert!(gaps.len() >= 5, "{:?}", gaps);
+        for gap in gaps {
+            assert!(gap >= Duration::from_millis(30), "{:?}", gap);
+        }
+    }
+
+    #[test]
+    fn test_hashed_phases_spread_over_the_interval() {
+        let phases: Vec<f64> = (0..1_000).map(hashed_phase).collect();
+        assert!(phases.iter().all(|phase| (0.0..1.0).contains(phase)));
+        let mean = phases.iter().sum::<f64>() / phases.len() as f64;
+        assert!((mean - 0.5).abs() < 0.05, "mean {}", mean);
+        assert_ne!(hashed_phase(1), hashed_phase(2));
+
+        let config = SentimentConfig {
+            stagger: true,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.phase(7),
+            Some(config.tick_interval.mul_f64(hashed_phase(7)))
+        );
+        let config = SentimentConfig {
+            phase: Some(1.0),
+            ..config
+        };
+        assert_eq!(config.validate(), Err(ConfigError::PhaseOutOfRange(1.0)));
+    }
+
+    #[test]
+    fn test_invalid_quantum_is_rejected() {
+        for quantum in [0.0, -0.1, 2.0, f64::NAN] {
+            let config = SentimentConfig {
+                quantum: Some(quantum),
+                ..SentimentConfig::default()
+            };
+            assert!(
+                matches!(config.validate(), Err(ConfigError::InvalidQuantum(_))),
+                "{}",
+                quantum
+            );
+        }
+        let config = SentimentConfig {
+            quantum: Some(1.0),
+            scale: SentimentScale::Unit,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(config.validate(), Err(ConfigError::InvalidQuantum(1.0)));
+    }
+
+    #[test]
+    fn test_broadcast_values_are_quantized() {
+        let config = SentimentConfig {
+            transport: Transport::InProcess,
+            tick_interval: Duration::from_millis(10),
+            quantum: Some(0.1),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let packets = service.bus().subscribe();
+        let handle = service.start();
+        let mut values = Vec::new();
+        while values.len() < 40 {
+            values.push(packets.recv_timeout(Duration::from_secs(2)).unwrap().value);
+        }
+        handle.join();
+
+        for value in values {
+            let steps = value / 0.1;
+            assert!((steps - steps.round()).abs() < 1e-9, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_invalid_news_rate_is_rejected() {
+        for rate in [-1.0, f64::INFINITY] {
+            let config = SentimentConfig {
+                news_rate: rate,
+                ..SentimentConfig::default()
+            };
+            assert_eq!(config.validate(), Err(ConfigError::InvalidNewsRate(rate)));
+        }
+    }
+
+    #[test]
+    fn test_invalid_news_impact_is_rejected() {
+        for impact in [-0.1, f64::INFINITY, f64::NAN] {
+            let config = SentimentConfig {
+                news_impact: impact,
+                ..SentimentConfig::default()
+            };
+            let err = config.validate().unwrap_err();
+            assert!(matches!(err, ConfigError::InvalidNewsImpact(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_reversion_speed_out_of_range_is_rejected() {
+        for speed in [-0.5, 1.5] {
+            let config = SentimentConfig {
+                reversion_speed: speed,
+                ..SentimentConfig::default()
+            };
+            assert_eq!(
+                config.validate(),
+                Err(ConfigError::ReversionSpeedOutOfRange(speed))
+            );
+        }
+    }
+
+    #[test]
+    fn test_mean_out_of_range_is_rejected() {
+        for mean in [-1.5, 2.0] {
+            let config = SentimentConfig {
+                mean,
+                ..SentimentConfig::default()
+            };
+            assert_eq!(config.validate(), Err(ConfigError::MeanOutOfRange(mean)));
+        }
+        assert_eq!(SentimentConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_empty_sentiment_range_is_rejected() {
+        let config = SentimentConfig {
+            sentiment_min: 1.0,
+            sentiment_max: 1.0,
+            mean: 1.0,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::InvalidSentimentRange { min: 1.0, max: 1.0 })
+        );
+    }
+
+    #[test]
+    fn test_custom_sentiment_range() {
+        let config = SentimentConfig {
+            sentiment_min: 0.0,
+            sentiment_max: 100.0,
+            mean: 60.0,
+            seed: Some(5),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        assert_eq!(service.get_sentiment(1), 60.0);
+        let mut engine = service.engine();
+
+        let ticks = 10_000;
+        let mut total = 0.0;
+        for _ in 0..ticks {
+            engine.step();
+            for value in service.snapshot().into_values() {
+                assert!((0.0..=100.0).contains(&value), "{} out of range", value);
+                total += value;
+            }
+        }
+        let empirical_mean = total / (2 * ticks) as f64;
+        assert!(
+            (empirical_mean - 60.0).abs() < 5.0,
+            "empirical mean {} drifted away from 60",
+            empirical_mean
+        );
+
+        service.set_sentiment(1, 250.0);
+        assert_eq!(service.get_sentiment(1), 100.0);
+    }
+
+    #[test]
+    fn test_unit_scale_maps_signed_extremes() {
+        let config = SentimentConfig {
+            scale: SentimentScale::Unit,
+            ..SentimentConfig::default()
+        };
+        assert_eq!(config.output_value(-1.0), 0.0);
+        assert_eq!(config.output_value(0.0), 0.5);
+        assert_eq!(config.output_value(1.0), 1.0);
+
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        service.set_sentiment(1, -1.0);
+        service.set_sentiment(2, 1.0);
+        assert_eq!(service.get_sentiment(1), 0.0);
+        assert_eq!(service.get_sentiment(2), 1.0);
+        assert_eq!(service.get_sentiment_by_ticker("googl"), Some(1.0));
+    }
+
+    #[test]
+    fn test_non_multicast_addr_is_rejected() {
+        let config = SentimentConfig {
+            transport: Transport::Multicast {
+                addr: Ipv4Addr::new(192, 168, 1, 10).into(),
+                ttl: 1,
+            },
+            ..SentimentConfig::default()
+        };
+        let result = SentimentService::new(create_test_stocks(), Some(config));
+        assert_eq!(
+            result.err(),
+            Some(ConfigError::InvalidMulticastAddr(
+                Ipv4Addr::new(192, 168, 1, 10).into()
+            ))
+        );
+
+        let config = SentimentConfig {
+            transport: Transport::Multicast {
+                addr: Ipv4Addr::new(239, 1, 2, 3).into(),
+                ttl: 4,
+            },
+            ..SentimentConfig::default()
+        };
+        assert!(SentimentService::new(create_test_stocks(), Some(config)).is_ok());
+
+        // Extra transports are held to the same rules
+        let config = SentimentConfig {
+            extra_transports: vec![Transport::Multicast {
+                addr: Ipv4Addr::new(10, 0, 0, 1).into(),
+                ttl: 1,
+            }],
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            SentimentService::new(create_test_stocks(), Some(config)).err(),
+            Some(ConfigError::InvalidMulticastAddr(
+                Ipv4Addr::new(10, 0, 0, 1).into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_transport_family_must_match_ip_family() {
+        let config = SentimentConfig {
+            transport: Transport::multicast(IpFamily::V6),
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::IpFamilyMismatch {
+                family: IpFamily::V4,
+                addr: MULTICAST_ADDR_V6.into(),
+            })
+        );
+
+        let config = SentimentConfig {
+            ip_family: IpFamily::V6,
+            ..config
+        };
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_ipv6_multicast_broadcast() {
+        // Skip on hosts without IPv6 multicast support.
+        let Ok(receiver) = std::net::UdpSocket::bind("[::]:18061") else {
+            return;
+        };
+        if receiver.join_multicast_v6(&MULTICAST_ADDR_V6, 0).is_err() {
+            return;
+        }
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let stock = Stock {
+            sentiment_port: 18061,
+            ..create_test_stocks().remove(0)
+        };
+        let config = SentimentConfig {
+            transport: Transport::multicast(IpFamily::V6),
+            ip_family: IpFamily::V6,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(vec![stock], Some(config)).unwrap();
+        let handle = service.start();
+        assert!(handle.failed_bindings().is_empty());
+
+        let mut buf = [0; 512];
+        let (len, _) = receiver.recv_from(&mut buf).unwrap();
+        let packet = wire::decode_any(&buf[..len]).unwrap();
+        assert_eq!(packet.ticker, "AAPL");
+
+        service.stop();
+        handle.join();
+    }
+
+    #[test]
+    fn test_binary_broadcast_does_not_allocate() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut stock = create_test_stocks().remove(0);
+        stock.sentiment_port = receiver.local_addr().unwrap().port();
+        let destinations = Destinations {
+            transport: Transport::Unicast {
+                subscribers: vec![receiver.local_addr().unwrap()],
+            },
+            subscribers: Arc::default(),
+        };
+        let metrics = Arc::new(Metrics::default());
+        let sink = UdpSink::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            destinations,
+            Arc::clone(&metrics),
+            SentimentConfig::default(),
+        );
+        let mut outgoing = Outgoing::new(&stock);
+
+        // One iteration of the broadcast loop
+        let mut send = |value| sink.send(&stock, outgoing.next(value, None, WireFormat::Binary));
+
+        // The first send sizes the buffers and registers the stock's counters
+        send(0.0);
+        let before = allocations();
+        for i in 0..1_000 {
+            send(f64::from(i) / 1_000.0);
+        }
+        assert_eq!(allocations() - before, 0);
+        assert_eq!(metrics.packets_sent(stock.id), 1_001);
+    }
+
+    /// A `Sink` that keeps every payload in memory.
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<Recorded>>>,
+    }
+
+    /// One payload sent to a `RecordingSink`, with its stock and send time.
+    struct Recorded {
+        stock_id: u64,
+        at: Instant,
+        payload: Vec<u8>,
+    }
+
+    impl Sink for RecordingSink {
+        fn send(&self, stock: &Stock, payload: &[u8]) {
+            self.sent.lock().unwrap().push(Recorded {
+                stock_id: stock.id,
+                at: Instant::now(),
+                payload: payload.to_vec(),
+            });
+        }
+    }
+
+    #[test]
+    fn test_broadcaster_sends_in_range_values_to_sink() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let stock = create_test_stocks().remove(0);
+        let sink = RecordingSink::default();
+        let handle = service.start_udp_broadcaster(stock.clone(), sink.clone());
+
+        let mut engine = service.engine();
+        while sink.sent.lock().unwrap().len() < 10 {
+            engine.step();
+            thread::sleep(service.config.broadcast_interval);
+        }
+        service.stop();
+        handle.join().unwrap();
+
+        let sent = sink.sent.lock().unwrap();
+        for (i, recorded) in sent.iter().enumerate() {
+            assert_eq!(recorded.stock_id, stock.id);
+            let packet = service
+                .config
+                .wire_format
+                .decode(&recorded.payload)
+                .unwrap();
+            assert_eq!(packet.ticker, stock.ticker);
+            assert_eq!(packet.seq, i as u64 + 1);
+            assert!((-1.0..=1.0).contains(&packet.value), "{}", packet.value);
+        }
+        // At most one send per broadcast interval
+        for pair in sent.windows(2) {
+            assert!(pair[1].at - pair[0].at >= service.config.broadcast_interval);
+        }
+    }
+
+    #[test]
+    fn test_every_sink_receives_identical_payloads() {
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: vec![],
+            },
+            ..SentimentConfig::default()
+        };
+        let mut service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let (first, second) = (RecordingSink::default(), RecordingSink::default());
+        service.add_sink(first.clone());
+        service.add_sink(second.clone());
+
+        let handle = service.start();
+        while second.sent.lock().unwrap().len() < 20 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        handle.join();
+
+        let payloads = |sink: &RecordingSink| -> Vec<(u64, Vec<u8>)> {
+            let sent = sink.sent.lock().unwrap();
+            sent.iter()
+                .map(|r| (r.stock_id, r.payload.clone()))
+                .collect()
+        };
+        let first = payloads(&first);
+        assert!(first.len() >= 20);
+        assert_eq!(first, payloads(&second));
+        for stock in create_test_stocks() {
+            assert!(first.iter().any(|(id, _)| *id == stock.id));
+        }
+    }
+
+    #[test]
+    fn test_in_process_bus_delivers_every_packet_to_each_consumer() {
+        let config = SentimentConfig {
+            transport: Transport::InProcess,
+            tick_interval: Duration::from_millis(10),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let (first, second) = (service.bus().subscribe(), service.bus().subscribe());
+        let only_aapl = service.bus().subscribe_stock(1);
+
+        let handle = service.start();
+        let mut seen = Vec::new();
+        while seen.len() < 40 {
+            seen.push(first.recv_timeout(Duration::from_secs(2)).unwrap());
+        }
+        handle.join();
+        seen.extend(first.try_iter());
+
+        assert_eq!(seen, second.try_iter().collect::<Vec<_>>());
+        let aapl: Vec<_> = seen.iter().filter(|p| p.stock_id == 1).cloned().collect();
+        assert_eq!(aapl, only_aapl.try_iter().collect::<Vec<_>>());
+        // Numbered without gaps, so nothing was dropped on the way
+        for stock in create_test_stocks() {
+            let seqs: Vec<u64> = seen
+                .iter()
+                .filter(|p| p.stock_id == stock.id)
+                .map(|p| p.seq)
+                .collect();
+            assert!(!seqs.is_empty());
+            assert!(seqs.iter().copied().eq(1..=seqs.len() as u64), "{:?}", seqs);
+        }
+    }
+
+    #[test]
+    fn test_extra_transports_receive_broadcasts() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut stock = create_test_stocks().remove(0);
+        stock.sentiment_port = receiver.local_addr().unwrap().port();
+        let local = Transport::Unicast {
+            subscribers: vec!["127.0.0.1:0".parse().unwrap()],
+        };
+        let config = SentimentConfig {
+            transport: local.clone(),
+            extra_transports: vec![local],
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(vec![stock.clone()], Some(config)).unwrap();
+
+        // Both transports reach the receiver, so it gets two copies
+        service
+            .broadcast_sink(&stock)
+            .unwrap()
+            .send(&stock, b"packet");
+        let mut buf = [0u8; 16];
+        for _ in 0..2 {
+            let n = receiver.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"packet");
+        }
+        assert_eq!(service.packets_sent(stock.id), 2);
+    }
+
+    /// Writes and reads completed in one second by a writer updating every
+    /// stock and `readers` threads each polling one stock in a tight loop.
+    fn measure_contention(
+        readers: u64,
+        read: impl Fn(u64) + Sync,
+        mut write: impl FnMut(),
+    ) -> (u64, u64) {
+        let done = AtomicBool::new(false);
+        let reads = AtomicU64::new(0);
+        let mut writes = 0;
+        thread::scope(|scope| {
+            for stock_id in 0..readers {
+                let (done, reads, read) = (&done, &reads, &read);
+                scope.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        read(stock_id);
+                        reads.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            let started = Instant::now();
+            while started.elapsed() < Duration::from_secs(1) {
+                write();
+                writes += 1;
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        (writes, reads.into_inner())
+    }
+
+    /// Compares the old `RwLock` map against `Sentiments` with many
+    /// per-stock broadcasters. Run with
+    /// `cargo test --release -- --ignored --nocapture contention`.
+    #[test]
+    #[ignore]
+    fn bench_sentiment_contention() {
+        const STOCKS: u64 = 500;
+        const READERS: u64 = 64;
+        let initial: HashMap<u64, f64> = (0..STOCKS).map(|id| (id, 0.0)).collect();
+
+        let locked = RwLock::new(initial.clone());
+        let (writes, reads) = measure_contention(
+            READERS,
+            |id| {
+                std::hint::black_box(locked.read().unwrap().get(&id).copied());
+            },
+            || {
+                for value in locked.write().unwrap().values_mut() {
+                    *value += 1e-9;
+                }
+            },
+        );
+        println!("RwLock:     {} ticks/s, {} reads/s", writes, reads);
+
+        let swapped = Sentiments::new(initial);
+        let (writes, reads) = measure_contention(
+            READERS,
+            |id| {
+                std::hint::black_box(swapped.get(id));
+            },
+            || {
+                swapped.update(|map| {
+                    for value in map.values_mut() {
+                        *value += 1e-9;
+                    }
+                })
+            },
+        );
+        println!("Sentiments: {} ticks/s, {} reads/s", writes, reads);
+    }
+
+    #[test]
+    fn test_sentiments_update_is_not_lost_under_concurrent_writers() {
+        let sentiments = Sentiments::new(HashMap::from([(1, 0.0)]));
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        sentiments.update(|map| *map.get_mut(&1).unwrap() += 1.0);
+                    }
+                });
+            }
+        });
+        assert_eq!(sentiments.get(1), Some(800.0));
+    }
+
+    #[test]
+    fn test_send_errors_are_throttled() {
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let error = io::Error::new(io::ErrorKind::NetworkUnreachable, "network is down");
+        let start = Instant::now();
+        let mut errors = SendErrors::default();
+
+        let logged = (0..1000)
+            .filter_map(|_| errors.failure(start, "AAPL", addr, &error))
+            .count();
+        assert_eq!(logged, 1);
+        assert_eq!(errors.consecutive, 1000);
+
+        let later = start + SEND_ERROR_LOG_INTERVAL;
+        let message = errors.failure(later, "AAPL", addr, &error).unwrap();
+        assert!(message.contains("999 more failures"), "{}", message);
+        assert!(errors.failure(later, "AAPL", addr, &error).is_none());
+
+        assert!(errors
+            .success("AAPL")
+            .unwrap()
+            .contains("1002 failed sends"));
+        assert!(errors.success("AAPL").is_none());
+    }
+
+    #[test]
+    fn test_failing_broadcaster_is_reported_unhealthy() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            transport: Transport::Unicast {
+                subscribers: Vec::new(),
+            },
+            max_send_failures: 5,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        // An IPv6 destination can never be reached from the IPv4 socket
+        service.add_subscriber(1, "[::1]:18071".parse().unwrap());
+        let handle = service.start();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while handle.unhealthy_broadcasters().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(handle.unhealthy_broadcasters(), vec![1]);
+        assert!(handle.consecutive_send_failures(1) >= 5);
+        assert_eq!(handle.consecutive_send_failures(2), 0);
+        handle.join();
+    }
+
+    #[test]
+    fn test_unicast_subscriber_receives_samples() {
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: Vec::new(),
+            },
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        service.add_subscriber(1, receiver.local_addr().unwrap());
+        let handle = service.start();
+
+        let mut buf = [0; 512];
+        let len = receiver.recv(&mut buf).unwrap();
+        handle.join();
+
+        let packet = wire::decode_any(&buf[..len]).unwrap();
+        assert_eq!(packet.ticker, "AAPL");
+        assert!((-1.0..=1.0).contains(&packet.value));
+    }
+
+    #[test]
+    fn test_loading_more_than_max_stocks_is_rejected() {
+        let path = write_temp_file(
+            "too_many.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n\
+             AAPL,1,Apple Inc.,15982000000,195.37,18001\n\
+             GOOGL,2,Alphabet Inc.,15982000000,2800.0,18002\n\
+             MSFT,3,Microsoft Corp.,7430000000,420.0,18003\n",
+        );
+        let config = SentimentConfig {
+            max_stocks: Some(2),
+            ..SentimentConfig::default()
+        };
+        let err = SentimentService::from_csv(path.to_str().unwrap(), Some(config.clone()))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<ConfigError>(),
+            Some(&ConfigError::TooManyStocks { count: 3, max: 2 })
+        );
+        assert!(err.to_string().contains("Shared"), "{}", err);
+
+        // Within the limit, a reload past it is refused and changes nothing
+        let service = SentimentService::new(create_test_stocks(), Some(config.clone())).unwrap();
+        assert!(service.reload_stocks(path.to_str().unwrap()).is_err());
+        assert_eq!(service.stocks().len(), 2);
+
+        let shared = SentimentConfig {
+            broadcast_mode: BroadcastMode::Shared,
+            ..config
+        };
+        assert!(SentimentService::from_csv(path.to_str().unwrap(), Some(shared)).is_ok());
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_estimated_thread_count_matches_start() {
+        let per_stock = SentimentConfig {
+            transport: Transport::InProcess,
+            index_port: Some(0),
+            ..SentimentConfig::default()
+        };
+        let shared = SentimentConfig {
+            broadcast_mode: BroadcastMode::Shared,
+            ..per_stock.clone()
+        };
+        for (config, expected) in [(per_stock, 4), (shared, 3)] {
+            let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+            assert_eq!(service.estimated_thread_count(), expected);
+            let handle = service.start();
+            assert_eq!(handle.threads.len(), expected);
+            handle.join();
+        }
+    }
+
+    #[test]
+    fn test_empty_stock_list_is_rejected() {
+        let path = write_temp_file(
+            "empty.csv",
+            "ticker,id,company_name,total_float,initial_price,sentiment_port\n",
+        );
+        let err = SentimentService::from_csv(path.to_str().unwrap(), None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<ConfigError>(),
+            Some(&ConfigError::NoStocks)
+        );
+        assert_eq!(
+            SentimentService::new(Vec::new(), None).err(),
+            Some(ConfigError::NoStocks)
+        );
+    }
+
+    #[test]
+    fn test_engine_waits_for_stocks_after_reload_empties_list() {
+        const HEADER: &str = "ticker,id,company_name,total_float,initial_price,sentiment_port\n";
+        let path = write_temp_file("emptied.csv", HEADER);
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            enable_broadcast: false,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        service.reload_stocks(path.to_str().unwrap()).unwrap();
+        let handle = service.start();
+        assert_eq!(handle.thread_count(), 0);
+
+        std::fs::write(
+            &path,
+            format!("{HEADER}PLTR,3,Palantir,2000000000,20.0,18003\n"),
+        )
+        .unwrap();
+        service.reload_stocks(path.to_str().unwrap()).unwrap();
+        assert_eq!(handle.thread_count(), 1);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while service.get_sentiment(3) == 0.0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_ne!(service.get_sentiment(3), 0.0);
+
+        // Further reloads don't start a second engine
+        service.reload_stocks(path.to_str().unwrap()).unwrap();
+        assert_eq!(handle.thread_count(), 1);
+        handle.join();
+    }
+
+    #[test]
+    fn test_reload_starts_broadcaster_for_new_stock() {
+        const HEADER: &str = "ticker,id,company_name,total_float,initial_price,sentiment_port\n";
+        const ROWS: &str = "AAPL,1,Apple Inc.,15982000000,195.37,18001\n\
+                            GOOGL,2,Alphabet Inc.,15982000000,2800.0,18002\n";
+        let path = write_temp_file("reload.csv", &format!("{HEADER}{ROWS}"));
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: Vec::new(),
+            },
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::from_csv(path.to_str().unwrap(), Some(config)).unwrap();
+        let handle = service.start();
+        let threads_before = handle.thread_count();
 
-        // Start service in background
-        thread::spawn(move || {
-            service.start();
+        std::fs::write(
+            &path,
+            format!("{HEADER}{ROWS}PLTR,3,Palantir,2000000000,20.0,18003\n"),
+        )
+        .unwrap();
+        service.add_subscriber(3, receiver.local_addr().unwrap());
+        let diff = service.reload_stocks(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut buf = [0; 512];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(handle.thread_count(), threads_before + 1);
+        handle.join();
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].ticker, "PLTR");
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.unchanged.len(), 2);
+        assert_eq!(wire::decode_any(&buf[..len]).unwrap().ticker, "PLTR");
+        assert!(service.snapshot().contains_key(&3));
+    }
+
+    #[test]
+    fn test_tcp_stream_delivers_json_lines() {
+        use std::io::{BufRead, BufReader};
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let addr = service
+            .start_tcp_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let tickers: Vec<String> = (0..4)
+            .map(|_| {
+                let line = lines.next().unwrap().unwrap();
+                wire::decode_packet(line.as_bytes()).unwrap().ticker
+            })
+            .collect();
+        service.stop();
+
+        assert_eq!(tickers, ["AAPL", "GOOGL", "AAPL", "GOOGL"]);
+    }
+
+    /// `count` copies of AAPL with their own ids and tickers, so a stream
+    /// cycle is big enough to compress.
+    fn create_many_stocks(count: u64) -> Vec<Stock> {
+        let template = create_test_stocks().remove(0);
+        (1..=count)
+            .map(|id| Stock {
+                ticker: format!("S{}", id),
+                id,
+                sentiment_port: 18000 + id as u16,
+                ..template.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tcp_stream_sends_compressed_frames() {
+        let stocks = create_many_stocks(20);
+        let config = SentimentConfig {
+            stream_compression: Some(StreamCompression::Gzip),
+            compression_threshold: 256,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(stocks.clone(), Some(config)).unwrap();
+        let addr = service
+            .start_tcp_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let frames: Vec<Vec<SentimentPacket>> = (0..3)
+            .map(|_| wire::read_frame(&mut stream).unwrap())
+            .collect();
+        let ratio = service.stats().compression_ratio;
+        service.stop();
+
+        let tickers: Vec<&str> = stocks.iter().map(|stock| stock.ticker.as_str()).collect();
+        for frame in &frames {
+            let received: Vec<&str> = frame.iter().map(|packet| packet.ticker.as_str()).collect();
+            assert_eq!(received, tickers);
+        }
+        assert!(ratio > 1.5, "compression ratio {}", ratio);
+    }
+
+    #[test]
+    fn test_stream_compression_ratio_starts_at_one() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        assert_eq!(service.stats().compression_ratio, 1.0);
+    }
+
+    #[test]
+    fn test_tcp_stream_backfills_recent_ticks() {
+        use std::io::{BufRead, BufReader};
+
+        let config = SentimentConfig {
+            backfill_ticks: 3,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+        for _ in 0..5 {
+            engine.step();
+        }
+        let addr = service
+            .start_tcp_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let mut next = || wire::decode_packet(lines.next().unwrap().unwrap().as_bytes()).unwrap();
+        let mut frames: Vec<(String, u64)> = (0..6)
+            .map(|_| next())
+            .map(|packet| (packet.ticker, packet.seq))
+            .collect();
+
+        // Live ticks follow straight on from the backfill
+        engine.step();
+        frames.extend(
+            (0..2)
+                .map(|_| next())
+                .map(|packet| (packet.ticker, packet.seq)),
+        );
+        service.stop();
+
+        let expected: Vec<(String, u64)> = (3..=6)
+            .flat_map(|seq| [("AAPL".to_string(), seq), ("GOOGL".to_string(), seq)])
+            .collect();
+        assert_eq!(frames, expected);
+    }
+
+    #[test]
+    fn test_subscribers_receive_every_tick() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let first = service.subscribe();
+        let second = service.subscribe();
+        let mut engine = service.engine();
+        for _ in 0..3 {
+            engine.step();
+        }
+
+        for rx in [&first, &second] {
+            let packets: Vec<SentimentPacket> = rx.try_iter().collect();
+            assert_eq!(packets.len(), 6);
+            let tickers: Vec<&str> = packets.iter().map(|p| p.ticker.as_str()).collect();
+            assert_eq!(tickers, ["AAPL", "GOOGL", "AAPL", "GOOGL", "AAPL", "GOOGL"]);
+            for packet in &packets {
+                assert!((-1.0..=1.0).contains(&packet.value));
+            }
+        }
+
+        // Dropped receivers are unsubscribed on the next tick
+        drop(first);
+        engine.step();
+        assert_eq!(service.subscriptions.lock().unwrap().len(), 1);
+        assert_eq!(second.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_subscribe_every_decimates_ticks() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(100),
+            enable_broadcast: false,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let every_tick = service.subscribe();
+        let every_second = service.subscribe_every(Duration::from_secs(1));
+
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(2_500));
+        handle.join();
+
+        // Two stocks per forwarded tick
+        let ticks = every_tick.try_iter().count() / 2;
+        let decimated = every_second.try_iter().count() / 2;
+        assert!(ticks >= 15, "{} ticks", ticks);
+        // Due on the first tick, then around 1.1s and 2.1s
+        assert!((2..=4).contains(&decimated), "{} updates", decimated);
+    }
+
+    #[test]
+    fn test_subscription_due_times() {
+        let start = Instant::now();
+        let mut subscription = Subscription {
+            tx: mpsc::sync_channel(1).0,
+            every: Some(Duration::from_secs(1)),
+            due: start,
+        };
+        let at = |ms| start + Duration::from_millis(ms);
+        let forwarded: Vec<u64> = (0..30)
+            .map(|tick| tick * 100)
+            .filter(|&ms| subscription.take_due(at(ms)))
+            .collect();
+        assert_eq!(forwarded, [0, 1_000, 2_000]);
+
+        // After a long gap, the next due time counts from the gap's end
+        assert!(subscription.take_due(at(10_050)));
+        assert!(!subscription.take_due(at(10_900)));
+        assert!(subscription.take_due(at(11_050)));
+    }
+
+    #[test]
+    fn test_slow_subscriber_misses_packets() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let rx = service.subscribe();
+        let mut engine = service.engine();
+        for _ in 0..SUBSCRIBER_QUEUE_CAPACITY {
+            engine.step();
+        }
+        assert_eq!(rx.try_iter().count(), SUBSCRIBER_QUEUE_CAPACITY);
+
+        // Still subscribed once drained
+        engine.step();
+        assert_eq!(rx.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_tick_history_keeps_capacity_and_numbers_ticks() {
+        let history = TickHistory::new(2);
+        for value in [0.1, 0.2, 0.3] {
+            history.push(vec![SentimentPacket::now("AAPL", 1, value)]);
+        }
+        let (ticks, last) = history.since(0);
+        let values: Vec<(u64, f64)> = ticks.iter().map(|t| (t[0].seq, t[0].value)).collect();
+        assert_eq!(values, [(2, 0.2), (3, 0.3)]);
+        assert_eq!(last, 3);
+
+        let (ticks, last) = history.since(3);
+        assert!(ticks.is_empty());
+        assert_eq!(last, 3);
+
+        let disabled = TickHistory::new(0);
+        disabled.push(vec![SentimentPacket::now("AAPL", 1, 0.1)]);
+        assert_eq!(disabled.since(0).1, 0);
+    }
+
+    #[test]
+    fn test_sse_stream_delivers_named_events() {
+        use std::io::{BufRead, BufReader};
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let addr = service
+            .start_sse_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nAccept: text/event-stream\r\n\r\n")
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines().map(Result::unwrap);
+
+        assert_eq!(lines.next().unwrap(), "HTTP/1.1 200 OK");
+        let headers: Vec<String> = lines.by_ref().take_while(|l| !l.is_empty()).collect();
+        assert!(headers.contains(&"Content-Type: text/event-stream".to_string()));
+
+        let mut events = Vec::new();
+        while events.len() < 4 {
+            let event = lines.next().unwrap();
+            let data = lines.next().unwrap();
+            assert_eq!(lines.next().unwrap(), "");
+            let packet =
+                wire::decode_packet(data.strip_prefix("data: ").unwrap().as_bytes()).unwrap();
+            assert_eq!(event, format!("event: {}", packet.ticker));
+            events.push(packet.ticker);
+        }
+        service.stop();
+
+        assert_eq!(events, ["AAPL", "GOOGL", "AAPL", "GOOGL"]);
+    }
+
+    #[test]
+    fn test_slow_sse_request_does_not_block_other_clients() {
+        use std::io::{BufRead, BufReader};
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let addr = service
+            .start_sse_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        // Trickles its headers a byte at a time, never finishing in time
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let trickle = thread::spawn(move || {
+            for _ in 0..15 {
+                if slow.write_all(b"X").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
         });
+        thread::sleep(Duration::from_millis(100));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(800)))
+            .unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status).unwrap();
+        service.stop();
+        trickle.join().unwrap();
+
+        assert_eq!(status, "HTTP/1.1 200 OK\r\n");
+    }
+
+    #[test]
+    fn test_oversized_sse_request_is_refused() {
+        use std::io::Read;
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let addr = service
+            .start_sse_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let padding = "X-Padding: ".to_string() + &"a".repeat(SSE_MAX_REQUEST_LEN as usize);
+        let _ = stream.write_all(format!("GET / HTTP/1.1\r\n{}\r\n\r\n", padding).as_bytes());
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        service.stop();
+
+        assert!(
+            response.is_empty(),
+            "{}",
+            String::from_utf8_lossy(&response)
+        );
+    }
+
+    #[test]
+    fn test_sse_rejects_non_get_requests() {
+        use std::io::Read;
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let addr = service
+            .start_sse_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"POST / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        service.stop();
+
+        assert!(response.starts_with("HTTP/1.1 405"));
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_websocket_client_receives_subscribed_frames() {
+        use tungstenite::Message;
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let addr = service
+            .start_websocket_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        socket
+            .send(Message::text(r#"{"subscribe": ["GOOGL"]}"#))
+            .unwrap();
+
+        let mut tickers = Vec::new();
+        while tickers.len() < 40 {
+            if let Message::Text(text) = socket.read().unwrap() {
+                tickers.push(wire::decode_packet(text.as_bytes()).unwrap().ticker);
+            }
+        }
+        service.stop();
+
+        assert!(tickers[30..].iter().all(|ticker| ticker == "GOOGL"));
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_websocket_client_receives_compressed_frames() {
+        use tungstenite::Message;
+
+        let config = SentimentConfig {
+            stream_compression: Some(StreamCompression::Deflate),
+            compression_threshold: 0,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_many_stocks(20), Some(config)).unwrap();
+        let addr = service
+            .start_websocket_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        socket
+            .send(Message::text(r#"{"subscribe": ["S3", "S7"]}"#))
+            .unwrap();
+
+        let mut frames = Vec::new();
+        while frames.len() < 40 {
+            if let Message::Binary(frame) = socket.read().unwrap() {
+                frames.push(frame);
+            }
+        }
+        service.stop();
+
+        assert!(frames.iter().any(|frame| frame[0] != 0));
+        let last = wire::decode_frame(&frames[39]).unwrap();
+        let tickers: Vec<&str> = last.iter().map(|packet| packet.ticker.as_str()).collect();
+        assert_eq!(tickers, ["S3", "S7"]);
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_websocket_client_is_backfilled() {
+        use tungstenite::Message;
+
+        let config = SentimentConfig {
+            backfill_ticks: 2,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+        for _ in 0..4 {
+            engine.step();
+        }
+        let addr = service
+            .start_websocket_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        let mut next = || loop {
+            if let Message::Text(text) = socket.read().unwrap() {
+                let packet = wire::decode_packet(text.as_bytes()).unwrap();
+                return (packet.ticker, packet.seq);
+            }
+        };
+        let mut frames: Vec<(String, u64)> = (0..4).map(|_| next()).collect();
+        engine.step();
+        frames.extend((0..2).map(|_| next()));
+        service.stop();
+
+        let expected: Vec<(String, u64)> = (3..=5)
+            .flat_map(|seq| [("AAPL".to_string(), seq), ("GOOGL".to_string(), seq)])
+            .collect();
+        assert_eq!(frames, expected);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_endpoint_exposes_counters() {
+        use std::io::Read;
+
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        let handle = service.start();
+        let addr = service
+            .start_metrics_server("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        thread::sleep(Duration::from_millis(300));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        handle.join();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("sentiment_packets_sent_total{ticker=\"AAPL\"}"));
+        assert!(response.contains("sentiment_bind_failures_total 0"));
+        assert!(response.contains("sentiment_current_value{ticker=\"GOOGL\"}"));
+        assert!(!response.contains("sentiment_packets_sent_total{ticker=\"AAPL\"} 0\n"));
+
+        // Values go out on the configured scale, like every other output
+        let config = SentimentConfig {
+            scale: SentimentScale::Unit,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        service.set_sentiment(1, -0.5);
+        assert_eq!(service.get_sentiment(1), 0.25);
+        assert!(service
+            .metrics_text()
+            .contains("sentiment_current_value{ticker=\"AAPL\"} 0.25\n"));
+    }
+
+    #[test]
+    fn test_metrics_text_survives_a_poisoned_counter_lock() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        service.metrics.packet_sent(1);
+        let metrics = Arc::clone(&service.metrics);
+        thread::spawn(move || {
+            let _counts = metrics.packets_sent.write().unwrap();
+            panic!("poison the counters");
+        })
+        .join()
+        .unwrap_err();
+
+        let text = service.metrics_text();
+        assert!(text.contains("sentiment_packets_sent_total{ticker=\"AAPL\"} 1\n"));
+    }
+
+    #[test]
+    fn test_replay_reproduces_recording() {
+        let path = write_temp_file("recording.csv", "");
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            seed: Some(3),
+            ..SentimentConfig::default()
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let mut service =
+            SentimentService::new(create_test_stocks(), Some(config.clone())).unwrap();
+        service.on_update(move |id, value| tx.send((id, value)).unwrap());
+        service.start_recording(&path).unwrap();
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(100));
+        handle.join();
+        let recorded: Vec<(u64, f64)> = rx.try_iter().collect();
+        assert!(recorded.len() >= 10);
+
+        let (tx, rx) = mpsc::channel();
+        let mut replay =
+            SentimentService::from_recording(create_test_stocks(), &path, Some(config)).unwrap();
+        replay.on_update(move |id, value| tx.send((id, value)).unwrap());
+        let handle = replay.start();
+        let replayed: Vec<(u64, f64)> = rx.iter().take(recorded.len()).collect();
+        handle.join();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn test_replay_speed_scales_wall_time() {
+        let rows: String = (0..=20)
+            .map(|i| format!("{},1,{}\n", 1_000 + i * 10, i as f64 / 100.0))
+            .collect();
+        let path = write_temp_file("speed.csv", &format!("timestamp_ms,stock_id,value\n{rows}"));
+
+        let replay_time = |speed: f64| {
+            let (tx, rx) = mpsc::channel();
+            let replay = ReplayConfig {
+                path: path.clone(),
+                speed,
+            };
+            let mut service = SentimentService::replay(create_test_stocks(), replay, None).unwrap();
+            service.on_update(move |id, value| tx.send((id, value)).unwrap());
+            let started = Instant::now();
+            let handle = service.start();
+            assert_eq!(rx.iter().take(21).count(), 21);
+            let elapsed = started.elapsed().as_secs_f64();
+            handle.join();
+            elapsed
+        };
+        let normal = replay_time(1.0);
+        let double = replay_time(2.0);
+        std::fs::remove_file(&path).unwrap();
+
+        let ratio = double / normal;
+        assert!((0.35..0.65).contains(&ratio), "ratio {ratio}");
+
+        let zero = ReplayConfig {
+            path: PathBuf::from("unused.csv"),
+            speed: 0.0,
+        };
+        let err = SentimentService::replay(create_test_stocks(), zero, None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<ConfigError>(),
+            Some(&ConfigError::InvalidReplaySpeed(0.0))
+        );
+    }
+
+    #[test]
+    fn test_pause_freezes_sentiments_until_resumed() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            seed: Some(11),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(30));
+
+        service.pause();
+        assert!(service.is_paused());
+        // Let a tick that was already in flight land before snapshotting
+        thread::sleep(Duration::from_millis(10));
+        let frozen = service.snapshot_with_mood();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(service.snapshot_with_mood(), frozen);
+
+        service.resume();
+        assert!(!service.is_paused());
+        thread::sleep(Duration::from_millis(50));
+        assert_ne!(service.snapshot_with_mood(), frozen);
+        handle.join();
+    }
+
+    #[test]
+    fn test_regimes_persist_and_set_the_mood_mean() {
+        let model = RegimeModel::default();
+        let config = SentimentConfig {
+            regimes: Some(model.clone()),
+            volatility: 0.05,
+            seed: Some(4),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        assert_eq!(service.market_regime(), Regime::Bullish);
+        let mut engine = service.engine();
+
+        let ticks = 30_000;
+        let mut switches = 0;
+        let mut settled_for = 0;
+        let (mut bull, mut bear) = (Vec::new(), Vec::new());
+        let mut regime = service.market_regime();
+        for _ in 0..ticks {
+            engine.step();
+            if service.market_regime() != regime {
+                regime = service.market_regime();
+                switches += 1;
+                settled_for = 0;
+            }
+            settled_for += 1;
+            // Ten seconds after a switch the mood has all but reached the
+            // new mean
+            if settled_for > 100 {
+                match regime {
+                    Regime::Bullish => bull.push(service.market_mood()),
+                    _ => bear.push(service.market_mood()),
+                }
+            }
+        }
+
+        assert!(switches >= 2, "{} switches", switches);
+        assert!(ticks / switches > 200, "{} switches", switches);
+        for (moods, mean) in [(bull, model.bull_mean), (bear, model.bear_mean)] {
+            let average = moods.iter().sum::<f64>() / moods.len() as f64;
+            assert!((average - mean).abs() < 0.05, "{} vs {}", average, mean);
+        }
+    }
+
+    #[test]
+    fn test_regime_probabilities_are_validated() {
+        let config = SentimentConfig {
+            regimes: Some(RegimeModel {
+                bear_stay: 1.5,
+                ..RegimeModel::default()
+            }),
+            ..SentimentConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::TransitionProbabilityOutOfRange(1.5))
+        );
+    }
+
+    #[test]
+    fn test_market_mood_is_bounded_and_classified() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(2),
+            volatility: 2.0,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(50));
+        handle.join();
+
+        let mood = service.market_mood();
+        assert!((-1.0..=1.0).contains(&mood));
+        assert_eq!(service.market_regime(), Regime::from_mood(mood));
+        assert_eq!(Regime::from_mood(0.5), Regime::Bullish);
+        assert_eq!(Regime::from_mood(0.0), Regime::Neutral);
+        assert_eq!(Regime::from_mood(-0.5), Regime::Bearish);
+    }
+
+    #[test]
+    fn test_get_sentiment_by_ticker_ignores_case() {
+        let service = SentimentService::new(create_test_stocks(), None).unwrap();
+        service.inject_shock(1, 0.25);
+
+        assert_eq!(service.get_sentiment_by_ticker("aapl"), Some(0.25));
+        assert_eq!(service.get_sentiment_by_ticker("AAPL"), Some(0.25));
+        assert_eq!(service.get_sentiment_by_ticker("MSFT"), None);
+    }
+
+    #[test]
+    fn test_stock_lookups() {
+        let stocks = create_test_stocks();
+        let service = SentimentService::new(stocks.clone(), None).unwrap();
+
+        assert_eq!(service.stocks().as_slice(), stocks.as_slice());
+        assert_eq!(service.stock_by_id(2), Some(stocks[1].clone()));
+        assert_eq!(service.stock_by_id(99), None);
+        assert_eq!(service.stock_by_ticker("googl"), Some(stocks[1].clone()));
+        assert_eq!(service.stock_by_ticker("MSFT"), None);
+    }
+
+    #[test]
+    fn test_set_sentiment_holds_while_paused() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+        service.pause();
+        thread::sleep(Duration::from_millis(10));
+
+        service.set_sentiment(1, 0.75);
+        service.set_sentiment(2, -3.0);
+        service.set_market_mood(0.5);
+        thread::sleep(Duration::from_millis(30));
+        handle.join();
+
+        assert_eq!(service.get_sentiment(1), 0.75);
+        assert_eq!(service.get_sentiment(2), -1.0);
+        assert_eq!(service.market_mood(), 0.5);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_service_sends_packets() {
+        let receiver = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = SentimentConfig {
+            transport: Transport::Unicast {
+                subscribers: Vec::new(),
+            },
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        service.add_subscriber(2, receiver.local_addr().unwrap());
+        let handle = service.start_async().unwrap();
+        assert_eq!(handle.task_count(), 2);
+
+        let mut buf = [0; 512];
+        let len = tokio::time::timeout(Duration::from_secs(2), receiver.recv(&mut buf))
+            .await
+            .expect("no packet within 2s")
+            .unwrap();
+        handle.shutdown().await;
+
+        let packet = wire::decode_any(&buf[..len]).unwrap();
+        assert_eq!(packet.ticker, "GOOGL");
+    }
 
-        // Give service time to start
-        thread::sleep(Duration::from_millis(200));
+    #[test]
+    fn test_engine_resets_nan_to_mean() {
+        let config = SentimentConfig {
+            mean: 0.25,
+            seed: Some(4),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let mut engine = service.engine();
+        service.set_sentiment(1, f64::NAN);
+        service.set_market_mood(f64::NAN);
+        engine.step();
 
-        // Try to receive UDP data
-        if let Ok(socket) = std::net::UdpSocket::bind("127.0.0.1:18001") {
-            socket
-                .set_read_timeout(Some(Duration::from_millis(500)))
-                .ok();
-            let mut buf = [0; 64];
+        let (mood, sentiments) = service.snapshot_with_mood();
+        assert_eq!(mood, 0.25);
+        assert_eq!(sentiments[&1], 0.25);
+        assert!(sentiments[&2].is_finite());
+        // And the engine carries on from there
+        engine.step();
+        assert!(service.get_sentiment(1).is_finite());
+    }
 
-            if let Ok((len, _)) = socket.recv_from(&mut buf) {
-                let data = String::from_utf8_lossy(&buf[..len]);
-                let sentiment: f64 = data.parse().unwrap_or(999.0);
-                assert!(sentiment >= -1.0 && sentiment <= 1.0);
+    #[test]
+    fn test_watchdog_restarts_panicked_engine() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(10),
+            enable_broadcast: false,
+            ..SentimentConfig::default()
+        };
+        let mut service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let armed = Arc::new(AtomicBool::new(true));
+        let trigger = Arc::clone(&armed);
+        service.on_update(move |_, _| {
+            if trigger.swap(false, Ordering::SeqCst) {
+                panic!("injected engine failure");
             }
+        });
+
+        let handle = service.start();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while service.health().engine_restarts == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!armed.load(Ordering::SeqCst));
+        let ticks = service.stats().ticks;
+        thread::sleep(Duration::from_millis(100));
+
+        let health = service.health();
+        assert_eq!(health.engine_restarts, 1);
+        assert!(health.engine_alive);
+        assert!(
+            service.stats().ticks > ticks,
+            "engine stopped after the panic"
+        );
+        // The lock the panic poisoned is usable again
+        service.snapshot_with_mood();
+        handle.join();
+
+        // Before a respawn clears it, a poisoned mood lock is still readable
+        let before = service.market_mood();
+        let mood = Arc::clone(&service.market_mood);
+        thread::spawn(move || {
+            let _mood = mood.write().unwrap();
+            panic!("poison the mood");
+        })
+        .join()
+        .unwrap_err();
+        assert!(service.market_mood.is_poisoned());
+        let (mood, sentiments) = service.snapshot_with_mood();
+        assert_eq!(mood, before);
+        assert_eq!(sentiments.len(), 2);
+    }
+
+    #[test]
+    fn test_health_tracks_running_service() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(10),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        assert!(!service.health().engine_alive);
+
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(100));
+        let health = service.health();
+        assert!(health.is_healthy(), "{:?}", health);
+        assert_eq!(health.broadcasters.len(), 2);
+        assert!(health
+            .broadcasters
+            .iter()
+            .all(|broadcaster| broadcaster.last_success_ms.is_some()));
+
+        service.stop();
+        handle.join();
+        let health = service.health();
+        assert!(!health.engine_alive);
+        assert!(!health.is_healthy());
+    }
+
+    #[test]
+    fn test_broadcasters_wait_for_warmup() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(20),
+            warmup_ticks: 15,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+
+        // 15 ticks take about 300ms
+        thread::sleep(Duration::from_millis(150));
+        assert!(service
+            .health()
+            .broadcasters
+            .iter()
+            .all(|broadcaster| broadcaster.last_success_ms.is_none()));
+
+        thread::sleep(Duration::from_millis(350));
+        assert!(service
+            .health()
+            .broadcasters
+            .iter()
+            .all(|broadcaster| broadcaster.last_success_ms.is_some()));
+        handle.join();
+    }
+
+    #[test]
+    fn test_interpolation_eases_over_tick_interval() {
+        let interpolation = Interpolation::new(Duration::from_millis(100));
+        assert_eq!(interpolation.value(1, 0.4, Instant::now()), 0.4);
+
+        interpolation.record(Arc::new(HashMap::from([(1, 0.0)])));
+        let at = interpolation.last_tick.load().as_ref().unwrap().at;
+        let value_at = |ms| interpolation.value(1, 0.4, at + Duration::from_millis(ms));
+        assert_eq!(value_at(0), 0.0);
+        assert!((value_at(25) - 0.1).abs() < 1e-12);
+        assert!((value_at(50) - 0.2).abs() < 1e-12);
+        assert_eq!(value_at(100), 0.4);
+        assert_eq!(value_at(250), 0.4);
+        // A stock added by the tick has no earlier value to ease from
+        assert_eq!(interpolation.value(2, -0.3, at), -0.3);
+    }
+
+    #[test]
+    fn test_interpolation_tick_is_recorded_before_its_values() {
+        let sentiments = Sentiments::new(HashMap::from([(1, 0.0)]));
+        let interpolation = Interpolation::new(Duration::from_millis(100));
+        interpolation.record(Arc::new(HashMap::from([(1, -0.5)])));
+
+        // Anyone who can see the new value already eases into it from 0.0
+        publish_tick(&sentiments, Some(&interpolation), |map| {
+            let tick = interpolation.last_tick.load_full().unwrap();
+            assert_eq!(tick.previous.get(&1), Some(&0.0));
+            map.insert(1, 0.4);
+        });
+        assert_eq!(sentiments.get(1), Some(0.4));
+        let at = interpolation.last_tick.load().as_ref().unwrap().at;
+        assert_eq!(interpolation.value(1, 0.4, at), 0.0);
+    }
+
+    #[test]
+    fn test_interpolated_broadcasts_change_gradually() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(100),
+            volatility: 2.0,
+            interpolate: true,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let stock = create_test_stocks().remove(0);
+        let mut engine = service.engine();
+        let before = service.get_sentiment(stock.id);
+        engine.step();
+        let jump = service.get_sentiment(stock.id) - before;
+
+        let sink = RecordingSink::default();
+        let handle = service.start_udp_broadcaster(stock.clone(), sink.clone());
+        let values = || -> Vec<f64> {
+            let sent = sink.sent.lock().unwrap();
+            sent.iter()
+                .map(|recorded| wire::decode_packet(&recorded.payload).unwrap().value)
+                .collect()
+        };
+        // Wait for the broadcaster to arrive rather than for a fixed time,
+        // which a loaded machine may not give it
+        let target = service.get_sentiment(stock.id);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let arrived = |values: &[f64]| values.last().is_some_and(|v| (v - target).abs() < 1e-12);
+        while !arrived(&values()) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        service.stop();
+        handle.join().unwrap();
+
+        let values = values();
+        assert!(values.len() >= 5, "{} packets", values.len());
+        assert!(arrived(&values), "{:?} never reached {}", values, target);
+        for pair in values.windows(2) {
+            let step = pair[1] - pair[0];
+            assert!(step * jump >= 0.0, "values move toward the new sentiment");
+            assert!(step.abs() < jump.abs() / 2.0, "{} of {}", step, jump);
+        }
+    }
+
+    #[test]
+    fn test_stats_count_ticks_and_packets() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut stock = create_test_stocks().remove(0);
+        stock.sentiment_port = receiver.local_addr().unwrap().port();
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(10),
+            transport: Transport::Unicast {
+                subscribers: vec!["127.0.0.1:0".parse().unwrap()],
+            },
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(vec![stock.clone()], Some(config)).unwrap();
+        assert_eq!(service.stats().uptime, Duration::ZERO);
+
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(500));
+        let stats = service.stats();
+        handle.join();
+
+        // Sleeping between ticks only ever makes the engine fall behind
+        let expected = stats.uptime.as_secs_f64() / 0.01;
+        assert!(
+            (stats.ticks as f64) <= expected + 1.0 && (stats.ticks as f64) >= expected * 0.5,
+            "{} ticks in {:?}",
+            stats.ticks,
+            stats.uptime
+        );
+        assert!(stats.packets_sent > 0);
+        assert_eq!(stats.stocks.len(), 1);
+        assert_eq!(stats.stocks[0].ticker, stock.ticker);
+        assert_eq!(stats.stocks[0].packets_sent, stats.packets_sent);
+        assert!(stats.stocks[0].send_rate > 0.0);
+    }
+
+    #[test]
+    fn test_send_rate_of_a_reloaded_stock_counts_from_its_addition() {
+        const HEADER: &str = "ticker,id,company_name,total_float,initial_price,sentiment_port\n";
+        const AAPL: &str = "AAPL,1,Apple Inc.,15982000000,195.37,18001\n";
+        let path = write_temp_file("rate.csv", &format!("{HEADER}{AAPL}"));
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = SentimentConfig {
+            broadcast_interval: Duration::from_millis(10),
+            transport: Transport::Unicast {
+                subscribers: vec![receiver.local_addr().unwrap()],
+            },
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::from_csv(path.to_str().unwrap(), Some(config)).unwrap();
+        let handle = service.start();
+        thread::sleep(Duration::from_millis(600));
+
+        std::fs::write(
+            &path,
+            format!("{HEADER}{AAPL}GOOGL,2,Alphabet Inc.,15982000000,2800.0,18002\n"),
+        )
+        .unwrap();
+        service.reload_stocks(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        let first = service.stats();
+        thread::sleep(Duration::from_millis(300));
+        let second = service.stats();
+        handle.join();
+
+        // Over its own lifetime GOOGL sends as fast as AAPL, not a third
+        // as fast as averaging over the whole uptime would say.
+        for stats in [&first, &second] {
+            let (aapl, googl) = (stats.stocks[0].send_rate, stats.stocks[1].send_rate);
+            assert!(aapl > 0.0, "{:?}", stats);
+            assert!(googl > aapl * 0.6 && googl < aapl * 1.6, "{:?}", stats);
+        }
+        // The second window only covers the 300ms since the first call.
+        let sent = second.stocks[0].packets_sent - first.stocks[0].packets_sent;
+        let window = (second.uptime - first.uptime).as_secs_f64();
+        assert!((second.stocks[0].send_rate - sent as f64 / window).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_engine_runs_with_broadcast_disabled() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(5),
+            enable_broadcast: false,
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+        assert!(handle.bindings().is_empty());
+        assert_eq!(handle.thread_count(), 1);
+
+        thread::sleep(Duration::from_millis(100));
+        let snapshot = service.snapshot();
+        assert!(
+            snapshot.values().any(|&value| value != 0.0),
+            "{:?}",
+            snapshot
+        );
+        handle.join();
+    }
+
+    #[test]
+    fn test_shutdown_signal_stops_threads() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_millis(10),
+            ..SentimentConfig::default()
+        };
+        let service = Arc::new(SentimentService::new(create_test_stocks(), Some(config)).unwrap());
+        let handle = service.start();
+        let (signal_tx, signal_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        {
+            let service = Arc::clone(&service);
+            thread::spawn(move || {
+                let summary = run_until_shutdown(&service, handle, &signal_rx);
+                done_tx.send(summary).unwrap();
+            });
         }
+
+        thread::sleep(Duration::from_millis(100));
+        signal_tx.send(()).unwrap();
+        let summary = done_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(summary.len(), 2);
+        assert!(summary[0].starts_with("ticker=AAPL packets_sent="));
+        assert!(service.packets_sent(1) > 0);
+        assert!(!service.health().engine_alive);
+    }
+
+    #[test]
+    fn test_udp_broadcast() {
+        let stocks = create_test_stocks();
+        let service = SentimentService::new(stocks, None).unwrap();
+        let handle = service.start();
+        assert!(handle.failed_bindings().is_empty());
+
+        // Multicast loopback delivers the group's packets on this host
+        let socket = join_multicast(18001);
+        let mut buf = [0; 512];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        let packet = wire::decode_any(&buf[..len]).unwrap();
+        assert_eq!(packet.ticker, "AAPL");
+        assert!((-1.0..=1.0).contains(&packet.value));
+        assert!(packet.regime.is_some());
+
+        service.stop();
+        handle.join();
+    }
+
+    #[test]
+    fn test_join_stops_all_threads_promptly() {
+        let config = SentimentConfig {
+            tick_interval: Duration::from_secs(5),
+            ..SentimentConfig::default()
+        };
+        let service = SentimentService::new(create_test_stocks(), Some(config)).unwrap();
+        let handle = service.start();
+        assert_eq!(handle.bindings().len(), 2);
+        thread::sleep(Duration::from_millis(50));
+
+        let started = Instant::now();
+        handle.join();
+        assert!(started.elapsed() < Duration::from_secs(1));
     }
 }
@@ -0,0 +1,982 @@
+//! Wire format for sentiment samples sent from the service to its clients.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    num::ParseFloatError,
+    str::Utf8Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// One sentiment observation as broadcast by the service. Carrying the ticker
+/// and a timestamp lets receivers on a shared socket route and align samples.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SentimentPacket {
+    pub ticker: String,
+    pub stock_id: u64,
+    /// Sentiment within the service's configured range, [-1, 1] unless
+    /// `sentiment_min`/`sentiment_max` are set.
+    pub value: f64,
+    /// Milliseconds since the Unix epoch at which the sample was taken.
+    pub timestamp_ms: u64,
+    /// Per-stock packet counter, starting at 1, so receivers can spot
+    /// dropped datagrams. 0 means the sender does not number its packets.
+    #[serde(default)]
+    pub seq: u64,
+    /// Market regime when the sample was taken, if the sender reports one.
+    #[serde(default)]
+    pub regime: Option<Regime>,
+}
+
+impl SentimentPacket {
+    /// Builds a packet stamped with the current wall-clock time.
+    pub fn now(ticker: &str, stock_id: u64, value: f64) -> Self {
+        Self {
+            ticker: ticker.to_string(),
+            stock_id,
+            value,
+            timestamp_ms: now_ms(),
+            seq: 0,
+            regime: None,
+        }
+    }
+
+    /// The same packet numbered `seq`.
+    pub fn with_seq(self, seq: u64) -> Self {
+        Self { seq, ..self }
+    }
+}
+
+/// Coarse state of the market mood.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Regime {
+    Bullish,
+    Neutral,
+    Bearish,
+}
+
+impl Regime {
+    /// Mood at or beyond this distance from zero, on the [-1, 1] scale,
+    /// counts as bullish or bearish.
+    pub const THRESHOLD: f64 = 0.2;
+
+    pub fn from_mood(mood: f64) -> Self {
+        if mood >= Self::THRESHOLD {
+            Regime::Bullish
+        } else if mood <= -Self::THRESHOLD {
+            Regime::Bearish
+        } else {
+            Regime::Neutral
+        }
+    }
+}
+
+impl fmt::Display for Regime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::str::FromStr for Regime {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Bullish" => Ok(Regime::Bullish),
+            "Neutral" => Ok(Regime::Neutral),
+            "Bearish" => Ok(Regime::Bearish),
+            _ => Err(DecodeError::InvalidPacket(format!("unknown regime {}", s))),
+        }
+    }
+}
+
+/// What a `NewsEvent`'s headline was about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HeadlineKind {
+    EarningsBeat,
+    EarningsMiss,
+    Upgrade,
+    Downgrade,
+    ProductLaunch,
+    Lawsuit,
+}
+
+impl HeadlineKind {
+    pub const ALL: [HeadlineKind; 6] = [
+        HeadlineKind::EarningsBeat,
+        HeadlineKind::EarningsMiss,
+        HeadlineKind::Upgrade,
+        HeadlineKind::Downgrade,
+        HeadlineKind::ProductLaunch,
+        HeadlineKind::Lawsuit,
+    ];
+
+    /// Whether this kind of headline pushes sentiment up.
+    pub fn is_positive(self) -> bool {
+        matches!(
+            self,
+            HeadlineKind::EarningsBeat | HeadlineKind::Upgrade | HeadlineKind::ProductLaunch
+        )
+    }
+}
+
+/// A simulated news headline and the shock it applied to one stock's
+/// sentiment. Sent as JSON on the service's `events_port`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NewsEvent {
+    pub stock_id: u64,
+    pub ticker: String,
+    pub headline_kind: HeadlineKind,
+    /// Amount added to the stock's sentiment, before clamping to the
+    /// service's range. Its sign follows `headline_kind`.
+    pub sentiment_impact: f64,
+    /// Milliseconds since the Unix epoch at which the event happened.
+    pub timestamp_ms: u64,
+}
+
+/// Many stocks' values at one moment, sent as one JSON datagram by the
+/// service's batch mode. Samples are `(stock_id, value)` pairs, so
+/// receivers name them from the stock list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SentimentBatch {
+    /// Milliseconds since the Unix epoch at which the values were taken.
+    pub timestamp_ms: u64,
+    pub samples: Vec<(u64, f64)>,
+}
+
+/// Largest datagram the service's batch mode sends: a 1500-byte Ethernet
+/// MTU less IPv6 and UDP headers, with room to spare.
+pub const MAX_BATCH_DATAGRAM_LEN: usize = 1400;
+
+impl SentimentBatch {
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("SentimentBatch always serializes")
+    }
+
+    /// Splits the samples, in order, into batches with this timestamp whose
+    /// encodings are at most `max_len` bytes. A sample too big to fit even
+    /// on its own still gets a batch of its own.
+    pub fn split(&self, max_len: usize) -> Vec<SentimentBatch> {
+        let empty = SentimentBatch {
+            timestamp_ms: self.timestamp_ms,
+            samples: Vec::new(),
+        };
+        let empty_len = empty.encode().len();
+
+        let mut batches = Vec::new();
+        let mut current = empty.clone();
+        let mut len = empty_len;
+        for &sample in &self.samples {
+            let sample_len = serde_json::to_vec(&sample).map_or(0, |json| json.len());
+            // A comma separates every sample after the first
+            let added = sample_len + usize::from(!current.samples.is_empty());
+            if !current.samples.is_empty() && len + added > max_len {
+                batches.push(std::mem::replace(&mut current, empty.clone()));
+                len = empty_len;
+                len += sample_len;
+            } else {
+                len += added;
+            }
+            current.samples.push(sample);
+        }
+        if !current.samples.is_empty() || batches.is_empty() {
+            batches.push(current);
+        }
+        batches
+    }
+
+    /// One unnumbered packet per sample, stamped with the batch's time and
+    /// named by `ticker`.
+    pub fn packets(&self, ticker: impl Fn(u64) -> String) -> Vec<SentimentPacket> {
+        self.samples
+            .iter()
+            .map(|&(stock_id, value)| SentimentPacket {
+                ticker: ticker(stock_id),
+                stock_id,
+                value,
+                timestamp_ms: self.timestamp_ms,
+                seq: 0,
+                regime: None,
+            })
+            .collect()
+    }
+}
+
+/// Decodes a datagram produced by `SentimentBatch::encode`.
+pub fn decode_batch(buf: &[u8]) -> Result<SentimentBatch, DecodeError> {
+    serde_json::from_slice(buf).map_err(|e| DecodeError::InvalidPacket(e.to_string()))
+}
+
+/// How `SentimentPacket`s are encoded on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WireFormat {
+    /// Space-separated `ticker stock_id value timestamp_ms seq [regime]`,
+    /// with the value written by `encode_sample` and the regime only when
+    /// there is one. `seq` may be absent when decoding.
+    Text,
+    /// A single JSON object, see `encode_packet`.
+    #[default]
+    Json,
+    /// MessagePack with the fields as a positional array.
+    MsgPack,
+    /// Fixed-width little-endian layout for receivers that can't afford to
+    /// parse, see `encode_binary`. Carries no ticker, timestamp or regime,
+    /// and is not recognised by `detect`, so receivers must expect it.
+    Binary,
+}
+
+impl WireFormat {
+    pub fn encode(self, packet: &SentimentPacket) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(packet, &mut buf);
+        buf
+    }
+
+    /// Like `encode`, but replaces the contents of `buf` so that a sender
+    /// can reuse one allocation for every packet.
+    pub fn encode_into(self, packet: &SentimentPacket, buf: &mut Vec<u8>) {
+        buf.clear();
+        match self {
+            // Same value formatting as `encode_sample`, without the String
+            WireFormat::Text => {
+                write!(
+                    buf,
+                    "{} {} {:.6} {} {}",
+                    packet.ticker, packet.stock_id, packet.value, packet.timestamp_ms, packet.seq
+                )
+                .expect("writing to a Vec never fails");
+                if let Some(regime) = packet.regime {
+                    write!(buf, " {}", regime).expect("writing to a Vec never fails");
+                }
+            }
+            WireFormat::Json => {
+                serde_json::to_writer(&mut *buf, packet).expect("SentimentPacket always serializes")
+            }
+            WireFormat::MsgPack => {
+                rmp_serde::encode::write(buf, packet).expect("SentimentPacket always serializes")
+            }
+            WireFormat::Binary => buf.extend_from_slice(&encode_binary(packet)),
+        }
+    }
+
+    pub fn decode(self, buf: &[u8]) -> Result<SentimentPacket, DecodeError> {
+        match self {
+            WireFormat::Text => decode_text(buf),
+            WireFormat::Json => decode_packet(buf),
+            WireFormat::MsgPack => {
+                rmp_serde::from_slice(buf).map_err(|e| DecodeError::InvalidPacket(e.to_string()))
+            }
+            WireFormat::Binary => decode_binary(buf),
+        }
+    }
+
+    /// Guesses the format of a datagram from its first byte: `{` for JSON, a
+    /// MessagePack array marker, or printable text otherwise.
+    pub fn detect(buf: &[u8]) -> Option<WireFormat> {
+        match *buf.first()? {
+            b'{' => Some(WireFormat::Json),
+            0x90..=0x9f | 0xdc | 0xdd => Some(WireFormat::MsgPack),
+            b if b.is_ascii_graphic() => Some(WireFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes a datagram in whichever format `WireFormat::detect` recognises.
+pub fn decode_any(buf: &[u8]) -> Result<SentimentPacket, DecodeError> {
+    let format = WireFormat::detect(buf)
+        .ok_or_else(|| DecodeError::InvalidPacket("unrecognised wire format".to_string()))?;
+    format.decode(buf)
+}
+
+fn decode_text(buf: &[u8]) -> Result<SentimentPacket, DecodeError> {
+    let text = std::str::from_utf8(buf).map_err(DecodeError::InvalidUtf8)?;
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let (ticker, stock_id, value, timestamp_ms, seq, regime) = match fields[..] {
+        [ticker, stock_id, value, timestamp_ms] => {
+            (ticker, stock_id, value, timestamp_ms, "0", None)
+        }
+        [ticker, stock_id, value, timestamp_ms, seq] => {
+            (ticker, stock_id, value, timestamp_ms, seq, None)
+        }
+        [ticker, stock_id, value, timestamp_ms, seq, regime] => {
+            (ticker, stock_id, value, timestamp_ms, seq, Some(regime))
+        }
+        _ => {
+            return Err(DecodeError::InvalidPacket(format!(
+                "expected 4 to 6 fields, got {}",
+                fields.len()
+            )))
+        }
+    };
+    let invalid = |field: &str| DecodeError::InvalidPacket(format!("invalid {}", field));
+
+    Ok(SentimentPacket {
+        ticker: ticker.to_string(),
+        stock_id: stock_id.parse().map_err(|_| invalid("stock_id"))?,
+        value: decode_sample(value.as_bytes())?,
+        timestamp_ms: timestamp_ms.parse().map_err(|_| invalid("timestamp_ms"))?,
+        seq: seq.parse().map_err(|_| invalid("seq"))?,
+        regime: regime.map(str::parse).transpose()?,
+    })
+}
+
+/// Size in bytes of a `WireFormat::Binary` packet.
+pub const BINARY_PACKET_LEN: usize = 16;
+
+/// Encodes a packet in the fixed 16-byte binary layout, all fields
+/// little-endian:
+///
+/// | bytes  | field      | type  |
+/// |--------|------------|-------|
+/// | 0..8   | `stock_id` | `u64` |
+/// | 8..12  | `value`    | `f32` |
+/// | 12..16 | `seq`      | `u32` |
+///
+/// The ticker and timestamp are dropped, the value is rounded to `f32` and
+/// `seq` wraps at `u32::MAX`.
+pub fn encode_binary(packet: &SentimentPacket) -> [u8; BINARY_PACKET_LEN] {
+    let mut buf = [0u8; BINARY_PACKET_LEN];
+    buf[0..8].copy_from_slice(&packet.stock_id.to_le_bytes());
+    buf[8..12].copy_from_slice(&(packet.value as f32).to_le_bytes());
+    buf[12..16].copy_from_slice(&(packet.seq as u32).to_le_bytes());
+    buf
+}
+
+/// Decodes a datagram produced by `encode_binary`. The packet's ticker is
+/// empty, its timestamp 0 and its regime `None`, since the layout carries
+/// none of them. NaN values are rejected.
+pub fn decode_binary(buf: &[u8]) -> Result<SentimentPacket, DecodeError> {
+    let buf: &[u8; BINARY_PACKET_LEN] = buf.try_into().map_err(|_| {
+        DecodeError::InvalidPacket(format!(
+            "expected {} bytes, got {}",
+            BINARY_PACKET_LEN,
+            buf.len()
+        ))
+    })?;
+    let stock_id = u64::from_le_bytes(buf[0..8].try_into().unwrap());
+    let value = f32::from_le_bytes(buf[8..12].try_into().unwrap());
+    let seq = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+    if value.is_nan() {
+        return Err(DecodeError::InvalidPacket("value is NaN".to_string()));
+    }
+
+    Ok(SentimentPacket {
+        ticker: String::new(),
+        stock_id,
+        value: f64::from(value),
+        timestamp_ms: 0,
+        seq: u64::from(seq),
+        regime: None,
+    })
+}
+
+/// Milliseconds since the Unix epoch, or 0 if the clock is before it.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Why a received datagram could not be decoded into a sample.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The payload was not valid UTF-8.
+    InvalidUtf8(Utf8Error),
+    /// The payload was text but not a number.
+    InvalidNumber(ParseFloatError),
+    /// The payload was not a valid `SentimentPacket` in the expected format.
+    InvalidPacket(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidUtf8(e) => write!(f, "sample is not valid UTF-8: {}", e),
+            DecodeError::InvalidNumber(e) => write!(f, "sample is not a number: {}", e),
+            DecodeError::InvalidPacket(e) => write!(f, "malformed sentiment packet: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::InvalidUtf8(e) => Some(e),
+            DecodeError::InvalidNumber(e) => Some(e),
+            DecodeError::InvalidPacket(_) => None,
+        }
+    }
+}
+
+/// Encodes a sentiment value as fixed six-decimal text, e.g. `-0.250000`.
+pub fn encode_sample(s: f64) -> String {
+    format!("{:.6}", s)
+}
+
+/// Decodes a datagram produced by `encode_sample`. Surrounding whitespace is
+/// ignored.
+pub fn decode_sample(buf: &[u8]) -> Result<f64, DecodeError> {
+    let text = std::str::from_utf8(buf).map_err(DecodeError::InvalidUtf8)?;
+    text.trim().parse().map_err(DecodeError::InvalidNumber)
+}
+
+/// Encodes a packet as a single JSON object.
+pub fn encode_packet(packet: &SentimentPacket) -> Vec<u8> {
+    serde_json::to_vec(packet).expect("SentimentPacket always serializes")
+}
+
+/// Decodes a datagram produced by `encode_packet`.
+pub fn decode_packet(buf: &[u8]) -> Result<SentimentPacket, DecodeError> {
+    serde_json::from_slice(buf).map_err(|e| DecodeError::InvalidPacket(e.to_string()))
+}
+
+/// How stream frames' payloads are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamCompression {
+    Gzip,
+    Deflate,
+}
+
+impl StreamCompression {
+    /// The frame header byte naming this compression.
+    fn tag(self) -> u8 {
+        match self {
+            StreamCompression::Gzip => 1,
+            StreamCompression::Deflate => 2,
+        }
+    }
+}
+
+/// Bytes before a stream frame's payload: a tag byte naming the payload's
+/// compression (0 for none, see `StreamCompression`) and its length as a
+/// big-endian `u32`.
+pub const FRAME_HEADER_LEN: usize = 5;
+
+/// Largest payload, compressed or inflated, that `decode_frame` and
+/// `read_frame` accept, so a corrupt or hostile header cannot make a reader
+/// allocate gigabytes.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Encodes `packets` as one stream frame whose payload is their
+/// `encode_packet` JSON, one per line. The payload is compressed with
+/// `compression` only when it is at least `threshold` bytes and compressing
+/// shrinks it, so small batches never grow. Returns the frame and the
+/// payload's uncompressed length.
+pub fn encode_frame(
+    packets: &[SentimentPacket],
+    compression: Option<StreamCompression>,
+    threshold: usize,
+) -> (Vec<u8>, usize) {
+    let mut lines = Vec::new();
+    for packet in packets {
+        lines.extend_from_slice(&encode_packet(packet));
+        lines.push(b'\n');
+    }
+    let compressed = compression
+        .filter(|_| lines.len() >= threshold)
+        .map(|compression| (compression.tag(), compress(compression, &lines)))
+        .filter(|(_, payload)| payload.len() < lines.len());
+    let (tag, payload) = match &compressed {
+        Some((tag, payload)) => (*tag, payload.as_slice()),
+        None => (0, lines.as_slice()),
+    };
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.push(tag);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    (frame, lines.len())
+}
+
+fn compress(compression: StreamCompression, buf: &[u8]) -> Vec<u8> {
+    use flate2::{write::DeflateEncoder, write::GzEncoder, Compression};
+
+    // Writing to a Vec cannot fail.
+    match compression {
+        StreamCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            let _ = encoder.write_all(buf);
+            encoder.finish().unwrap_or_default()
+        }
+        StreamCompression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+            let _ = encoder.write_all(buf);
+            encoder.finish().unwrap_or_default()
+        }
+    }
+}
+
+/// Decodes one whole frame produced by `encode_frame` back into its
+/// packets.
+pub fn decode_frame(buf: &[u8]) -> Result<Vec<SentimentPacket>, DecodeError> {
+    if buf.len() < FRAME_HEADER_LEN {
+        return Err(DecodeError::InvalidPacket(format!(
+            "frame needs at least {} bytes, got {}",
+            FRAME_HEADER_LEN,
+            buf.len()
+        )));
+    }
+    let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(DecodeError::InvalidPacket(format!(
+            "frame payload of {} bytes exceeds {}",
+            len, MAX_FRAME_LEN
+        )));
+    }
+    let payload = &buf[FRAME_HEADER_LEN..];
+    if payload.len() != len {
+        return Err(DecodeError::InvalidPacket(format!(
+            "frame payload is {} bytes, header says {}",
+            payload.len(),
+            len
+        )));
+    }
+
+    // One byte past the cap, so an oversized payload is detectable.
+    let limit = MAX_FRAME_LEN as u64 + 1;
+    let mut lines = Vec::new();
+    let inflated = match buf[0] {
+        0 => {
+            lines.extend_from_slice(payload);
+            Ok(0)
+        }
+        1 => flate2::read::GzDecoder::new(payload)
+            .take(limit)
+            .read_to_end(&mut lines),
+        2 => flate2::read::DeflateDecoder::new(payload)
+            .take(limit)
+            .read_to_end(&mut lines),
+        tag => {
+            return Err(DecodeError::InvalidPacket(format!(
+                "unknown frame compression {}",
+                tag
+            )))
+        }
+    };
+    inflated.map_err(|e| DecodeError::InvalidPacket(e.to_string()))?;
+    if lines.len() > MAX_FRAME_LEN {
+        return Err(DecodeError::InvalidPacket(format!(
+            "frame inflates past {} bytes",
+            MAX_FRAME_LEN
+        )));
+    }
+    lines
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(decode_packet)
+        .collect()
+}
+
+/// Reads the next frame from a stream of `encode_frame` frames. A frame
+/// that does not decode, or whose header claims a payload over
+/// `MAX_FRAME_LEN`, is an `InvalidData` error.
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Vec<SentimentPacket>> {
+    let mut frame = vec![0; FRAME_HEADER_LEN];
+    reader.read_exact(&mut frame)?;
+    let len = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame payload of {} bytes exceeds {}", len, MAX_FRAME_LEN),
+        ));
+    }
+    frame.resize(FRAME_HEADER_LEN + len, 0);
+    reader.read_exact(&mut frame[FRAME_HEADER_LEN..])?;
+    decode_frame(&frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: f64) -> f64 {
+        decode_sample(encode_sample(value).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_preserves_six_decimals() {
+        for value in [0.0, 0.123456, -0.654321, -0.5] {
+            assert!((round_trip(value) - value).abs() < 1e-6, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_at_clamp_boundaries() {
+        assert_eq!(round_trip(-1.0), -1.0);
+        assert_eq!(round_trip(1.0), 1.0);
+    }
+
+    #[test]
+    fn test_decode_tolerates_whitespace() {
+        assert_eq!(decode_sample(b" 0.500000\n").unwrap(), 0.5);
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_input() {
+        assert!(matches!(
+            decode_sample(b"bullish"),
+            Err(DecodeError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            decode_sample(b""),
+            Err(DecodeError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            decode_sample(&[0xff, 0xfe]),
+            Err(DecodeError::InvalidUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn test_packet_round_trip() {
+        let packet = SentimentPacket::now("AAPL", 1, -0.25);
+        assert_eq!(decode_packet(&encode_packet(&packet)).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_packet_fits_in_one_datagram() {
+        let packet = SentimentPacket {
+            ticker: "BRK.B".to_string(),
+            stock_id: u64::MAX,
+            value: -0.123456789012345,
+            timestamp_ms: u64::MAX,
+            seq: u64::MAX,
+            regime: Some(Regime::Neutral),
+        };
+        assert!(encode_packet(&packet).len() < 512);
+    }
+
+    fn sample_packet() -> SentimentPacket {
+        SentimentPacket {
+            ticker: "GOOGL".to_string(),
+            stock_id: 2,
+            value: 0.731245,
+            timestamp_ms: 1_700_000_000_123,
+            seq: 42,
+            regime: Some(Regime::Bullish),
+        }
+    }
+
+    #[test]
+    fn test_every_format_round_trips() {
+        let packet = sample_packet();
+        for format in [WireFormat::Text, WireFormat::Json, WireFormat::MsgPack] {
+            let encoded = format.encode(&packet);
+            assert_eq!(WireFormat::detect(&encoded), Some(format));
+            assert_eq!(decode_any(&encoded).unwrap(), packet, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_encode_into_replaces_buffer_contents() {
+        let mut buf = b"stale".to_vec();
+        let packet = sample_packet();
+        for format in [
+            WireFormat::Text,
+            WireFormat::Json,
+            WireFormat::MsgPack,
+            WireFormat::Binary,
+        ] {
+            format.encode_into(&packet, &mut buf);
+            assert_eq!(buf, format.encode(&packet), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_msgpack_is_smallest_encoding() {
+        let packet = sample_packet();
+        let text = WireFormat::Text.encode(&packet).len();
+        let json = WireFormat::Json.encode(&packet).len();
+        let msgpack = WireFormat::MsgPack.encode(&packet).len();
+
+        assert!(
+            msgpack < text && msgpack < json,
+            "text={} json={} msgpack={}",
+            text,
+            json,
+            msgpack
+        );
+    }
+
+    #[test]
+    fn test_text_regime_is_optional() {
+        let packet = sample_packet();
+        let without = SentimentPacket {
+            regime: None,
+            ..packet.clone()
+        };
+        let encoded = WireFormat::Text.encode(&without);
+        assert!(!String::from_utf8_lossy(&encoded).contains("Bullish"));
+        assert_eq!(WireFormat::Text.decode(&encoded).unwrap(), without);
+        assert!(WireFormat::Text
+            .decode(b"AAPL 1 0.500000 1700000000000 3 Sideways")
+            .is_err());
+    }
+
+    #[test]
+    fn test_text_decode_rejects_missing_fields() {
+        assert!(matches!(
+            WireFormat::Text.decode(b"AAPL 1 0.5"),
+            Err(DecodeError::InvalidPacket(_))
+        ));
+    }
+
+    #[test]
+    fn test_unnumbered_packets_decode_with_seq_zero() {
+        let text = WireFormat::Text
+            .decode(b"AAPL 1 0.500000 1700000000000")
+            .unwrap();
+        assert_eq!(text.seq, 0);
+        let json = decode_packet(
+            br#"{"ticker":"AAPL","stock_id":1,"value":0.5,"timestamp_ms":1700000000000}"#,
+        )
+        .unwrap();
+        assert_eq!(json, text);
+    }
+
+    #[test]
+    fn test_binary_round_trips() {
+        for stock_id in [0, 1, 0x0102_0304_0506_0708, u64::MAX] {
+            for value in [-1.0, -0.25, 0.0, -0.0, 0.5, 1.0, f64::INFINITY, 1e-40] {
+                for seq in [0, 1, 42, u64::from(u32::MAX)] {
+                    let packet = SentimentPacket {
+                        ticker: String::new(),
+                        stock_id,
+                        value,
+                        timestamp_ms: 0,
+                        seq,
+                        regime: None,
+                    };
+                    let encoded = WireFormat::Binary.encode(&packet);
+                    assert_eq!(encoded.len(), BINARY_PACKET_LEN);
+                    let decoded = WireFormat::Binary.decode(&encoded).unwrap();
+                    assert_eq!(decoded.stock_id, stock_id);
+                    assert_eq!(decoded.seq, seq);
+                    assert_eq!(decoded.value, f64::from(value as f32));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_layout_is_little_endian() {
+        let packet = SentimentPacket::now("AAPL", 0x0102_0304_0506_0708, 1.0).with_seq(0x0a0b_0c0d);
+        assert_eq!(
+            encode_binary(&packet),
+            [
+                0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, // stock_id
+                0x00, 0x00, 0x80, 0x3f, // 1.0f32
+                0x0d, 0x0c, 0x0b, 0x0a, // seq
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_drops_ticker_and_narrows_fields() {
+        let packet = sample_packet().with_seq(u64::from(u32::MAX) + 2);
+        let decoded = decode_binary(&encode_binary(&packet)).unwrap();
+        assert_eq!(decoded.ticker, "");
+        assert_eq!(decoded.timestamp_ms, 0);
+        assert_eq!(decoded.regime, None);
+        assert_eq!(decoded.seq, 1);
+        assert!((decoded.value - packet.value).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_binary_decode_rejects_nan_and_bad_lengths() {
+        let mut nan = encode_binary(&sample_packet());
+        nan[8..12].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert!(matches!(
+            decode_binary(&nan),
+            Err(DecodeError::InvalidPacket(_))
+        ));
+
+        let encoded = encode_binary(&sample_packet());
+        for len in [0, BINARY_PACKET_LEN - 1] {
+            assert!(decode_binary(&encoded[..len]).is_err());
+        }
+        assert!(decode_binary(&[0; BINARY_PACKET_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_news_event_json_round_trips() {
+        let event = NewsEvent {
+            stock_id: 7,
+            ticker: "AAPL".to_string(),
+            headline_kind: HeadlineKind::Downgrade,
+            sentiment_impact: -0.12,
+            timestamp_ms: 1_700_000_000_000,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("\"headline_kind\":\"Downgrade\""), "{}", json);
+        assert_eq!(serde_json::from_str::<NewsEvent>(&json).unwrap(), event);
+    }
+
+    fn sample_batch(len: u64) -> Vec<SentimentPacket> {
+        (0..len)
+            .map(|id| SentimentPacket::now("AAPL", id, 0.25).with_seq(id))
+            .collect()
+    }
+
+    #[test]
+    fn test_compressed_frames_round_trip() {
+        let packets = sample_batch(50);
+        for compression in [StreamCompression::Gzip, StreamCompression::Deflate] {
+            let (frame, raw_len) = encode_frame(&packets, Some(compression), 0);
+            assert_eq!(frame[0], compression.tag());
+            assert!(frame.len() < raw_len, "{:?} did not shrink", compression);
+            assert_eq!(decode_frame(&frame).unwrap(), packets);
+        }
+    }
+
+    #[test]
+    fn test_frames_below_threshold_are_not_compressed() {
+        let packets = sample_batch(1);
+        let (frame, raw_len) = encode_frame(&packets, Some(StreamCompression::Gzip), 1024);
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame.len(), FRAME_HEADER_LEN + raw_len);
+        assert_eq!(decode_frame(&frame).unwrap(), packets);
+
+        // Compressing a tiny payload would grow it, so it goes out as is
+        let (frame, raw_len) = encode_frame(&packets, Some(StreamCompression::Gzip), 0);
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame.len(), FRAME_HEADER_LEN + raw_len);
+    }
+
+    #[test]
+    fn test_read_frame_splits_a_stream() {
+        let first = sample_batch(40);
+        let second = sample_batch(2);
+        let mut stream = encode_frame(&first, Some(StreamCompression::Deflate), 64).0;
+        stream.extend(encode_frame(&second, Some(StreamCompression::Deflate), 64).0);
+
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), first);
+        assert_eq!(read_frame(&mut reader).unwrap(), second);
+        assert!(read_frame(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_decode_frame_rejects_bad_frames() {
+        let (mut frame, _) = encode_frame(&sample_batch(3), None, 0);
+        assert!(decode_frame(&frame[..FRAME_HEADER_LEN - 1]).is_err());
+        assert!(decode_frame(&frame[..frame.len() - 1]).is_err());
+        frame[0] = 9;
+        assert!(decode_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn test_oversized_frames_are_rejected() {
+        let mut header = vec![0];
+        header.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode_frame(&header).is_err());
+        let err = read_frame(&mut header.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A small payload that inflates past the cap.
+        let blank_lines = vec![b'\n'; MAX_FRAME_LEN + 1];
+        let payload = compress(StreamCompression::Deflate, &blank_lines);
+        let mut frame = vec![StreamCompression::Deflate.tag()];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        assert!(payload.len() < MAX_FRAME_LEN);
+        assert!(decode_frame(&frame).is_err());
+        let err = read_frame(&mut frame.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_batch_of_50_splits_into_bounded_datagrams() {
+        let batch = SentimentBatch {
+            timestamp_ms: 1_700_000_000_000,
+            samples: (0..50)
+                .map(|i| {
+                    (
+                        1_000_000_000 + i,
+                        -0.123_456_789_012_345_6 * i as f64 / 50.0,
+                    )
+                })
+                .collect(),
+        };
+        let parts = batch.split(MAX_BATCH_DATAGRAM_LEN);
+        assert!(
+            parts.len() > 1,
+            "{} bytes fit in one datagram",
+            batch.encode().len()
+        );
+
+        let mut samples = Vec::new();
+        for part in &parts {
+            let datagram = part.encode();
+            assert!(
+                datagram.len() <= MAX_BATCH_DATAGRAM_LEN,
+                "{}",
+                datagram.len()
+            );
+            let decoded = decode_batch(&datagram).unwrap();
+            assert_eq!(decoded.timestamp_ms, batch.timestamp_ms);
+            samples.extend(decoded.samples);
+        }
+        // serde_json may round the last digit of a value
+        assert_eq!(samples.len(), batch.samples.len());
+        for (&(id, value), &(expected_id, expected)) in samples.iter().zip(&batch.samples) {
+            assert_eq!(id, expected_id);
+            assert!(
+                (value - expected).abs() < 1e-15,
+                "{} vs {}",
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch_split_fills_each_datagram() {
+        let batch = SentimentBatch {
+            timestamp_ms: 1,
+            samples: (0..20).map(|i| (i, 0.5)).collect(),
+        };
+        for max_len in [40, 60, 100, 1000] {
+            let parts = batch.split(max_len);
+            // Moving the next part's first sample over would overflow
+            for pair in parts.windows(2) {
+                let mut grown = pair[0].clone();
+                grown.samples.push(pair[1].samples[0]);
+                assert!(grown.encode().len() > max_len);
+            }
+            assert!(parts.iter().all(|part| part.encode().len() <= max_len));
+        }
+        assert_eq!(batch.split(1).len(), 20);
+    }
+
+    #[test]
+    fn test_batch_unpacks_into_named_packets() {
+        let batch = SentimentBatch {
+            timestamp_ms: 42,
+            samples: vec![(1, 0.5), (9, -0.25)],
+        };
+        let packets = batch.packets(|id| {
+            if id == 1 {
+                "AAPL".into()
+            } else {
+                id.to_string()
+            }
+        });
+        assert_eq!(packets[0].ticker, "AAPL");
+        assert_eq!(packets[1].ticker, "9");
+        assert_eq!(packets[1].value, -0.25);
+        assert!(packets
+            .iter()
+            .all(|packet| packet.timestamp_ms == 42 && packet.seq == 0));
+    }
+
+    #[test]
+    fn test_decode_packet_rejects_bare_sample() {
+        assert!(matches!(
+            decode_packet(b"0.500000"),
+            Err(DecodeError::InvalidPacket(_))
+        ));
+    }
+}